@@ -0,0 +1,165 @@
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use store::{
+    camera::KeyboardCaptured,
+    protocol::{self, ChatLine, ChatMessage, CHAT_CHANNEL, MAX_CHAT_LEN},
+    transport::Transport,
+};
+
+use crate::connect::Screen;
+
+/// How many chat lines are kept and shown.
+const CHAT_HISTORY: usize = 8;
+
+#[derive(Debug, Default, Resource)]
+pub struct ChatLog {
+    pub lines: VecDeque<ChatLine>,
+    /// Message being typed, `None` while the chat input is closed.
+    pub input: Option<String>,
+}
+
+#[derive(Component)]
+struct ChatPanel;
+
+#[derive(Component)]
+struct ChatText;
+
+pub struct ChatPlugin;
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ChatLog::default())
+            .add_system_set(SystemSet::on_enter(Screen::Playing).with_system(spawn_chat_panel))
+            .add_system_set(
+                SystemSet::on_update(Screen::Playing)
+                    .with_system(receive_chat)
+                    .with_system(type_chat)
+                    .with_system(update_chat_panel.after(receive_chat).after(type_chat)),
+            )
+            .add_system_set(SystemSet::on_exit(Screen::Playing).with_system(despawn_chat_panel));
+    }
+}
+
+fn spawn_chat_panel(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        left: Val::Px(10.0),
+                        bottom: Val::Px(10.0),
+                        ..default()
+                    },
+                    padding: UiRect::all(Val::Px(5.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.4).into(),
+                ..default()
+            },
+            ChatPanel,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("Inconsolata.ttf"),
+                        font_size: 14.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                ChatText,
+            ));
+        });
+}
+
+fn despawn_chat_panel(
+    mut commands: Commands,
+    query: Query<Entity, With<ChatPanel>>,
+    mut chat: ResMut<ChatLog>,
+    mut keyboard_captured: ResMut<KeyboardCaptured>,
+) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+    *chat = ChatLog::default();
+    keyboard_captured.0 = false;
+}
+
+fn receive_chat(transport: Option<ResMut<Transport>>, mut chat: ResMut<ChatLog>) {
+    let mut transport = match transport {
+        Some(transport) => transport,
+        None => return,
+    };
+    while let Some(message) = transport.receive_message(CHAT_CHANNEL) {
+        match protocol::decode::<ChatLine>(&message) {
+            Ok(line) => {
+                chat.lines.push_back(line);
+                if chat.lines.len() > CHAT_HISTORY {
+                    chat.lines.pop_front();
+                }
+            }
+            Err(err) => error!("Could not decode chat message: {}", err),
+        }
+    }
+}
+
+/// Enter opens the chat input and sends the message, Escape closes it.
+fn type_chat(
+    mut characters: EventReader<ReceivedCharacter>,
+    kb_input: Res<Input<KeyCode>>,
+    mut chat: ResMut<ChatLog>,
+    mut keyboard_captured: ResMut<KeyboardCaptured>,
+    transport: Option<ResMut<Transport>>,
+) {
+    if chat.input.is_none() {
+        for _ in characters.iter() {}
+        if kb_input.just_pressed(KeyCode::Return) {
+            chat.input = Some(String::new());
+            keyboard_captured.0 = true;
+        }
+        return;
+    }
+
+    if kb_input.just_pressed(KeyCode::Escape) {
+        chat.input = None;
+        keyboard_captured.0 = false;
+        return;
+    }
+
+    if kb_input.just_pressed(KeyCode::Return) {
+        let text = chat.input.take().unwrap_or_default();
+        keyboard_captured.0 = false;
+        if let Some(mut transport) = transport {
+            if !text.trim().is_empty() {
+                transport.send_message(CHAT_CHANNEL, protocol::encode(&ChatMessage { text }));
+            }
+        }
+        return;
+    }
+
+    let input = chat.input.as_mut().unwrap();
+    if kb_input.just_pressed(KeyCode::Back) {
+        input.pop();
+    }
+    for ev in characters.iter() {
+        if !ev.char.is_control() && input.chars().count() < MAX_CHAT_LEN {
+            input.push(ev.char);
+        }
+    }
+}
+
+fn update_chat_panel(chat: Res<ChatLog>, mut query: Query<&mut Text, With<ChatText>>) {
+    let mut content: Vec<String> = chat
+        .lines
+        .iter()
+        .map(|line| format!("{}: {}", line.name, line.text))
+        .collect();
+    content.push(match &chat.input {
+        Some(input) => format!("> {}_", input),
+        None => "Enter to chat".to_string(),
+    });
+    for mut text in &mut query {
+        text.sections[0].value = content.join("\n");
+    }
+}
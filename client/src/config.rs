@@ -0,0 +1,138 @@
+use anyhow::Context;
+use bevy::prelude::Resource;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use store::protocol::PlayerToken;
+
+/// Where the client configuration is read from and saved to when `--config` is not given.
+pub const DEFAULT_CONFIG_PATH: &str = "battleships.json";
+
+/// Command line flags. Every flag is optional and overrides the persisted configuration.
+#[derive(Parser, Debug)]
+#[command(name = "client", about = "BattleGrounds! client")]
+pub struct Args {
+    /// Server address, e.g. 127.0.0.1:5000
+    #[arg(short, long)]
+    pub server: Option<String>,
+
+    /// Name shown to the other players
+    #[arg(short, long)]
+    pub username: Option<String>,
+
+    /// Window width in logical pixels
+    #[arg(long)]
+    pub width: Option<f32>,
+
+    /// Window height in logical pixels
+    #[arg(long)]
+    pub height: Option<f32>,
+
+    /// Enable or disable vsync
+    #[arg(long)]
+    pub vsync: Option<bool>,
+
+    /// Path of the client configuration file
+    #[arg(short, long, default_value = DEFAULT_CONFIG_PATH)]
+    pub config: PathBuf,
+
+    /// Connect right away instead of waiting on the connect screen
+    #[arg(long)]
+    pub connect: bool,
+
+    /// Watch a match instead of playing
+    #[arg(long)]
+    pub spectate: bool,
+
+    /// Play slow correspondence games, which go on when you disconnect
+    #[arg(long, conflicts_with = "spectate")]
+    pub correspondence: bool,
+}
+
+/// Window options applied when the app starts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub width: f32,
+    pub height: f32,
+    pub vsync: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            width: 500.,
+            height: 300.,
+            vsync: true,
+        }
+    }
+}
+
+/// Persisted client configuration. Saved back to disk after every successful connection.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Resource)]
+#[serde(default)]
+pub struct ClientConfig {
+    pub server_addr: String,
+    pub username: String,
+    /// Tokens servers issued to keep our rating, by server address. Keep them secret.
+    pub player_tokens: BTreeMap<String, PlayerToken>,
+    pub window: WindowConfig,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            server_addr: "127.0.0.1:5000".to_string(),
+            username: String::new(),
+            player_tokens: BTreeMap::new(),
+            window: WindowConfig::default(),
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Reads the configuration at `path`, falling back to the defaults if the file does not exist yet.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read {}", path.display()))?;
+        serde_json::from_str(&contents).with_context(|| format!("{} is not valid", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, contents)
+            .with_context(|| format!("could not write {}", path.display()))
+    }
+
+    /// The token the server we connect to issued us, if it did already.
+    pub fn player_token(&self) -> Option<&PlayerToken> {
+        self.player_tokens.get(self.server_addr.trim())
+    }
+
+    /// Overrides the persisted values with the ones given on the command line.
+    pub fn apply_args(&mut self, args: &Args) {
+        if let Some(server) = &args.server {
+            self.server_addr = server.clone();
+        }
+        if let Some(username) = &args.username {
+            self.username = username.clone();
+        }
+        if let Some(width) = args.width {
+            self.window.width = width;
+        }
+        if let Some(height) = args.height {
+            self.window.height = height;
+        }
+        if let Some(vsync) = args.vsync {
+            self.window.vsync = vsync;
+        }
+    }
+}
+
+/// Path the [`ClientConfig`] resource is saved to.
+#[derive(Debug, Clone, Resource)]
+pub struct ConfigPath(pub PathBuf);
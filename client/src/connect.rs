@@ -0,0 +1,302 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use std::marker::PhantomData;
+use store::{
+    protocol::{PlayerToken, Role},
+    transport::Transport,
+    GameStage, GameState, PendingCommands, Spectator, WhoAmI,
+};
+
+use crate::config::{ClientConfig, ConfigPath};
+use crate::reconnect::ConnectionStatus;
+use crate::{new_renet_client, LocalGame};
+
+/// Top level screens of the client. What happens inside a match is driven by [`store::GameStage`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Screen {
+    Connect,
+    Playing,
+}
+
+/// Inserted when the client is started with `--connect`, so the connect screen submits on its own.
+#[derive(Resource)]
+pub struct AutoConnect;
+
+/// Sent when the connection to the server is over for good.
+/// The client drops it and goes back to the connect screen, showing `reason`.
+pub struct ConnectionClosed {
+    pub reason: String,
+}
+
+/// Sent when the server issued us the token to identify ourselves with from now on.
+pub struct TokenIssued(pub PlayerToken);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Component)]
+enum ConnectField {
+    Server,
+    Username,
+    Role,
+}
+
+#[derive(Debug, Resource)]
+pub struct ConnectForm {
+    focus: ConnectField,
+    pub role: Role,
+    pub error: Option<String>,
+}
+
+/// The connect screen: what was entered into it and whether it is shown.
+#[derive(SystemParam)]
+struct ConnectUi<'w, 's> {
+    form: ResMut<'w, ConnectForm>,
+    screen: ResMut<'w, State<Screen>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+#[derive(Component)]
+struct ConnectScreen;
+
+#[derive(Component)]
+struct ConnectError;
+
+pub struct ConnectPlugin {
+    /// Role preselected on the connect screen.
+    pub role: Role,
+}
+impl Plugin for ConnectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state(Screen::Connect)
+            .insert_resource(ConnectForm {
+                focus: ConnectField::Username,
+                role: self.role,
+                error: None,
+            })
+            .add_event::<ConnectionClosed>()
+            .add_event::<TokenIssued>()
+            .add_system(close_connection)
+            .add_system(keep_player_token)
+            .add_system_set(SystemSet::on_enter(Screen::Connect).with_system(spawn_connect_screen))
+            .add_system_set(
+                SystemSet::on_update(Screen::Connect)
+                    .with_system(edit_fields)
+                    .with_system(submit.after(edit_fields))
+                    .with_system(update_connect_screen.after(submit)),
+            )
+            .add_system_set(
+                SystemSet::on_exit(Screen::Connect).with_system(despawn_connect_screen),
+            );
+    }
+}
+
+fn spawn_connect_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("Inconsolata.ttf");
+    let text_style = TextStyle {
+        font: font.clone(),
+        font_size: 20.0,
+        color: Color::WHITE,
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    // bevy_ui lays columns out bottom to top
+                    flex_direction: FlexDirection::ColumnReverse,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: Color::hex("282828").unwrap().into(),
+                ..default()
+            },
+            ConnectScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "BattleGrounds!",
+                TextStyle {
+                    font_size: 32.0,
+                    ..text_style.clone()
+                },
+            ));
+            parent.spawn((
+                TextBundle::from_section("", text_style.clone()),
+                ConnectField::Server,
+            ));
+            parent.spawn((
+                TextBundle::from_section("", text_style.clone()),
+                ConnectField::Username,
+            ));
+            parent.spawn((
+                TextBundle::from_section("", text_style.clone()),
+                ConnectField::Role,
+            ));
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        color: Color::ORANGE_RED,
+                        ..text_style.clone()
+                    },
+                ),
+                ConnectError,
+            ));
+            parent.spawn(TextBundle::from_section(
+                "Tab: next field   Space: change role   Enter: connect",
+                TextStyle {
+                    font_size: 14.0,
+                    color: Color::GRAY,
+                    font,
+                },
+            ));
+        });
+}
+
+fn despawn_connect_screen(mut commands: Commands, query: Query<Entity, With<ConnectScreen>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Types into the focused field. Tab moves the focus to the next field.
+fn edit_fields(
+    mut characters: EventReader<ReceivedCharacter>,
+    kb_input: Res<Input<KeyCode>>,
+    mut form: ResMut<ConnectForm>,
+    mut config: ResMut<ClientConfig>,
+) {
+    if kb_input.just_pressed(KeyCode::Tab) {
+        form.focus = match form.focus {
+            ConnectField::Server => ConnectField::Username,
+            ConnectField::Username => ConnectField::Role,
+            ConnectField::Role => ConnectField::Server,
+        };
+    }
+
+    let value = match form.focus {
+        ConnectField::Server => &mut config.server_addr,
+        ConnectField::Username => &mut config.username,
+        ConnectField::Role => {
+            if kb_input.just_pressed(KeyCode::Space) {
+                form.role = match form.role {
+                    Role::Player => Role::Correspondence,
+                    Role::Correspondence => Role::Spectator,
+                    Role::Spectator => Role::Player,
+                };
+            }
+            for _ in characters.iter() {}
+            return;
+        }
+    };
+    if kb_input.just_pressed(KeyCode::Back) {
+        value.pop();
+    }
+    for ev in characters.iter() {
+        if !ev.char.is_control() {
+            value.push(ev.char);
+        }
+    }
+}
+
+/// Connects to the server over renet on Enter (or right away with `--connect`) and moves on to the game.
+fn submit(
+    mut commands: Commands,
+    mut kb_input: ResMut<Input<KeyCode>>,
+    auto_connect: Option<Res<AutoConnect>>,
+    config: Res<ClientConfig>,
+    config_path: Res<ConfigPath>,
+    mut ui: ConnectUi,
+    mut status: ResMut<ConnectionStatus>,
+) {
+    if auto_connect.is_some() {
+        commands.remove_resource::<AutoConnect>();
+    } else if !kb_input.just_pressed(KeyCode::Return) {
+        return;
+    }
+
+    match new_renet_client(&config, ui.form.role) {
+        Ok(client) => {
+            if let Err(err) = config.save(&config_path.0) {
+                warn!("{:#}", err);
+            }
+            commands.insert_resource(WhoAmI(client.client_id()));
+            commands.insert_resource(Transport::new(client));
+            *status = ConnectionStatus::Connecting { attempt: 0 };
+            ui.form.error = None;
+            // Don't let the same Enter press open the chat on the next screen
+            kb_input.reset(KeyCode::Return);
+            ui.screen.set(Screen::Playing).unwrap();
+        }
+        Err(err) => ui.form.error = Some(format!("{:#}", err)),
+    }
+}
+
+/// Saves the token the server issued us, so reconnects and later sessions present it.
+fn keep_player_token(
+    mut issued: EventReader<TokenIssued>,
+    mut config: ResMut<ClientConfig>,
+    config_path: Res<ConfigPath>,
+) {
+    let TokenIssued(token) = match issued.iter().last() {
+        Some(issued) => issued,
+        None => return,
+    };
+    let server_addr = config.server_addr.trim().to_string();
+    config.player_tokens.insert(server_addr, token.clone());
+    if let Err(err) = config.save(&config_path.0) {
+        warn!("{:#}", err);
+    }
+}
+
+fn close_connection(
+    mut commands: Commands,
+    mut closed: EventReader<ConnectionClosed>,
+    transport: Option<ResMut<Transport>>,
+    mut ui: ConnectUi,
+    mut game: LocalGame,
+    mut status: ResMut<ConnectionStatus>,
+) {
+    let ev = match closed.iter().last() {
+        Some(ev) => ev,
+        None => return,
+    };
+    warn!("Connection closed: {}", ev.reason);
+    ui.form.error = Some(ev.reason.clone());
+    *status = ConnectionStatus::Offline;
+
+    if let Some(mut transport) = transport {
+        transport.disconnect();
+        commands.remove_resource::<Transport>();
+    }
+    commands.remove_resource::<WhoAmI>();
+    commands.remove_resource::<Spectator>();
+    *game.state = GameState::default();
+    *game.pending = PendingCommands::default();
+    // Fails if the match never left the lobby, which is fine
+    let _ = game.stage.overwrite_set(GameStage::Lobby);
+    if *ui.screen.current() != Screen::Connect {
+        ui.screen.set(Screen::Connect).unwrap();
+    }
+}
+
+fn update_connect_screen(
+    config: Res<ClientConfig>,
+    form: Res<ConnectForm>,
+    mut fields: Query<(&ConnectField, &mut Text)>,
+    mut errors: Query<&mut Text, (With<ConnectError>, Without<ConnectField>)>,
+) {
+    for (field, mut text) in &mut fields {
+        let role = format!("{:?}", form.role);
+        let (label, value) = match field {
+            ConnectField::Server => ("Server", &config.server_addr),
+            ConnectField::Username => ("Name", &config.username),
+            ConnectField::Role => ("Join as", &role),
+        };
+        let cursor = if *field == form.focus { "_" } else { "" };
+        text.sections[0].value = format!("{}: {}{}", label, value, cursor);
+    }
+    for mut text in &mut errors {
+        text.sections[0].value = form.error.clone().unwrap_or_default();
+    }
+}
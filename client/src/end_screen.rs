@@ -0,0 +1,236 @@
+use bevy::prelude::*;
+use store::{
+    camera::KeyboardCaptured,
+    protocol::{self, ClientMessage, GAME_CHANNEL},
+    transport::Transport,
+    EndGameReason, GameEvent, GameStage, GameState, PlayerId, Spectator, WhoAmI,
+};
+
+/// Sent when a player of our room asked for a rematch, possibly us.
+pub struct RematchRequested {
+    pub by: PlayerId,
+}
+
+/// Who asked for a rematch since the game ended.
+#[derive(Debug, Default, Resource)]
+struct RematchVotes {
+    ours: bool,
+    theirs: bool,
+    /// Set once we asked to leave the room instead.
+    left: bool,
+}
+
+#[derive(Component)]
+struct EndScreen;
+
+#[derive(Component)]
+struct EndScreenOptions;
+
+/// Shows the result once the game is over, over the board with every fleet revealed.
+/// Players can ask for a rematch or leave for a new opponent, spectators for another game.
+pub struct EndScreenPlugin;
+impl Plugin for EndScreenPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<RematchRequested>()
+            .insert_resource(RematchVotes::default())
+            .add_system_set(
+                SystemSet::on_enter(GameStage::Ended)
+                    .with_system(reset_votes)
+                    .with_system(spawn_end_screen),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameStage::Ended)
+                    .with_system(count_votes)
+                    .with_system(end_screen_input)
+                    .with_system(update_end_screen.after(count_votes)),
+            )
+            .add_system_set(SystemSet::on_exit(GameStage::Ended).with_system(despawn_end_screen));
+    }
+}
+
+/// How the game ended, as the player or spectator should read it.
+fn result_text(game_state: &GameState, me: PlayerId, spectating: bool) -> String {
+    let reason = game_state
+        .history
+        .iter()
+        .rev()
+        .find_map(|event| match event {
+            GameEvent::EndGame { reason } => Some(reason),
+            _ => None,
+        });
+    match reason {
+        Some(EndGameReason::PlayerWon { winner }) if spectating => {
+            let name = game_state
+                .players
+                .get(winner)
+                .map_or("Somebody", |player| player.name.as_str());
+            format!("{} won!", name)
+        }
+        Some(EndGameReason::PlayerWon { winner }) if *winner == me => "Victory!".to_string(),
+        Some(EndGameReason::PlayerWon { .. }) => "Defeat".to_string(),
+        Some(EndGameReason::PlayerLeft { .. }) if spectating => {
+            "A player left the game".to_string()
+        }
+        Some(EndGameReason::PlayerLeft { .. }) => "Your opponent left the game".to_string(),
+        Some(EndGameReason::PlayerResigned { player_id }) if *player_id == me && !spectating => {
+            "You resigned".to_string()
+        }
+        Some(EndGameReason::PlayerResigned { player_id }) => {
+            let name = game_state
+                .players
+                .get(player_id)
+                .map_or("Your opponent", |player| player.name.as_str());
+            format!("{} resigned", name)
+        }
+        Some(EndGameReason::TimedOut { player_id }) if *player_id == me && !spectating => {
+            "You ran out of time".to_string()
+        }
+        Some(EndGameReason::TimedOut { player_id }) => {
+            let name = game_state
+                .players
+                .get(player_id)
+                .map_or("Your opponent", |player| player.name.as_str());
+            format!("{} ran out of time", name)
+        }
+        Some(EndGameReason::Aborted { reason }) => format!("Game aborted: {}", reason),
+        None => "Game over".to_string(),
+    }
+}
+
+fn reset_votes(mut votes: ResMut<RematchVotes>) {
+    *votes = RematchVotes::default();
+}
+
+fn spawn_end_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    game_state: Res<GameState>,
+    who_am_i: Option<Res<WhoAmI>>,
+    spectator: Option<Res<Spectator>>,
+) {
+    let font = asset_server.load("Inconsolata.ttf");
+    let me = who_am_i.map_or(0, |who_am_i| who_am_i.0);
+    let title = result_text(&game_state, me, spectator.is_some());
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        top: Val::Px(10.0),
+                        ..default()
+                    },
+                    size: Size::new(Val::Percent(100.0), Val::Auto),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            EndScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        // bevy_ui lays columns out bottom to top
+                        flex_direction: FlexDirection::ColumnReverse,
+                        align_items: AlignItems::Center,
+                        padding: UiRect::all(Val::Px(10.0)),
+                        ..default()
+                    },
+                    background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                    ..default()
+                })
+                .with_children(|panel| {
+                    panel.spawn(TextBundle::from_section(
+                        title,
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 32.0,
+                            color: Color::WHITE,
+                        },
+                    ));
+                    panel.spawn((
+                        TextBundle::from_section(
+                            "",
+                            TextStyle {
+                                font,
+                                font_size: 16.0,
+                                color: Color::GRAY,
+                            },
+                        ),
+                        EndScreenOptions,
+                    ));
+                });
+        });
+}
+
+fn despawn_end_screen(mut commands: Commands, query: Query<Entity, With<EndScreen>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn count_votes(
+    mut requests: EventReader<RematchRequested>,
+    who_am_i: Option<Res<WhoAmI>>,
+    mut votes: ResMut<RematchVotes>,
+) {
+    for request in requests.iter() {
+        match &who_am_i {
+            Some(who_am_i) if who_am_i.0 == request.by => votes.ours = true,
+            _ => votes.theirs = true,
+        }
+    }
+}
+
+/// R asks for a rematch, L leaves the room. The server moves us on from there.
+fn end_screen_input(
+    kb_input: Res<Input<KeyCode>>,
+    keyboard_captured: Res<KeyboardCaptured>,
+    transport: Option<ResMut<Transport>>,
+    game_state: Res<GameState>,
+    spectator: Option<Res<Spectator>>,
+    mut votes: ResMut<RematchVotes>,
+) {
+    let mut transport = match transport {
+        Some(transport) if transport.is_connected() => transport,
+        _ => return,
+    };
+    if keyboard_captured.0 || votes.left {
+        return;
+    }
+
+    let can_rematch = spectator.is_none() && game_state.players.len() == 2 && !votes.ours;
+    if can_rematch && kb_input.just_pressed(KeyCode::R) {
+        transport.send_message(GAME_CHANNEL, protocol::encode(&ClientMessage::Rematch));
+    } else if kb_input.just_pressed(KeyCode::L) {
+        transport.send_message(GAME_CHANNEL, protocol::encode(&ClientMessage::LeaveRoom));
+        votes.left = true;
+    }
+}
+
+fn update_end_screen(
+    game_state: Res<GameState>,
+    spectator: Option<Res<Spectator>>,
+    votes: Res<RematchVotes>,
+    mut query: Query<&mut Text, With<EndScreenOptions>>,
+) {
+    let options = if spectator.is_some() {
+        "L: watch another game".to_string()
+    } else if game_state.players.len() < 2 {
+        "L: find a new opponent".to_string()
+    } else {
+        let status = match (votes.ours, votes.theirs) {
+            (true, false) => "Waiting for your opponent to accept the rematch...\n",
+            (false, true) => "Your opponent wants a rematch!\n",
+            _ => "",
+        };
+        let rematch = if votes.ours { "" } else { "R: rematch   " };
+        format!("{}{}L: find a new opponent", status, rematch)
+    };
+    for mut text in &mut query {
+        text.sections[0].value = options.clone();
+    }
+}
@@ -0,0 +1,225 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use std::fmt::Write;
+use std::marker::PhantomData;
+use std::time::SystemTime;
+use store::{
+    camera::KeyboardCaptured,
+    protocol::{self, ClientMessage, OpenGame, Role, GAME_CHANNEL},
+    transport::Transport,
+    GameStage,
+};
+
+use crate::connect::{ConnectForm, Screen};
+
+/// Sent when the server listed our open correspondence games.
+pub struct OpenGamesReceived(pub Vec<OpenGame>);
+
+/// The games listed last, which the number keys switch to.
+#[derive(Resource, Default)]
+struct ListedGames(Vec<OpenGame>);
+
+#[derive(Component)]
+struct GamesPanel;
+
+/// The G key, which only lists games while playing correspondence and typing nothing else.
+#[derive(SystemParam)]
+struct GamesKey<'w, 's> {
+    kb_input: Res<'w, Input<KeyCode>>,
+    keyboard_captured: Res<'w, KeyboardCaptured>,
+    form: Res<'w, ConnectForm>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl GamesKey<'_, '_> {
+    fn just_pressed(&self) -> bool {
+        !self.keyboard_captured.0
+            && self.form.role == Role::Correspondence
+            && self.kb_input.just_pressed(KeyCode::G)
+    }
+}
+
+#[derive(Component)]
+struct GamesText;
+
+const NUMBER_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+/// G lists our open correspondence games while playing them. A number key switches to that
+/// game, N looks for a new one. The games we leave go on without us until we come back.
+pub struct GamesPlugin;
+impl Plugin for GamesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<OpenGamesReceived>()
+            .init_resource::<ListedGames>()
+            .add_system_set(
+                SystemSet::on_update(Screen::Playing)
+                    .with_system(toggle_games)
+                    .with_system(update_games.after(toggle_games))
+                    .with_system(switch_game.after(update_games)),
+            )
+            .add_system_set(SystemSet::on_exit(Screen::Playing).with_system(despawn_games));
+    }
+}
+
+fn toggle_games(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    games_key: GamesKey,
+    mut listed: ResMut<ListedGames>,
+    transport: Option<ResMut<Transport>>,
+    panel: Query<Entity, With<GamesPanel>>,
+) {
+    if !games_key.just_pressed() {
+        return;
+    }
+    if !panel.is_empty() {
+        for entity in &panel {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    }
+    let mut transport = match transport {
+        Some(transport) if transport.is_connected() => transport,
+        _ => return,
+    };
+    transport.send_message(GAME_CHANNEL, protocol::encode(&ClientMessage::ListGames));
+    // Numbers only pick from the list once it is on screen
+    listed.0.clear();
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        left: Val::Px(10.0),
+                        top: Val::Px(10.0),
+                        ..default()
+                    },
+                    padding: UiRect::all(Val::Px(5.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                ..default()
+            },
+            GamesPanel,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "Loading your games...",
+                    TextStyle {
+                        font: asset_server.load("Inconsolata.ttf"),
+                        font_size: 14.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                GamesText,
+            ));
+        });
+}
+
+fn update_games(
+    mut received: EventReader<OpenGamesReceived>,
+    mut listed: ResMut<ListedGames>,
+    mut query: Query<&mut Text, With<GamesText>>,
+) {
+    let games = match received.iter().last() {
+        Some(OpenGamesReceived(games)) => games,
+        None => return,
+    };
+    listed.0 = games.clone();
+
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut text = String::from("Your correspondence games\n");
+    if games.is_empty() {
+        text.push_str("None yet.\n");
+    }
+    for (index, game) in games.iter().enumerate() {
+        let number = match index < NUMBER_KEYS.len() {
+            true => format!("{}.", index + 1),
+            false => "  ".to_string(),
+        };
+        let opponent = game
+            .opponent
+            .as_deref()
+            .unwrap_or("waiting for an opponent");
+        let state = match (game.stage, game.your_turn) {
+            (GameStage::Lobby, _) => "",
+            (GameStage::PreGame, true) => "place your fleet",
+            (_, true) => "your turn",
+            (_, false) => "their turn",
+        };
+        write!(text, "{} vs {:<20} {:<16}", number, opponent, state).unwrap();
+        if let Some(deadline) = game.deadline {
+            text.push_str(&format_time_left(deadline.saturating_sub(now)));
+        }
+        text.push('\n');
+    }
+    text.push_str("1-9: switch   N: new game   G: close");
+    for mut panel_text in &mut query {
+        panel_text.sections[0].value = text.clone();
+    }
+}
+
+fn format_time_left(secs: u64) -> String {
+    let hours = secs / 3600;
+    match hours {
+        0 if secs == 0 => "out of time".to_string(),
+        0 => format!("{}m left", (secs / 60).max(1)),
+        1..=47 => format!("{}h left", hours),
+        _ => format!("{}d {}h left", hours / 24, hours % 24),
+    }
+}
+
+fn switch_game(
+    mut commands: Commands,
+    kb_input: Res<Input<KeyCode>>,
+    keyboard_captured: Res<KeyboardCaptured>,
+    listed: Res<ListedGames>,
+    transport: Option<ResMut<Transport>>,
+    panel: Query<Entity, With<GamesPanel>>,
+) {
+    if keyboard_captured.0 || panel.is_empty() {
+        return;
+    }
+    let message = if kb_input.just_pressed(KeyCode::N) {
+        ClientMessage::LeaveRoom
+    } else {
+        let picked = NUMBER_KEYS
+            .iter()
+            .zip(&listed.0)
+            .find(|(key, _)| kb_input.just_pressed(**key));
+        match picked {
+            Some((_, game)) => ClientMessage::SwitchGame { room: game.room },
+            None => return,
+        }
+    };
+    let mut transport = match transport {
+        Some(transport) if transport.is_connected() => transport,
+        _ => return,
+    };
+    transport.send_message(GAME_CHANNEL, protocol::encode(&message));
+    for entity in &panel {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn despawn_games(mut commands: Commands, query: Query<Entity, With<GamesPanel>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,135 @@
+use bevy::prelude::*;
+use std::fmt::Write;
+use store::{
+    camera::KeyboardCaptured,
+    protocol::{self, ClientMessage, LeaderboardEntry, GAME_CHANNEL},
+    transport::Transport,
+};
+
+use crate::connect::Screen;
+
+/// Sent when the server answered a leaderboard request.
+pub struct LeaderboardReceived {
+    pub top: Vec<LeaderboardEntry>,
+    pub own: Option<LeaderboardEntry>,
+}
+
+#[derive(Component)]
+struct LeaderboardPanel;
+
+#[derive(Component)]
+struct LeaderboardText;
+
+/// Tab shows the server's leaderboard while playing, and hides it again.
+pub struct LeaderboardPlugin;
+impl Plugin for LeaderboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<LeaderboardReceived>()
+            .add_system_set(
+                SystemSet::on_update(Screen::Playing)
+                    .with_system(toggle_leaderboard)
+                    .with_system(update_leaderboard.after(toggle_leaderboard)),
+            )
+            .add_system_set(SystemSet::on_exit(Screen::Playing).with_system(despawn_leaderboard));
+    }
+}
+
+fn toggle_leaderboard(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    kb_input: Res<Input<KeyCode>>,
+    keyboard_captured: Res<KeyboardCaptured>,
+    transport: Option<ResMut<Transport>>,
+    panel: Query<Entity, With<LeaderboardPanel>>,
+) {
+    if keyboard_captured.0 || !kb_input.just_pressed(KeyCode::Tab) {
+        return;
+    }
+    if !panel.is_empty() {
+        for entity in &panel {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    }
+    let mut transport = match transport {
+        Some(transport) if transport.is_connected() => transport,
+        _ => return,
+    };
+    transport.send_message(
+        GAME_CHANNEL,
+        protocol::encode(&ClientMessage::RequestLeaderboard),
+    );
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        right: Val::Px(10.0),
+                        top: Val::Px(10.0),
+                        ..default()
+                    },
+                    padding: UiRect::all(Val::Px(5.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                ..default()
+            },
+            LeaderboardPanel,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "Loading the leaderboard...",
+                    TextStyle {
+                        font: asset_server.load("Inconsolata.ttf"),
+                        font_size: 14.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                LeaderboardText,
+            ));
+        });
+}
+
+fn update_leaderboard(
+    mut received: EventReader<LeaderboardReceived>,
+    mut query: Query<&mut Text, With<LeaderboardText>>,
+) {
+    let leaderboard = match received.iter().last() {
+        Some(leaderboard) => leaderboard,
+        None => return,
+    };
+
+    let mut text = String::from("Leaderboard\n");
+    if leaderboard.top.is_empty() {
+        text.push_str("Nobody is rated yet.\n");
+    }
+    for entry in &leaderboard.top {
+        writeln!(text, "{}", format_entry(entry)).unwrap();
+    }
+    match &leaderboard.own {
+        Some(own) if own.rank as usize > leaderboard.top.len() => {
+            write!(text, "...\n{}", format_entry(own)).unwrap();
+        }
+        Some(_) => {}
+        None => text.push_str("Finish a game to get rated."),
+    }
+    for mut panel_text in &mut query {
+        panel_text.sections[0].value = text.clone();
+    }
+}
+
+fn format_entry(entry: &LeaderboardEntry) -> String {
+    format!(
+        "{:>3}. {:<20} {:>5}  {}/{}",
+        entry.rank, entry.name, entry.rating, entry.wins, entry.games
+    )
+}
+
+fn despawn_leaderboard(mut commands: Commands, query: Query<Entity, With<LeaderboardPanel>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -1,74 +1,120 @@
-use bevy::{prelude::*, window::PresentMode};
-use bevy_renet::{run_if_client_connected, RenetClientPlugin};
-use renet::{
-    ClientAuthentication, RenetClient, RenetConnectionConfig, RenetError, NETCODE_USER_DATA_BYTES,
+mod chat;
+mod config;
+mod connect;
+mod end_screen;
+mod games;
+mod leaderboard;
+mod profile;
+mod reconnect;
+mod spectator;
+
+use anyhow::Context;
+use bevy::{ecs::system::SystemParam, prelude::*, window::PresentMode};
+use clap::Parser;
+use renet::{ClientAuthentication, RenetClient};
+use std::{
+    marker::PhantomData,
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, SystemTime},
 };
-use std::{net::UdpSocket, time::SystemTime};
 use store::{
-    camera::CameraPlugin,
+    camera::{CameraPlugin, KeyboardCaptured},
     game_objects::GameObjectsPlugin,
     map::{components::MouseCubePos, HexPlugin},
-    GameEvent, GameStage, GameState, WhoAmI,
+    names,
+    protocol::{self, ClientMessage, EventSeq, Role, ServerMessage, GAME_CHANNEL, PROTOCOL_ID},
+    transport::{run_if_connected, run_if_transport, Transport, TransportPlugin},
+    EndGameReason, GameEvent, GameStage, GameState, PendingCommands, SnapshotLoaded, Spectator,
 };
 
+use chat::ChatPlugin;
+use config::{Args, ClientConfig, ConfigPath};
+use connect::{AutoConnect, ConnectPlugin, ConnectionClosed, TokenIssued};
+use end_screen::{EndScreenPlugin, RematchRequested};
+use games::{GamesPlugin, OpenGamesReceived};
+use leaderboard::{LeaderboardPlugin, LeaderboardReceived};
+use profile::{ProfilePlugin, ProfileReceived};
+use reconnect::{close_for_good, ConnectionStatus, ReconnectPlugin};
+use spectator::SpectatorPlugin;
 use ui::UiPlugin;
-// This id needs to be the same as the server is using
-const PROTOCOL_ID: u64 = 1208;
 
-fn main() {
-    // Get username from stdin args
-    let args = std::env::args().collect::<Vec<String>>();
-    let username = &args[1];
+/// How often the client pings the server.
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let mut config = ClientConfig::load(&args.config)?;
+    config.apply_args(&args);
 
     let mut app = App::new();
 
     app.add_plugins(DefaultPlugins.set(WindowPlugin {
         window: WindowDescriptor {
             title: "BattleGrounds!".to_string(),
-            width: 500.,
-            height: 300.,
-            present_mode: PresentMode::AutoVsync,
+            width: config.window.width,
+            height: config.window.height,
+            present_mode: match config.window.vsync {
+                true => PresentMode::AutoVsync,
+                false => PresentMode::AutoNoVsync,
+            },
             ..default()
         },
         ..default()
     }))
     .insert_resource(ClearColor(Color::hex("282828").unwrap()))
-    // Renet setup
-    .add_plugin(RenetClientPlugin::default())
-    .insert_resource(new_renet_client(&username).unwrap())
-    .add_system(handle_renet_error)
+    // Client configuration and connect screen. The Transport is only inserted once connected.
+    .insert_resource(config)
+    .insert_resource(ConfigPath(args.config))
+    .add_plugin(ConnectPlugin {
+        role: match (args.spectate, args.correspondence) {
+            (true, _) => Role::Spectator,
+            (false, true) => Role::Correspondence,
+            (false, false) => Role::Player,
+        },
+    })
+    .add_plugin(ChatPlugin)
+    .add_plugin(SpectatorPlugin)
+    .add_plugin(EndScreenPlugin)
+    .add_plugin(LeaderboardPlugin)
+    .add_plugin(ProfilePlugin)
+    .add_plugin(GamesPlugin)
+    // Networking setup
+    .add_plugin(TransportPlugin)
+    .add_plugin(ReconnectPlugin)
     // Add game state and register GameEvent
     .insert_resource(GameState::default())
     .add_event::<GameEvent>()
+    .add_event::<SnapshotLoaded>()
+    .insert_resource(PendingCommands::default())
     // my own code
-    .add_startup_system(setup)
     .add_state(GameStage::Lobby)
-    .add_system(input)
+    .add_system(input.with_run_criteria(run_if_connected))
+    .add_system(ping_server.with_run_criteria(run_if_connected))
+    .add_system(request_snapshot.with_run_criteria(run_if_connected))
+    .add_system(resign.with_run_criteria(run_if_connected))
     .add_plugin(HexPlugin)
     .add_plugin(UiPlugin)
     .add_plugin(GameObjectsPlugin)
     .add_system(update_board)
     .add_system_to_stage(
         CoreStage::PostUpdate,
-        // Renet exposes a nice run criteria
-        // that can be used to make sure that this system only runs when connected
-        receive_events_from_server.with_run_criteria(run_if_client_connected),
+        receive_events_from_server.with_run_criteria(run_if_transport),
     );
 
     app.add_plugin(CameraPlugin);
     // .add_startup_system(setup_level);
     // .add_plugin(MaterialPlugin::<PlanetMaterial>::default())
 
+    if args.connect {
+        app.insert_resource(AutoConnect);
+    }
+
     app.run();
+    Ok(())
 }
 
 ////////// COMPONENTS /////////////
 
-////////// SETUP /////////////
-fn setup(mut commands: Commands, client: Res<RenetClient>) {
-    commands.insert_resource(WhoAmI(client.client_id()));
-}
-
 /////////// UPDATE SYSTEMTS /////////////
 
 fn input(
@@ -76,27 +122,40 @@ fn input(
     // kb_input: Res<Input<KeyCode>>,
     ms_coord_pos: Res<MouseCubePos>,
     game_state: Res<GameState>,
-    mut client: ResMut<RenetClient>,
+    mut transport: ResMut<Transport>,
+    mut pending: ResMut<PendingCommands>,
+    spectator: Option<Res<Spectator>>,
 ) {
+    if spectator.is_some() {
+        return;
+    }
     // If left mouse button is pressed, send mouse world pos
     if input.just_pressed(MouseButton::Left) {
         // We only want to handle inputs once we are ingame
         match game_state.stage {
             store::GameStage::PreGame => {}
             store::GameStage::InGame => {
+                // Moves out of turn would be refused and count against us
+                if game_state.cur_player != Some(transport.client_id()) {
+                    return;
+                }
+                // One move per turn, wait for the server to answer the last one
+                if pending.any(|event| matches!(event, GameEvent::ShipMove { .. })) {
+                    return;
+                }
                 let event = GameEvent::ShipMove {
-                    player_id: client.client_id(),
+                    player_id: transport.client_id(),
                     at: ms_coord_pos.0,
                 };
-                client.send_message(0, bincode::serialize(&event).unwrap());
-            }
-            _ => {
-                return;
+                pending.send(&mut transport, event);
             }
+            _ => {}
         };
     }
 }
 
+// Several events may change the stage within one frame, e.g. a game ending and its rematch
+// setting up the board again, so the last one wins instead of queueing a second transition
 fn update_board(mut game_stage: ResMut<State<GameStage>>, mut game_events: EventReader<GameEvent>) {
     for event in game_events.iter() {
         match event {
@@ -106,39 +165,46 @@ fn update_board(mut game_stage: ResMut<State<GameStage>>, mut game_events: Event
             GameEvent::ShipPlaced { .. } => {}
             GameEvent::BeginGame { .. } => {
                 info!("GAME STARTED!");
-                game_stage.set(GameStage::InGame).unwrap();
+                let _ = game_stage.overwrite_set(GameStage::InGame);
+            }
+            GameEvent::EndGame { reason } => {
+                info!("Game over: {:?}", reason);
+                let _ = game_stage.overwrite_set(GameStage::Ended);
             }
-            GameEvent::EndGame { .. } => todo!(),
             GameEvent::PlayerJoined { .. } => {}
-            GameEvent::PlayerDisconnected { .. } => todo!(),
+            GameEvent::PlayerDisconnected { player_id } => {
+                info!("{:?} left the game", player_id);
+            }
             GameEvent::SetupBoard => {
-                game_stage.set(GameStage::PreGame).unwrap();
+                let _ = game_stage.overwrite_set(GameStage::PreGame);
             }
         }
     }
 }
 
 //////////// RENET NETWORKING //////////////
-// Creates a RenetClient that is already connected to a server.
-// Returns an Err if connections fails
-fn new_renet_client(username: &String) -> anyhow::Result<RenetClient> {
-    let server_addr = "127.0.0.1:5000".parse()?;
-    let socket = UdpSocket::bind("127.0.0.1:0")?;
+// Creates a RenetClient that is already connected to a server, with the handshake queued.
+// Returns an Err with a message fit for the connect screen if the configuration is unusable
+pub fn new_renet_client(config: &ClientConfig, role: Role) -> anyhow::Result<RenetClient> {
+    let server_addr: SocketAddr = config
+        .server_addr
+        .trim()
+        .parse()
+        .with_context(|| format!("\"{}\" is not a valid server address", config.server_addr))?;
+    // Same checks as the server, so a bad name is caught before connecting
+    let username = names::validate_name(&config.username).map_err(|err| anyhow::anyhow!(err))?;
+
+    let socket = UdpSocket::bind("0.0.0.0:0").context("could not open a local socket")?;
     let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
     let client_id = current_time.as_millis() as u64;
 
     // Place username in user data
-    let mut user_data = [0u8; NETCODE_USER_DATA_BYTES];
-    if username.len() > NETCODE_USER_DATA_BYTES - 8 {
-        panic!("Username is too big");
-    }
-    user_data[0..8].copy_from_slice(&(username.len() as u64).to_le_bytes());
-    user_data[8..username.len() + 8].copy_from_slice(username.as_bytes());
+    let user_data = names::name_to_user_data(&username);
 
-    let client = RenetClient::new(
+    let mut client = RenetClient::new(
         current_time,
         socket,
-        RenetConnectionConfig::default(),
+        protocol::connection_config(),
         ClientAuthentication::Unsecure {
             client_id,
             protocol_id: PROTOCOL_ID,
@@ -147,31 +213,216 @@ fn new_renet_client(username: &String) -> anyhow::Result<RenetClient> {
         },
     )?;
 
+    // Messages are held back until the connection is established
+    client.send_message(
+        GAME_CHANNEL,
+        protocol::encode(&ClientMessage::Hello {
+            role,
+            token: config.player_token().cloned(),
+        }),
+    );
+
     Ok(client)
 }
 
-// If there's any network error we just panic
-// Ie. Client has lost connection to server, if internet is gone or server shudown
-fn handle_renet_error(mut renet_error: EventReader<RenetError>) {
-    for err in renet_error.iter() {
-        panic!("{}", err);
+fn ping_server(time: Res<Time>, mut timer: Local<Timer>, mut transport: ResMut<Transport>) {
+    if timer.duration().is_zero() {
+        *timer = Timer::new(PING_INTERVAL, TimerMode::Repeating);
+    }
+    if timer.tick(time.delta()).just_finished() {
+        let message = ClientMessage::Ping {
+            sent_at: unix_millis(),
+        };
+        transport.send_message(GAME_CHANNEL, protocol::encode(&message));
+    }
+}
+
+/// F5 asks the server for a fresh snapshot in case the board went out of sync.
+fn request_snapshot(kb_input: Res<Input<KeyCode>>, mut transport: ResMut<Transport>) {
+    if kb_input.just_pressed(KeyCode::F5) {
+        transport.send_message(
+            GAME_CHANNEL,
+            protocol::encode(&ClientMessage::RequestSnapshot),
+        );
+    }
+}
+
+/// The local copy of the game. Replaced by snapshots and reset when the connection closes.
+#[derive(SystemParam)]
+pub struct LocalGame<'w, 's> {
+    pub state: ResMut<'w, GameState>,
+    pub stage: ResMut<'w, State<GameStage>>,
+    pub pending: ResMut<'w, PendingCommands>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+/// Events raised for messages from the server.
+#[derive(SystemParam)]
+struct ServerEvents<'w, 's> {
+    game_events: EventWriter<'w, 's, GameEvent>,
+    snapshots: EventWriter<'w, 's, SnapshotLoaded>,
+    closed: EventWriter<'w, 's, ConnectionClosed>,
+    rematch_requests: EventWriter<'w, 's, RematchRequested>,
+    leaderboards: EventWriter<'w, 's, LeaderboardReceived>,
+    tokens: EventWriter<'w, 's, TokenIssued>,
+    profiles: EventWriter<'w, 's, ProfileReceived>,
+    open_games: EventWriter<'w, 's, OpenGamesReceived>,
+}
+
+/// Ctrl+Q resigns the game we are playing, which counts as a loss once it began.
+fn resign(
+    kb_input: Res<Input<KeyCode>>,
+    keyboard_captured: Res<KeyboardCaptured>,
+    game_state: Res<GameState>,
+    spectator: Option<Res<Spectator>>,
+    mut transport: ResMut<Transport>,
+    mut pending: ResMut<PendingCommands>,
+) {
+    let ctrl = kb_input.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    if !ctrl || !kb_input.just_pressed(KeyCode::Q) || keyboard_captured.0 || spectator.is_some() {
+        return;
     }
+    if !matches!(game_state.stage, GameStage::PreGame | GameStage::InGame) {
+        return;
+    }
+    let event = GameEvent::EndGame {
+        reason: EndGameReason::PlayerResigned {
+            player_id: transport.client_id(),
+        },
+    };
+    pending.send(&mut transport, event);
 }
 
 fn receive_events_from_server(
-    mut client: ResMut<RenetClient>,
-    mut game_state: ResMut<GameState>,
-    mut game_events: EventWriter<GameEvent>,
+    mut commands: Commands,
+    mut transport: ResMut<Transport>,
+    mut game: LocalGame,
+    mut events: ServerEvents,
+    // Set while waiting on the snapshot requested after a checksum mismatch or a bad message
+    mut resyncing: Local<bool>,
+    // Sequence number of the last event consumed
+    mut last_seq: Local<EventSeq>,
+    mut status: ResMut<ConnectionStatus>,
 ) {
-    while let Some(message) = client.receive_message(0) {
-        // Whenever the server sends a message we know it must be a game event
-        let event: GameEvent = bincode::deserialize(&message).unwrap();
-        trace!("{:#?}", event);
+    while let Some(message) = transport.receive_message(GAME_CHANNEL) {
+        let message: ServerMessage = match protocol::decode(&message) {
+            Ok(message) => message,
+            Err(err) => {
+                // A server speaking another protocol version will not understand us either
+                if let Some(handshake_error) = err.handshake_error() {
+                    let reason = handshake_error.to_string();
+                    close_for_good(&mut status, &mut events.closed, reason);
+                    return;
+                }
+                // Whatever we missed might have been an event, start over from a snapshot
+                error!("Could not decode message from server: {}, resyncing", err);
+                if !*resyncing {
+                    transport.send_message(
+                        GAME_CHANNEL,
+                        protocol::encode(&ClientMessage::RequestSnapshot),
+                    );
+                    *resyncing = true;
+                }
+                continue;
+            }
+        };
+
+        match message {
+            ServerMessage::Welcome {
+                player_id,
+                room,
+                role,
+            } => {
+                info!("Joined room {} as {:?} {}", room, role, player_id);
+                if role == Role::Spectator {
+                    commands.insert_resource(Spectator);
+                }
+            }
+            ServerMessage::HelloRejected(reason) => {
+                close_for_good(&mut status, &mut events.closed, reason.to_string());
+                return;
+            }
+            ServerMessage::Kicked(reason) => {
+                close_for_good(&mut status, &mut events.closed, reason.to_string());
+                return;
+            }
+            ServerMessage::Shutdown { reason } => {
+                close_for_good(&mut status, &mut events.closed, reason);
+                return;
+            }
+            ServerMessage::Restarting => {
+                // The connection drops next and reconnecting puts us back in our seat
+                info!("The server is restarting");
+            }
+            ServerMessage::Event {
+                seq,
+                event,
+                checksum,
+            } => {
+                trace!("{}: {:#?}", seq, event);
+                // Everything up to the snapshot we asked for is part of it
+                if *resyncing || seq <= *last_seq {
+                    continue;
+                }
+                *last_seq = seq;
 
-        // We trust the server, no need to validade events
-        game_state.consume(&event);
+                // We trust the server, no need to validade events
+                game.state.consume(&event);
+                if game.state.checksum() != checksum {
+                    warn!("Out of sync with the server after {:?}, resyncing", event);
+                    transport.send_message(
+                        GAME_CHANNEL,
+                        protocol::encode(&ClientMessage::RequestSnapshot),
+                    );
+                    *resyncing = true;
+                    continue;
+                }
 
-        // Send the event into the bevy event system so systems can react to it
-        game_events.send(event);
+                // Send the event into the bevy event system so systems can react to it
+                events.game_events.send(event);
+            }
+            ServerMessage::Accepted { id } => {
+                game.pending.ack(id);
+            }
+            ServerMessage::Rejected { id, reason } => {
+                warn!("Server rejected {:?}: {:?}", game.pending.ack(id), reason);
+            }
+            ServerMessage::Snapshot { seq, state } => {
+                trace!("Snapshot at stage {:?}", state.stage);
+                if *game.stage.current() != state.stage {
+                    game.stage.overwrite_set(state.stage).unwrap();
+                }
+                *game.state = state;
+                *resyncing = false;
+                *last_seq = seq;
+                events.snapshots.send(SnapshotLoaded);
+            }
+            ServerMessage::RematchRequested { by } => {
+                events.rematch_requests.send(RematchRequested { by });
+            }
+            ServerMessage::TokenIssued { token } => {
+                events.tokens.send(TokenIssued(token));
+            }
+            ServerMessage::Leaderboard { top, own } => {
+                events.leaderboards.send(LeaderboardReceived { top, own });
+            }
+            ServerMessage::Profile(profile) => {
+                events.profiles.send(ProfileReceived(profile));
+            }
+            ServerMessage::OpenGames(games) => {
+                events.open_games.send(OpenGamesReceived(games));
+            }
+            ServerMessage::Pong { sent_at } => {
+                trace!("Ping: {}ms", unix_millis().saturating_sub(sent_at));
+            }
+        }
     }
 }
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
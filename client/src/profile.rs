@@ -0,0 +1,151 @@
+use bevy::prelude::*;
+use std::fmt::Write;
+use store::{
+    camera::KeyboardCaptured,
+    protocol::{self, ClientMessage, PlayerProfile, GAME_CHANNEL},
+    transport::Transport,
+};
+
+use crate::connect::Screen;
+
+/// Sent when the server answered a profile request.
+pub struct ProfileReceived(pub Option<PlayerProfile>);
+
+#[derive(Component)]
+struct ProfileScreen;
+
+#[derive(Component)]
+struct ProfileText;
+
+/// P shows our career on this server while playing, and hides it again.
+pub struct ProfilePlugin;
+impl Plugin for ProfilePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ProfileReceived>()
+            .add_system_set(
+                SystemSet::on_update(Screen::Playing)
+                    .with_system(toggle_profile)
+                    .with_system(update_profile.after(toggle_profile)),
+            )
+            .add_system_set(SystemSet::on_exit(Screen::Playing).with_system(despawn_profile));
+    }
+}
+
+fn toggle_profile(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    kb_input: Res<Input<KeyCode>>,
+    keyboard_captured: Res<KeyboardCaptured>,
+    transport: Option<ResMut<Transport>>,
+    screen: Query<Entity, With<ProfileScreen>>,
+) {
+    if keyboard_captured.0 || !kb_input.just_pressed(KeyCode::P) {
+        return;
+    }
+    if !screen.is_empty() {
+        for entity in &screen {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    }
+    let mut transport = match transport {
+        Some(transport) if transport.is_connected() => transport,
+        _ => return,
+    };
+    transport.send_message(
+        GAME_CHANNEL,
+        protocol::encode(&ClientMessage::RequestProfile),
+    );
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            ProfileScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        padding: UiRect::all(Val::Px(10.0)),
+                        ..default()
+                    },
+                    background_color: Color::rgba(0.0, 0.0, 0.0, 0.8).into(),
+                    ..default()
+                })
+                .with_children(|panel| {
+                    panel.spawn((
+                        TextBundle::from_section(
+                            "Loading your profile...",
+                            TextStyle {
+                                font: asset_server.load("Inconsolata.ttf"),
+                                font_size: 16.0,
+                                color: Color::WHITE,
+                            },
+                        ),
+                        ProfileText,
+                    ));
+                });
+        });
+}
+
+fn update_profile(
+    mut received: EventReader<ProfileReceived>,
+    mut query: Query<&mut Text, With<ProfileText>>,
+) {
+    let profile = match received.iter().last() {
+        Some(ProfileReceived(Some(profile))) => profile,
+        Some(ProfileReceived(None)) => {
+            for mut text in &mut query {
+                text.sections[0].value = "No profile yet, finish a game first.".to_string();
+            }
+            return;
+        }
+        None => return,
+    };
+
+    let mut text = String::new();
+    writeln!(text, "{}\n", profile.name).unwrap();
+    writeln!(
+        text,
+        "Games: {}  Wins: {}  Losses: {}",
+        profile.games, profile.wins, profile.losses
+    )
+    .unwrap();
+    write!(text, "Shots: {}  Hits: {}", profile.shots, profile.hits).unwrap();
+    match profile.accuracy() {
+        Some(accuracy) => writeln!(text, "  Accuracy: {:.0}%", accuracy * 100.0).unwrap(),
+        None => text.push('\n'),
+    }
+    if let Some(turns) = profile.average_turns_to_win {
+        writeln!(text, "Turns to win: {:.1} on average", turns).unwrap();
+    }
+    if !profile.favourite_placements.is_empty() {
+        text.push_str("\nFavourite placements:\n");
+        for placement in &profile.favourite_placements {
+            writeln!(
+                text,
+                "  {:?} at ({}, {}) turned {}, {} times",
+                placement.ship, placement.at.q, placement.at.r, placement.rotation, placement.times
+            )
+            .unwrap();
+        }
+    }
+    for mut panel_text in &mut query {
+        panel_text.sections[0].value = text.clone();
+    }
+}
+
+fn despawn_profile(mut commands: Commands, query: Query<Entity, With<ProfileScreen>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
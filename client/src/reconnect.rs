@@ -0,0 +1,265 @@
+use bevy::prelude::*;
+use std::time::Duration;
+use store::{
+    transport::{Transport, TransportError},
+    PendingCommands, WhoAmI,
+};
+
+use crate::config::ClientConfig;
+use crate::connect::{ConnectForm, ConnectionClosed};
+use crate::{new_renet_client, receive_events_from_server};
+
+/// Reconnect attempts before giving up and going back to the connect screen.
+const MAX_ATTEMPTS: u32 = 6;
+
+/// Longest wait between two reconnect attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Where the client's connection to the server stands.
+#[derive(Debug, Resource)]
+pub enum ConnectionStatus {
+    /// No connection, or one the server closed for good. The connect screen is up.
+    Offline,
+    /// Waiting for the server to accept the connection. `attempt` is 0 unless reconnecting.
+    Connecting {
+        attempt: u32,
+    },
+    Connected,
+    /// The connection dropped, the next attempt is made once `retry` finishes.
+    Lost {
+        reason: String,
+        attempt: u32,
+        retry: Timer,
+    },
+}
+
+#[derive(Component)]
+struct LostOverlay;
+
+#[derive(Component)]
+struct LostText;
+
+/// Notices when the connection drops and reconnects with exponential backoff, showing an
+/// overlay meanwhile. Escape gives up and goes back to the connect screen.
+/// Connections closed on purpose go through [`ConnectionClosed`] instead and are not retried.
+pub struct ReconnectPlugin;
+impl Plugin for ReconnectPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ConnectionStatus::Offline)
+            // Only once the last messages were read, they may close the connection for good
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                watch_connection.after(receive_events_from_server),
+            )
+            .add_system(retry_connection)
+            .add_system(give_up)
+            .add_system(update_overlay.after(retry_connection));
+    }
+}
+
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(1 << attempt.saturating_sub(1).min(5)).min(MAX_BACKOFF)
+}
+
+/// Follows the Transport from connecting to connected, and drops it once it failed.
+fn watch_connection(
+    mut commands: Commands,
+    mut errors: EventReader<TransportError>,
+    transport: Option<ResMut<Transport>>,
+    mut status: ResMut<ConnectionStatus>,
+    mut pending: ResMut<PendingCommands>,
+    mut closed: EventWriter<ConnectionClosed>,
+) {
+    let error = errors.iter().last().map(|err| err.to_string());
+    let mut transport = match transport {
+        Some(transport) => transport,
+        None => return,
+    };
+    if matches!(*status, ConnectionStatus::Offline) {
+        return;
+    }
+    let reason = match error.or_else(|| transport.disconnected()) {
+        Some(reason) => reason,
+        None => {
+            if transport.is_connected() && !matches!(*status, ConnectionStatus::Connected) {
+                info!("Connected to the server");
+                *status = ConnectionStatus::Connected;
+            }
+            return;
+        }
+    };
+
+    transport.disconnect();
+    commands.remove_resource::<Transport>();
+    // Commands in flight died with the connection
+    *pending = PendingCommands::default();
+    lose_connection(&mut status, reason, &mut closed);
+}
+
+/// Closes the connection for good because the server said so, e.g. when kicked.
+/// Unlike a lost connection this is not retried.
+pub fn close_for_good(
+    status: &mut ConnectionStatus,
+    closed: &mut EventWriter<ConnectionClosed>,
+    reason: String,
+) {
+    *status = ConnectionStatus::Offline;
+    closed.send(ConnectionClosed { reason });
+}
+
+/// Schedules the next reconnect attempt, or gives up.
+fn lose_connection(
+    status: &mut ConnectionStatus,
+    reason: String,
+    closed: &mut EventWriter<ConnectionClosed>,
+) {
+    let attempt = match status {
+        // Never got in, most likely a wrong address. Let the player fix it.
+        ConnectionStatus::Connecting { attempt: 0 } => {
+            closed.send(ConnectionClosed {
+                reason: format!("Could not connect: {}", reason),
+            });
+            return;
+        }
+        ConnectionStatus::Connecting { attempt } | ConnectionStatus::Lost { attempt, .. } => {
+            *attempt + 1
+        }
+        ConnectionStatus::Connected | ConnectionStatus::Offline => 1,
+    };
+    if attempt > MAX_ATTEMPTS {
+        closed.send(ConnectionClosed {
+            reason: format!("Connection lost: {}", reason),
+        });
+        return;
+    }
+
+    let wait = backoff(attempt);
+    warn!("Connection lost ({}), reconnecting in {:?}", reason, wait);
+    *status = ConnectionStatus::Lost {
+        reason,
+        attempt,
+        retry: Timer::new(wait, TimerMode::Once),
+    };
+}
+
+/// Opens a new connection once the backoff is over. A server that restarted with our game
+/// saved seats us back in it by our token, otherwise this joins a room from scratch.
+fn retry_connection(
+    mut commands: Commands,
+    time: Res<Time>,
+    config: Res<ClientConfig>,
+    form: Res<ConnectForm>,
+    mut status: ResMut<ConnectionStatus>,
+    mut closed: EventWriter<ConnectionClosed>,
+) {
+    let (attempt, retry) = match &mut *status {
+        ConnectionStatus::Lost { attempt, retry, .. } => (*attempt, retry),
+        _ => return,
+    };
+    if !retry.tick(time.delta()).finished() {
+        return;
+    }
+
+    info!("Reconnecting, attempt {}/{}", attempt, MAX_ATTEMPTS);
+    match new_renet_client(&config, form.role) {
+        Ok(client) => {
+            commands.insert_resource(WhoAmI(client.client_id()));
+            commands.insert_resource(Transport::new(client));
+            *status = ConnectionStatus::Connecting { attempt };
+        }
+        Err(err) => lose_connection(&mut status, format!("{:#}", err), &mut closed),
+    }
+}
+
+fn give_up(
+    kb_input: Res<Input<KeyCode>>,
+    status: Res<ConnectionStatus>,
+    mut closed: EventWriter<ConnectionClosed>,
+) {
+    let reconnecting = match *status {
+        ConnectionStatus::Lost { .. } => true,
+        ConnectionStatus::Connecting { attempt } => attempt > 0,
+        _ => false,
+    };
+    if reconnecting && kb_input.just_pressed(KeyCode::Escape) {
+        closed.send(ConnectionClosed {
+            reason: "Gave up reconnecting".to_string(),
+        });
+    }
+}
+
+fn update_overlay(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    status: Res<ConnectionStatus>,
+    overlay: Query<Entity, With<LostOverlay>>,
+    mut texts: Query<&mut Text, With<LostText>>,
+) {
+    let message = match &*status {
+        ConnectionStatus::Lost {
+            reason,
+            attempt,
+            retry,
+        } => format!(
+            "Connection lost: {}\nReconnecting in {:.0}s (attempt {}/{})",
+            reason,
+            retry.remaining_secs().ceil(),
+            attempt,
+            MAX_ATTEMPTS
+        ),
+        ConnectionStatus::Connecting { attempt } if *attempt > 0 => {
+            format!("Reconnecting... (attempt {}/{})", attempt, MAX_ATTEMPTS)
+        }
+        _ => {
+            for entity in &overlay {
+                commands.entity(entity).despawn_recursive();
+            }
+            return;
+        }
+    };
+
+    if overlay.is_empty() {
+        let font = asset_server.load("Inconsolata.ttf");
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                        flex_direction: FlexDirection::ColumnReverse,
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                    ..default()
+                },
+                LostOverlay,
+            ))
+            .with_children(|parent| {
+                parent.spawn((
+                    TextBundle::from_section(
+                        message,
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 20.0,
+                            color: Color::ORANGE_RED,
+                        },
+                    ),
+                    LostText,
+                ));
+                parent.spawn(TextBundle::from_section(
+                    "Escape: back to the connect screen",
+                    TextStyle {
+                        font,
+                        font_size: 14.0,
+                        color: Color::GRAY,
+                    },
+                ));
+            });
+        return;
+    }
+    for mut text in &mut texts {
+        text.sections[0].value = message.clone();
+    }
+}
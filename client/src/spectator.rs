@@ -0,0 +1,47 @@
+use bevy::prelude::*;
+use store::Spectator;
+
+#[derive(Component)]
+struct SpectatorBanner;
+
+/// Shows a banner while the client is watching a match instead of playing it.
+pub struct SpectatorPlugin;
+impl Plugin for SpectatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(update_spectator_banner);
+    }
+}
+
+fn update_spectator_banner(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    spectator: Option<Res<Spectator>>,
+    query: Query<Entity, With<SpectatorBanner>>,
+) {
+    match (spectator.is_some(), query.get_single()) {
+        (true, Err(_)) => {
+            commands.spawn((
+                TextBundle::from_section(
+                    "SPECTATING",
+                    TextStyle {
+                        font: asset_server.load("Inconsolata.ttf"),
+                        font_size: 20.0,
+                        color: Color::YELLOW,
+                    },
+                )
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        top: Val::Px(10.0),
+                        right: Val::Px(10.0),
+                        ..default()
+                    },
+                    ..default()
+                }),
+                SpectatorBanner,
+            ));
+        }
+        (false, Ok(entity)) => commands.entity(entity).despawn_recursive(),
+        _ => {}
+    }
+}
@@ -0,0 +1,585 @@
+//! The rules of the game without any rendering: GameState and its events, the hex grid,
+//! ship footprints and the network protocol. Used by the server as is and by the client
+//! through `store`, which layers the Bevy plugins on top. With the `bevy` feature the
+//! GameState can be used as a Bevy resource.
+
+pub mod hex;
+pub mod names;
+pub mod protocol;
+pub mod ships;
+pub mod transport;
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use hex::CubeCoords;
+use ships::{get_max_grid_rotation, get_object_all_coords, GameObject, SHIPS};
+
+/// Struct for storing player related data.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Player {
+    pub name: String,
+}
+
+/// An event that progresses the GameState forward
+#[derive(Debug, Clone, Serialize, PartialEq, Deserialize)]
+pub enum GameEvent {
+    SetupBoard,
+    BeginGame {
+        first_player: PlayerId,
+    },
+    EndGame {
+        reason: EndGameReason,
+    },
+    PlayerJoined {
+        player_id: PlayerId,
+        player_details: Player,
+    },
+    PlayerDisconnected {
+        player_id: PlayerId,
+    },
+    // PlayerSelects {
+    //     player_id: PlayerId,
+    //     select_box: SelectQuad,
+    // },
+    ShipMove {
+        player_id: PlayerId,
+        at: CubeCoords,
+    },
+    ShipPlaced {
+        player_id: PlayerId,
+        ship_type: GameObject,
+        at: CubeCoords,
+        rotation: i32,
+    },
+}
+
+impl GameEvent {
+    /// The same event with every mention of player `from` replaced by `to`.
+    pub fn with_player_renamed(&self, from: PlayerId, to: PlayerId) -> Self {
+        let rename = |player_id: &PlayerId| if *player_id == from { to } else { *player_id };
+        let mut event = self.clone();
+        match &mut event {
+            GameEvent::BeginGame { first_player } => *first_player = rename(first_player),
+            GameEvent::EndGame { reason } => match reason {
+                EndGameReason::PlayerLeft { player_id }
+                | EndGameReason::PlayerResigned { player_id }
+                | EndGameReason::TimedOut { player_id } => *player_id = rename(player_id),
+                EndGameReason::PlayerWon { winner } => *winner = rename(winner),
+                EndGameReason::Aborted { .. } => {}
+            },
+            GameEvent::PlayerJoined { player_id, .. }
+            | GameEvent::PlayerDisconnected { player_id }
+            | GameEvent::ShipMove { player_id, .. }
+            | GameEvent::ShipPlaced { player_id, .. } => *player_id = rename(player_id),
+            GameEvent::SetupBoard => {}
+        }
+        event
+    }
+
+    /// Whether `viewer` may see this event. Ship placements are hidden from everyone but
+    /// their owner, and from spectators unless the server reveals fleets.
+    pub fn is_visible_to(&self, viewer: &Viewer) -> bool {
+        match self {
+            GameEvent::ShipPlaced { player_id, .. } => viewer.sees_fleet_of(player_id),
+            _ => true,
+        }
+    }
+}
+
+/// Whoever events or a GameState are being sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Viewer {
+    Player(PlayerId),
+    Spectator { reveal_fleets: bool },
+}
+
+impl Viewer {
+    pub fn sees_fleet_of(&self, player_id: &PlayerId) -> bool {
+        match self {
+            Viewer::Player(id) => id == player_id,
+            Viewer::Spectator { reveal_fleets } => *reveal_fleets,
+        }
+    }
+}
+
+/// The different states a game can be in. (not to be confused with the entire "GameState")
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum GameStage {
+    Lobby,
+    PreGame,
+    InGame,
+    Paused,
+    Ended,
+}
+
+/// This just makes it easier to dissern between a player id and any ol' u64
+pub type PlayerId = u64;
+
+/// A GameState object that is able to keep track of a game of TicTacTussle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "bevy", derive(bevy_ecs::system::Resource))]
+pub struct GameState {
+    pub stage: GameStage,
+    pub players: HashMap<PlayerId, Player>,
+    pub player_ships: HashMap<PlayerId, Vec<(GameObject, CubeCoords, i32)>>,
+    pub history: Vec<GameEvent>,
+    pub cur_player: Option<PlayerId>,
+}
+
+impl Default for GameState {
+    fn default() -> Self {
+        Self {
+            stage: GameStage::Lobby,
+            players: HashMap::new(),
+            player_ships: HashMap::new(),
+            history: Vec::new(),
+            cur_player: None,
+        }
+    }
+}
+
+impl GameState {
+    /// Determines whether an event is valid considering the current GameState
+    pub fn validade(&self, event: &GameEvent) -> bool {
+        use GameEvent::*;
+        match event {
+            BeginGame { first_player } => {
+                if self.stage != GameStage::PreGame {
+                    return false;
+                }
+                if !self.players.contains_key(first_player) {
+                    return false;
+                }
+                if self.players.len() != 2 {
+                    return false;
+                }
+                if self
+                    .player_ships
+                    .iter()
+                    .any(|(_, vec)| vec.len() < SHIPS.len())
+                {
+                    return false;
+                }
+            }
+            EndGame { reason } => match reason {
+                EndGameReason::PlayerWon { winner } => {
+                    if self.stage != GameStage::InGame {
+                        return false;
+                    }
+                    if !self.players.contains_key(winner) {
+                        return false;
+                    }
+                }
+                EndGameReason::PlayerResigned { player_id }
+                | EndGameReason::TimedOut { player_id } => {
+                    if !matches!(self.stage, GameStage::PreGame | GameStage::InGame) {
+                        return false;
+                    }
+                    if !self.players.contains_key(player_id) {
+                        return false;
+                    }
+                }
+                EndGameReason::PlayerLeft { .. } | EndGameReason::Aborted { .. } => {}
+            },
+            PlayerJoined {
+                player_id,
+                player_details: _,
+            } => {
+                if self.players.contains_key(player_id) {
+                    return false;
+                }
+            }
+            PlayerDisconnected { player_id } => {
+                if !self.players.contains_key(player_id) {
+                    return false;
+                }
+            }
+            ShipMove { player_id, at: _ } => {
+                return self.stage == GameStage::InGame && self.is_player_turn(player_id)
+            }
+            ShipPlaced {
+                player_id,
+                ship_type,
+                rotation,
+                ..
+            } => {
+                // check if game is in PreGame
+                if self.stage != GameStage::PreGame {
+                    return false;
+                }
+
+                // the footprint of the ship is only known for these rotations
+                let max_rotation = get_max_grid_rotation(ship_type);
+                if !(-max_rotation..max_rotation).contains(rotation) {
+                    return false;
+                }
+
+                // check if player is still allowed to place ships
+                match self.player_ships.get(player_id) {
+                    Some(garage) => {
+                        if garage.len() == SHIPS.len() {
+                            return false;
+                        }
+                        if garage.len() > SHIPS.len() {
+                            panic!("{:?} has placed more ships than allowed", player_id);
+                        }
+                    }
+                    None => {
+                        return false;
+                    }
+                }
+            }
+            SetupBoard => {
+                if self.stage != GameStage::Lobby {
+                    return false;
+                }
+                if self.players.len() != 2 {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    pub fn consume(&mut self, valid_event: &GameEvent) {
+        use GameEvent::*;
+        match valid_event {
+            BeginGame { first_player } => {
+                let player = self
+                    .players
+                    .iter()
+                    .find(|(p, _)| *p != first_player)
+                    .unwrap();
+                self.cur_player = Some(*player.0);
+                log::trace!("First player: {:?}", *player.0);
+                self.stage = GameStage::InGame;
+            }
+            EndGame { reason: _ } => self.stage = GameStage::Ended,
+            PlayerDisconnected { player_id } => {
+                self.players.remove(player_id);
+            }
+            PlayerJoined {
+                player_id,
+                player_details,
+            } => {
+                self.players.insert(*player_id, player_details.clone());
+            }
+            ShipMove {
+                player_id: _,
+                at: _,
+            } => {
+                self.cur_player = self.next_player();
+            }
+            ShipPlaced {
+                player_id,
+                at,
+                rotation,
+                ship_type,
+            } => {
+                let ship_vec = self.player_ships.get_mut(player_id).unwrap();
+                ship_vec.push((*ship_type, *at, *rotation));
+            }
+            SetupBoard => {
+                self.stage = GameStage::PreGame;
+                for p in &self.players {
+                    self.player_ships.insert(*p.0, Vec::new());
+                }
+            }
+        }
+
+        self.history.push(valid_event.clone());
+    }
+
+    /// Everyone who joined the game in order, including players that left since.
+    pub fn joined_players(&self) -> Vec<(PlayerId, &Player)> {
+        self.history
+            .iter()
+            .filter_map(|event| match event {
+                GameEvent::PlayerJoined {
+                    player_id,
+                    player_details,
+                } => Some((*player_id, player_details)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The players the game waits for: whoever still has ships to place while setting up,
+    /// then the player whose turn it is.
+    pub fn players_to_move(&self) -> Vec<PlayerId> {
+        match self.stage {
+            GameStage::PreGame => {
+                let mut waiting: Vec<_> = self
+                    .player_ships
+                    .iter()
+                    .filter(|(_, ships)| ships.len() < SHIPS.len())
+                    .map(|(player_id, _)| *player_id)
+                    .collect();
+                waiting.sort();
+                waiting
+            }
+            GameStage::InGame => self.cur_player.into_iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The same game with player `from` going by `to` instead, e.g. after reconnecting.
+    pub fn with_player_renamed(&self, from: PlayerId, to: PlayerId) -> Self {
+        let history: Vec<_> = self
+            .history
+            .iter()
+            .map(|event| event.with_player_renamed(from, to))
+            .collect();
+        Self::replay(&history)
+    }
+
+    /// Rebuilds a GameState by consuming `events` in order.
+    pub fn replay<'a>(events: impl IntoIterator<Item = &'a GameEvent>) -> Self {
+        let mut game_state = Self::default();
+        for event in events {
+            game_state.consume(event);
+        }
+        game_state
+    }
+
+    /// A copy of the GameState with everything `viewer` may not see taken out:
+    /// hidden fleets are emptied and the events that placed them are dropped from the history.
+    pub fn redacted_for(&self, viewer: &Viewer) -> Self {
+        let mut redacted = self.clone();
+        for (player_id, ships) in redacted.player_ships.iter_mut() {
+            if !viewer.sees_fleet_of(player_id) {
+                ships.clear();
+            }
+        }
+        redacted.history.retain(|event| event.is_visible_to(viewer));
+        redacted
+    }
+
+    /// A hash of the GameState that is the same on every machine and build, so the client
+    /// can tell whether it still agrees with the server. Maps are hashed in player id order.
+    pub fn checksum(&self) -> u64 {
+        let players: BTreeMap<_, _> = self.players.iter().collect();
+        let player_ships: BTreeMap<_, _> = self.player_ships.iter().collect();
+        let canonical = (
+            self.history.len() as u64,
+            self.stage,
+            players,
+            player_ships,
+            &self.history,
+            self.cur_player,
+        );
+        // FNV-1a, std's hashers are not guaranteed to be stable across builds
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in bincode::serialize(&canonical).unwrap() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+
+    /// Whoever still has ships afloat once the fleet of the other player is sunk, i.e. every
+    /// hex it covers was shot at.
+    pub fn determine_winner(&self) -> Option<PlayerId> {
+        if self.stage != GameStage::InGame || self.players.len() != 2 {
+            return None;
+        }
+        let is_sunk = |owner: &PlayerId| {
+            let ships = match self.player_ships.get(owner) {
+                Some(ships) => ships,
+                None => return false,
+            };
+            ships
+                .iter()
+                .flat_map(|(ship_type, at, rotation)| {
+                    get_object_all_coords(ship_type, *rotation, at)
+                })
+                .all(|hex| {
+                    self.history.iter().any(|event| {
+                        matches!(event, GameEvent::ShipMove { player_id, at }
+                            if player_id != owner && *at == hex)
+                    })
+                })
+        };
+        let afloat: Vec<_> = self
+            .players
+            .keys()
+            .filter(|player_id| !is_sunk(player_id))
+            .collect();
+        match afloat[..] {
+            [winner] => Some(*winner),
+            _ => None,
+        }
+    }
+
+    fn next_player(&self) -> Option<PlayerId> {
+        if let Some(player_moved) = self.cur_player {
+            for (key, _) in self.players.iter() {
+                if player_moved != *key {
+                    return Some(*key);
+                }
+            }
+        }
+        None
+    }
+
+    fn is_player_turn(&self, player_id: &PlayerId) -> bool {
+        if let Some(p) = self.cur_player {
+            if *player_id == p {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// The various reasons why a game could end
+#[derive(Debug, Clone, Serialize, PartialEq, Deserialize)]
+pub enum EndGameReason {
+    PlayerLeft {
+        player_id: PlayerId,
+    },
+    PlayerWon {
+        winner: PlayerId,
+    },
+    /// The player gave up, the opponent wins.
+    PlayerResigned {
+        player_id: PlayerId,
+    },
+    /// Ended by the server's operator, `reason` is shown to the players.
+    Aborted {
+        reason: String,
+    },
+    /// The player let the deadline of a correspondence game pass, the opponent wins.
+    TimedOut {
+        player_id: PlayerId,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: PlayerId = 1;
+    const BOB: PlayerId = 2;
+
+    /// Both players joined, no ship placed yet.
+    fn pre_game() -> GameState {
+        let mut state = GameState::default();
+        for (player_id, name) in [(ALICE, "alice"), (BOB, "bob")] {
+            state.consume(&GameEvent::PlayerJoined {
+                player_id,
+                player_details: Player { name: name.into() },
+            });
+        }
+        state.consume(&GameEvent::SetupBoard);
+        state
+    }
+
+    fn place_fleet(state: &mut GameState, player_id: PlayerId, r: i32) {
+        for (i, ship_type) in SHIPS.iter().enumerate() {
+            let q = 2 * i as i32;
+            let event = GameEvent::ShipPlaced {
+                player_id,
+                ship_type: *ship_type,
+                at: CubeCoords { q, r, s: -q - r },
+                rotation: 0,
+            };
+            assert!(state.validade(&event));
+            state.consume(&event);
+        }
+    }
+
+    fn fleet_hexes(state: &GameState, player_id: PlayerId) -> Vec<CubeCoords> {
+        state.player_ships[&player_id]
+            .iter()
+            .flat_map(|(ship_type, at, rotation)| get_object_all_coords(ship_type, *rotation, at))
+            .collect()
+    }
+
+    #[test]
+    fn ships_are_placed_within_their_rotations() {
+        let state = pre_game();
+        let placed = |rotation| GameEvent::ShipPlaced {
+            player_id: ALICE,
+            ship_type: GameObject::Boat,
+            at: CubeCoords::ZERO,
+            rotation,
+        };
+        assert!(state.validade(&placed(5)));
+        assert!(state.validade(&placed(-6)));
+        assert!(!state.validade(&placed(6)));
+        assert!(!state.validade(&placed(-7)));
+        assert!(!state.validade(&placed(i32::MIN)));
+        assert!(!state.validade(&placed(i32::MAX)));
+    }
+
+    #[test]
+    fn the_game_begins_once_both_fleets_are_placed() {
+        let mut state = pre_game();
+        let begin = GameEvent::BeginGame { first_player: BOB };
+        place_fleet(&mut state, ALICE, 0);
+        assert!(!state.validade(&begin));
+        place_fleet(&mut state, BOB, 4);
+        assert!(state.validade(&begin));
+        state.consume(&begin);
+        assert!(!state.validade(&begin));
+    }
+
+    #[test]
+    fn the_player_with_ships_afloat_wins() {
+        let mut state = pre_game();
+        place_fleet(&mut state, ALICE, 0);
+        place_fleet(&mut state, BOB, 4);
+        assert_eq!(state.determine_winner(), None);
+        state.consume(&GameEvent::BeginGame {
+            first_player: ALICE,
+        });
+
+        let hexes = fleet_hexes(&state, ALICE);
+        for at in &hexes {
+            assert_eq!(state.determine_winner(), None);
+            state.consume(&GameEvent::ShipMove {
+                player_id: BOB,
+                at: *at,
+            });
+        }
+        assert_eq!(state.determine_winner(), Some(BOB));
+    }
+
+    #[test]
+    fn the_game_waits_for_fleets_then_turns() {
+        let mut state = pre_game();
+        assert_eq!(state.players_to_move(), vec![ALICE, BOB]);
+        place_fleet(&mut state, ALICE, 0);
+        assert_eq!(state.players_to_move(), vec![BOB]);
+        place_fleet(&mut state, BOB, 4);
+        state.consume(&GameEvent::BeginGame {
+            first_player: ALICE,
+        });
+        assert_eq!(state.players_to_move(), vec![BOB]);
+    }
+
+    #[test]
+    fn renamed_players_keep_their_fleet_and_turn() {
+        let mut state = pre_game();
+        place_fleet(&mut state, ALICE, 0);
+        place_fleet(&mut state, BOB, 4);
+        state.consume(&GameEvent::BeginGame {
+            first_player: ALICE,
+        });
+        assert!(state.is_player_turn(&BOB));
+
+        let renamed = state.with_player_renamed(BOB, 7);
+        assert_eq!(renamed.history.len(), state.history.len());
+        assert_eq!(renamed.players[&7].name, "bob");
+        assert!(!renamed.players.contains_key(&BOB));
+        assert_eq!(fleet_hexes(&renamed, 7), fleet_hexes(&state, BOB));
+        let shot = |player_id| GameEvent::ShipMove {
+            player_id,
+            at: CubeCoords::ZERO,
+        };
+        assert!(renamed.validade(&shot(7)));
+        assert!(!renamed.validade(&shot(BOB)));
+    }
+}
@@ -0,0 +1,176 @@
+use renet::NETCODE_USER_DATA_BYTES;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Longest player name, in characters.
+pub const MAX_NAME_LEN: usize = 20;
+
+/// Why a player name was refused.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NameError {
+    /// The connection's user data does not hold a name at all.
+    Malformed,
+    NotUtf8,
+    Empty,
+    TooLong {
+        max: usize,
+    },
+    InvalidCharacter(char),
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameError::Malformed => write!(f, "The server could not read your username."),
+            NameError::NotUtf8 => write!(f, "Your username is not valid text."),
+            NameError::Empty => write!(f, "Please enter a username."),
+            NameError::TooLong { max } => {
+                write!(f, "Your username is too long (at most {} characters).", max)
+            }
+            NameError::InvalidCharacter(c) => write!(
+                f,
+                "Your username contains '{}'. Use letters, digits, spaces, '-', '_' and '.'.",
+                c.escape_default()
+            ),
+        }
+    }
+}
+
+/// Trims a player name and checks that it is fit to be shown to others.
+pub fn validate_name(name: &str) -> Result<String, NameError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(NameError::Empty);
+    }
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err(NameError::TooLong { max: MAX_NAME_LEN });
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.')))
+    {
+        return Err(NameError::InvalidCharacter(c));
+    }
+    // Collapse runs of spaces so names can't be told apart by whitespace alone
+    Ok(name.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// Packs a player name into a connection's user data: its length in bytes as a little endian
+/// u64, followed by the UTF-8 bytes. Names that don't fit are cut.
+pub fn name_to_user_data(name: &str) -> [u8; NETCODE_USER_DATA_BYTES] {
+    let mut user_data = [0u8; NETCODE_USER_DATA_BYTES];
+    let mut len = name.len().min(NETCODE_USER_DATA_BYTES - 8);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    user_data[0..8].copy_from_slice(&(len as u64).to_le_bytes());
+    user_data[8..len + 8].copy_from_slice(&name.as_bytes()[..len]);
+    user_data
+}
+
+/// Reads and validates the player name packed by [`name_to_user_data`].
+pub fn name_from_user_data(user_data: &[u8]) -> Result<String, NameError> {
+    let user_data = &user_data[..user_data.len().min(NETCODE_USER_DATA_BYTES)];
+    if user_data.len() < 8 {
+        return Err(NameError::Malformed);
+    }
+    let mut buffer = [0u8; 8];
+    buffer.copy_from_slice(&user_data[0..8]);
+    let len = u64::from_le_bytes(buffer);
+    if len > (user_data.len() - 8) as u64 {
+        return Err(NameError::Malformed);
+    }
+    let name =
+        std::str::from_utf8(&user_data[8..8 + len as usize]).map_err(|_| NameError::NotUtf8)?;
+    validate_name(name)
+}
+
+/// Makes `name` unique among `taken` by appending " (2)", " (3)", ... as needed,
+/// shortening it so the result still fits in [`MAX_NAME_LEN`].
+pub fn unique_name<'a>(name: &str, taken: impl IntoIterator<Item = &'a str> + Clone) -> String {
+    let is_taken = |candidate: &str| {
+        taken
+            .clone()
+            .into_iter()
+            .any(|other| other.eq_ignore_ascii_case(candidate))
+    };
+    if !is_taken(name) {
+        return name.to_string();
+    }
+    (2..)
+        .map(|n| {
+            let suffix = format!(" ({})", n);
+            let base: String = name
+                .chars()
+                .take(MAX_NAME_LEN.saturating_sub(suffix.chars().count()))
+                .collect();
+            format!("{}{}", base.trim_end(), suffix)
+        })
+        .find(|candidate| !is_taken(candidate))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_trimmed_and_checked() {
+        assert_eq!(
+            validate_name("  Ada   Lovelace "),
+            Ok("Ada Lovelace".to_string())
+        );
+        assert_eq!(validate_name("jörg_1.b-2"), Ok("jörg_1.b-2".to_string()));
+        assert_eq!(validate_name(" \t "), Err(NameError::Empty));
+        assert_eq!(
+            validate_name(&"a".repeat(MAX_NAME_LEN + 1)),
+            Err(NameError::TooLong { max: MAX_NAME_LEN })
+        );
+        assert!(validate_name(&"ä".repeat(MAX_NAME_LEN)).is_ok());
+        assert_eq!(
+            validate_name("bob<script>"),
+            Err(NameError::InvalidCharacter('<'))
+        );
+        assert_eq!(
+            validate_name("bob\u{202e}"),
+            Err(NameError::InvalidCharacter('\u{202e}'))
+        );
+    }
+
+    #[test]
+    fn names_survive_the_user_data() {
+        let user_data = name_to_user_data("alice");
+        assert_eq!(name_from_user_data(&user_data), Ok("alice".to_string()));
+
+        // Names too long for the user data are cut on a character boundary
+        let long = "é".repeat(NETCODE_USER_DATA_BYTES);
+        let user_data = name_to_user_data(&long);
+        let len = u64::from_le_bytes(user_data[0..8].try_into().unwrap()) as usize;
+        assert!(std::str::from_utf8(&user_data[8..8 + len]).is_ok());
+    }
+
+    #[test]
+    fn malformed_user_data_is_refused() {
+        assert_eq!(name_from_user_data(&[0; 4]), Err(NameError::Malformed));
+
+        let mut user_data = name_to_user_data("alice");
+        user_data[0..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(name_from_user_data(&user_data), Err(NameError::Malformed));
+
+        let mut user_data = name_to_user_data("ab");
+        user_data[8] = 0xff;
+        assert_eq!(name_from_user_data(&user_data), Err(NameError::NotUtf8));
+    }
+
+    #[test]
+    fn taken_names_get_a_number() {
+        assert_eq!(unique_name("alice", ["bob"]), "alice");
+        assert_eq!(unique_name("alice", ["ALICE"]), "alice (2)");
+        assert_eq!(unique_name("alice", ["alice", "alice (2)"]), "alice (3)");
+
+        let long = "a".repeat(MAX_NAME_LEN);
+        let unique = unique_name(&long, [long.as_str()]);
+        assert_eq!(unique.chars().count(), MAX_NAME_LEN);
+        assert!(unique.ends_with(" (2)"));
+    }
+}
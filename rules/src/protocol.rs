@@ -0,0 +1,827 @@
+use bincode::Options;
+use renet::{ChannelConfig, ReliableChannelConfig, RenetConnectionConfig};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt;
+
+use crate::hex::CubeCoords;
+use crate::names::{NameError, MAX_NAME_LEN};
+use crate::ships::{get_max_grid_rotation, GameObject};
+use crate::{EndGameReason, GameEvent, GameStage, GameState, Player, PlayerId};
+
+/// Only clients that can provide the same PROTOCOL_ID that the server is using will be able to connect.
+/// Renet silently drops clients with a different id, so this only identifies the game;
+/// compatibility between releases is checked with [`PROTOCOL_VERSION`] during the handshake.
+pub const PROTOCOL_ID: u64 = 1208;
+
+/// Bumped whenever [`ClientMessage`] or [`ServerMessage`] change in an incompatible way.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Reliable channel carrying every [`ClientMessage`] and [`ServerMessage`].
+pub const GAME_CHANNEL: u8 = 0;
+
+/// Reliable channel carrying [`ChatMessage`]s and [`ChatLine`]s, so chat never delays game events.
+pub const CHAT_CHANNEL: u8 = 1;
+
+/// Longest chat message the server relays, in characters. Longer ones are cut.
+pub const MAX_CHAT_LEN: usize = 200;
+
+/// Largest message either side accepts, in bytes. Matches the largest message renet delivers.
+pub const MAX_MESSAGE_SIZE: u64 = 64 * 1024;
+
+/// Longest shutdown reason, in characters.
+pub const MAX_REASON_LEN: usize = 200;
+
+/// Length bounds of a [`PlayerToken`], in characters.
+pub const MIN_TOKEN_LEN: usize = 16;
+pub const MAX_TOKEN_LEN: usize = 64;
+
+/// Most entries a [`ServerMessage::Leaderboard`] holds.
+pub const LEADERBOARD_LEN: usize = 20;
+
+/// Most placements listed as a [`PlayerProfile`]'s favourites.
+pub const MAX_FAVOURITE_PLACEMENTS: usize = 3;
+
+/// Most games a [`ServerMessage::OpenGames`] lists.
+pub const MAX_OPEN_GAMES: usize = 50;
+
+/// Channel layout shared by the client and the server.
+pub fn connection_config() -> RenetConnectionConfig {
+    let channels = vec![
+        ChannelConfig::Reliable(ReliableChannelConfig {
+            channel_id: GAME_CHANNEL,
+            ..Default::default()
+        }),
+        ChannelConfig::Reliable(ReliableChannelConfig {
+            channel_id: CHAT_CHANNEL,
+            ..Default::default()
+        }),
+    ];
+    RenetConnectionConfig {
+        send_channels_config: channels.clone(),
+        receive_channels_config: channels,
+        ..Default::default()
+    }
+}
+
+/// Every message on the wire is wrapped in an envelope. The version always comes first,
+/// so it can be read even when the rest of the message does not decode.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub version: u32,
+    pub message: T,
+}
+
+/// Identifies a room, i.e. a single match hosted by the server.
+pub type RoomId = u64;
+
+/// Identifies a command within a connection, see [`ClientMessage::Command`].
+pub type CommandId = u64;
+
+/// Position of an event in the history of a room.
+pub type EventSeq = u64;
+
+/// Secret a server issues to a client in a [`ServerMessage::TokenIssued`], which the client
+/// presents on every later connection so the server can keep its rating between sessions.
+/// Made of ASCII letters and digits, see [`is_valid_token`]. Nobody can make up the token of
+/// another player, but renet runs unsecured, so it travels in plain text.
+pub type PlayerToken = String;
+
+pub fn is_valid_token(token: &str) -> bool {
+    (MIN_TOKEN_LEN..=MAX_TOKEN_LEN).contains(&token.len())
+        && token.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// How a client takes part in a room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Role {
+    Player,
+    /// Watches the match without being able to send commands.
+    Spectator,
+    /// Plays correspondence games, which last as long as their players keep moving in time.
+    /// Disconnecting keeps the seat, reconnecting with the same token gets it back.
+    Correspondence,
+}
+
+/// Messages sent from a client to the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClientMessage {
+    /// First message of every connection. The server ignores anything else until it arrives.
+    /// Clients without a `token`, or with one the server did not issue, get a new one.
+    Hello {
+        role: Role,
+        token: Option<PlayerToken>,
+    },
+    /// A GameEvent the client would like the server to apply. Ids are chosen by the client and
+    /// must increase with every command, the server ignores ids it has already seen.
+    Command { id: CommandId, event: GameEvent },
+    /// Asks for a [`ServerMessage::Snapshot`], e.g. after the client lost track of the game.
+    RequestSnapshot,
+    /// `sent_at` is echoed back in a [`ServerMessage::Pong`].
+    Ping { sent_at: u64 },
+    /// Once the game is over, asks to play again against the same opponent. The room starts
+    /// over when both players asked.
+    Rematch,
+    /// Once the game is over, leaves the room for another one with the same role.
+    LeaveRoom,
+    /// Asks for a [`ServerMessage::Leaderboard`].
+    RequestLeaderboard,
+    /// Asks for a [`ServerMessage::Profile`] of the client's own career.
+    RequestProfile,
+    /// Asks for a [`ServerMessage::OpenGames`] listing the client's correspondence games.
+    ListGames,
+    /// Moves a correspondence player to another of its open games.
+    SwitchGame { room: RoomId },
+}
+
+/// Messages sent from the server to a client.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ServerMessage {
+    /// Handshake accepted, the client is now part of a room.
+    Welcome {
+        player_id: PlayerId,
+        room: RoomId,
+        role: Role,
+    },
+    /// Handshake refused. The server disconnects the client right after.
+    HelloRejected(HandshakeError),
+    /// The token to present in every later [`ClientMessage::Hello`] to this server.
+    TokenIssued {
+        token: PlayerToken,
+    },
+    /// A validated event every client should consume, along with the
+    /// [`GameState::checksum`] the client should end up with after consuming it.
+    /// `seq` numbers the events of a room, clients drop events they have already seen.
+    Event {
+        seq: EventSeq,
+        event: GameEvent,
+        checksum: u64,
+    },
+    /// The command with this id was applied. Its event follows as a [`ServerMessage::Event`].
+    Accepted {
+        id: CommandId,
+    },
+    /// The command with this id was not applied.
+    Rejected {
+        id: CommandId,
+        reason: RejectReason,
+    },
+    /// The full GameState as this client may see it, replacing whatever the client had.
+    /// Sent on join and on request. `seq` is the number of the last event it contains.
+    Snapshot {
+        seq: EventSeq,
+        state: GameState,
+    },
+    Pong {
+        sent_at: u64,
+    },
+    /// The server is about to disconnect this client for misbehaving.
+    Kicked(KickReason),
+    /// A player of the room asked for a [`ClientMessage::Rematch`].
+    RematchRequested {
+        by: PlayerId,
+    },
+    /// The server is going away, `reason` is meant to be shown to the player.
+    Shutdown {
+        reason: String,
+    },
+    /// The best rated players, and where the client stands if it is rated.
+    Leaderboard {
+        top: Vec<LeaderboardEntry>,
+        own: Option<LeaderboardEntry>,
+    },
+    /// The client's career, `None` until it finished a game with a token.
+    Profile(Option<PlayerProfile>),
+    /// The server is going away for a moment and keeps the game in progress.
+    /// Reconnecting with the same token puts the player back in their seat.
+    Restarting,
+    /// The client's correspondence games that are not over yet, most urgent first.
+    OpenGames(Vec<OpenGame>),
+}
+
+/// A correspondence game as listed to one of its players.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpenGame {
+    pub room: RoomId,
+    /// `None` while the game waits for an opponent.
+    pub opponent: Option<String>,
+    pub stage: GameStage,
+    /// Whether the game waits for this player.
+    pub your_turn: bool,
+    /// Unix time in seconds at which whoever has to move runs out of time.
+    pub deadline: Option<u64>,
+}
+
+/// A rated player's standing on the ladder.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    /// 1 for the best rated player.
+    pub rank: u32,
+    pub name: String,
+    pub rating: i32,
+    pub games: u32,
+    pub wins: u32,
+}
+
+/// Career statistics of a player, over every game that began.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerProfile {
+    pub name: String,
+    pub games: u32,
+    pub wins: u32,
+    pub losses: u32,
+    pub shots: u32,
+    /// Shots that landed on a hex covered by an enemy ship.
+    pub hits: u32,
+    /// Turns the player took in the games they won, on average.
+    pub average_turns_to_win: Option<f32>,
+    /// Most used placements first, at most [`MAX_FAVOURITE_PLACEMENTS`].
+    pub favourite_placements: Vec<ShipPlacement>,
+}
+
+impl PlayerProfile {
+    /// Share of the shots that hit, between 0 and 1.
+    pub fn accuracy(&self) -> Option<f32> {
+        match self.shots {
+            0 => None,
+            shots => Some(self.hits as f32 / shots as f32),
+        }
+    }
+}
+
+/// Where a player put a ship, and how many times they did.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShipPlacement {
+    pub ship: GameObject,
+    pub at: CubeCoords,
+    pub rotation: i32,
+    pub times: u32,
+}
+
+/// Chat message sent by a client on [`CHAT_CHANNEL`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub text: String,
+}
+
+/// Chat message relayed by the server to everyone in the sender's room.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatLine {
+    pub from: PlayerId,
+    pub name: String,
+    pub text: String,
+}
+
+/// Why the server refused a handshake.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum HandshakeError {
+    ClientTooOld { client: u32, server: u32 },
+    ServerTooOld { client: u32, server: u32 },
+    InvalidName(NameError),
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::ClientTooOld { client, server } => write!(
+                f,
+                "Your client is too old (protocol v{}, server needs v{}). Please update the game.",
+                client, server
+            ),
+            HandshakeError::ServerTooOld { client, server } => write!(
+                f,
+                "The server is too old (protocol v{}, client uses v{}).",
+                server, client
+            ),
+            HandshakeError::InvalidName(err) => err.fmt(f),
+        }
+    }
+}
+
+/// Why the server kicked a client.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum KickReason {
+    /// Sent more messages than the server's rate limit allows.
+    Flooding,
+    /// Sent too many commands the server could not accept.
+    TooManyStrikes,
+    /// Kicked by the server's operator.
+    ByAdmin,
+    /// Banned by the server's operator, also sent to banned clients trying to connect.
+    Banned,
+}
+
+impl fmt::Display for KickReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KickReason::Flooding => {
+                write!(f, "Kicked by the server for sending too many messages.")
+            }
+            KickReason::TooManyStrikes => {
+                write!(
+                    f,
+                    "Kicked by the server for sending too many invalid moves."
+                )
+            }
+            KickReason::ByAdmin => write!(f, "Kicked by the server's operator."),
+            KickReason::Banned => write!(f, "You are banned from this server."),
+        }
+    }
+}
+
+/// Why a [`ClientMessage::Command`] was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RejectReason {
+    /// The handshake has not completed yet.
+    NotJoined,
+    /// The event is not valid for the current GameState.
+    InvalidEvent,
+    /// Spectators cannot send gameplay commands.
+    Spectator,
+    /// The command id was used before. A replayed command is never applied twice.
+    Duplicate,
+    /// The server is restarting and takes no more moves until it is back.
+    Restarting,
+}
+
+/// Errors returned by [`decode`].
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The sender speaks another version of the protocol.
+    VersionMismatch {
+        ours: u32,
+        theirs: u32,
+    },
+    TooLarge {
+        size: usize,
+    },
+    Malformed(bincode::Error),
+    /// The message decoded but holds values no well-behaved peer sends, see [`SanityCheck`].
+    Invalid(&'static str),
+}
+
+impl DecodeError {
+    /// The handshake error the server should answer with, if this is a version mismatch.
+    pub fn handshake_error(&self) -> Option<HandshakeError> {
+        match *self {
+            DecodeError::VersionMismatch { ours, theirs } if theirs < ours => {
+                Some(HandshakeError::ClientTooOld {
+                    client: theirs,
+                    server: ours,
+                })
+            }
+            DecodeError::VersionMismatch { ours, theirs } => Some(HandshakeError::ServerTooOld {
+                client: theirs,
+                server: ours,
+            }),
+            DecodeError::TooLarge { .. } | DecodeError::Malformed(_) | DecodeError::Invalid(_) => {
+                None
+            }
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::VersionMismatch { ours, theirs } => write!(
+                f,
+                "protocol version mismatch (ours v{}, theirs v{})",
+                ours, theirs
+            ),
+            DecodeError::TooLarge { size } => write!(
+                f,
+                "message of {} bytes exceeds the limit of {} bytes",
+                size, MAX_MESSAGE_SIZE
+            ),
+            DecodeError::Malformed(err) => write!(f, "malformed message: {}", err),
+            DecodeError::Invalid(what) => write!(f, "invalid message: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Wraps a message in an [`Envelope`] with our [`PROTOCOL_VERSION`] and serializes it.
+pub fn encode<T: Serialize>(message: &T) -> Vec<u8> {
+    bincode::serialize(&Envelope {
+        version: PROTOCOL_VERSION,
+        message,
+    })
+    .unwrap()
+}
+
+/// Deserializes an [`Envelope`], checking its version against ours and the message against
+/// [`SanityCheck`]. Never panics, whatever the bytes.
+pub fn decode<T: DeserializeOwned + SanityCheck>(bytes: &[u8]) -> Result<T, DecodeError> {
+    if bytes.len() as u64 > MAX_MESSAGE_SIZE {
+        return Err(DecodeError::TooLarge { size: bytes.len() });
+    }
+    // Read the version on its own first, the message layout may differ between versions
+    let theirs: u32 = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .deserialize(bytes)
+        .map_err(DecodeError::Malformed)?;
+    if theirs != PROTOCOL_VERSION {
+        return Err(DecodeError::VersionMismatch {
+            ours: PROTOCOL_VERSION,
+            theirs,
+        });
+    }
+    let envelope: Envelope<T> = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit(MAX_MESSAGE_SIZE)
+        .reject_trailing_bytes()
+        .deserialize(bytes)
+        .map_err(DecodeError::Malformed)?;
+    envelope
+        .message
+        .sanity_check()
+        .map_err(DecodeError::Invalid)?;
+    Ok(envelope.message)
+}
+
+/// Checks on decoded values that the type system can't express, such as coordinates being on
+/// the board and strings being of reasonable length. Errors describe what is wrong.
+pub trait SanityCheck {
+    fn sanity_check(&self) -> Result<(), &'static str>;
+}
+
+impl SanityCheck for ClientMessage {
+    fn sanity_check(&self) -> Result<(), &'static str> {
+        match self {
+            ClientMessage::Command { event, .. } => event.sanity_check(),
+            ClientMessage::Hello {
+                token: Some(token), ..
+            } if token.len() > MAX_TOKEN_LEN => Err("player token too long"),
+            ClientMessage::Hello { .. }
+            | ClientMessage::RequestLeaderboard
+            | ClientMessage::RequestProfile
+            | ClientMessage::ListGames
+            | ClientMessage::SwitchGame { .. }
+            | ClientMessage::RequestSnapshot
+            | ClientMessage::Ping { .. }
+            | ClientMessage::Rematch
+            | ClientMessage::LeaveRoom => Ok(()),
+        }
+    }
+}
+
+impl SanityCheck for ServerMessage {
+    fn sanity_check(&self) -> Result<(), &'static str> {
+        match self {
+            ServerMessage::Event { event, .. } => event.sanity_check(),
+            ServerMessage::Snapshot { state, .. } => state.sanity_check(),
+            ServerMessage::Shutdown { reason } if reason.chars().count() > MAX_REASON_LEN => {
+                Err("shutdown reason too long")
+            }
+            ServerMessage::TokenIssued { token } if !is_valid_token(token) => {
+                Err("malformed player token")
+            }
+            ServerMessage::Leaderboard { top, own } => {
+                if top.len() > LEADERBOARD_LEN {
+                    return Err("leaderboard too long");
+                }
+                if top
+                    .iter()
+                    .chain(own)
+                    .any(|entry| entry.name.chars().count() > MAX_NAME_LEN)
+                {
+                    return Err("name too long");
+                }
+                Ok(())
+            }
+            ServerMessage::Profile(Some(profile)) => profile.sanity_check(),
+            ServerMessage::OpenGames(games) => {
+                if games.len() > MAX_OPEN_GAMES {
+                    return Err("too many open games");
+                }
+                if games
+                    .iter()
+                    .filter_map(|game| game.opponent.as_ref())
+                    .any(|name| name.chars().count() > MAX_NAME_LEN)
+                {
+                    return Err("name too long");
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl SanityCheck for PlayerProfile {
+    fn sanity_check(&self) -> Result<(), &'static str> {
+        if self.name.chars().count() > MAX_NAME_LEN {
+            return Err("name too long");
+        }
+        if self.favourite_placements.len() > MAX_FAVOURITE_PLACEMENTS {
+            return Err("too many favourite placements");
+        }
+        if self
+            .favourite_placements
+            .iter()
+            .any(|placement| !placement.at.is_on_map())
+        {
+            return Err("favourite placement off the board");
+        }
+        Ok(())
+    }
+}
+
+impl SanityCheck for ChatMessage {
+    fn sanity_check(&self) -> Result<(), &'static str> {
+        // Longer messages are cut by the server, but not arbitrarily long ones
+        if self.text.len() > MAX_CHAT_LEN * 4 {
+            return Err("chat message too long");
+        }
+        Ok(())
+    }
+}
+
+impl SanityCheck for ChatLine {
+    fn sanity_check(&self) -> Result<(), &'static str> {
+        if self.name.chars().count() > MAX_NAME_LEN {
+            return Err("name too long");
+        }
+        if self.text.chars().count() > MAX_CHAT_LEN {
+            return Err("chat message too long");
+        }
+        Ok(())
+    }
+}
+
+impl SanityCheck for Player {
+    fn sanity_check(&self) -> Result<(), &'static str> {
+        if self.name.chars().count() > MAX_NAME_LEN {
+            return Err("name too long");
+        }
+        Ok(())
+    }
+}
+
+impl SanityCheck for GameEvent {
+    fn sanity_check(&self) -> Result<(), &'static str> {
+        match self {
+            GameEvent::PlayerJoined { player_details, .. } => player_details.sanity_check(),
+            GameEvent::EndGame {
+                reason: EndGameReason::Aborted { reason },
+            } if reason.chars().count() > MAX_REASON_LEN => Err("end game reason too long"),
+            GameEvent::ShipMove { at, .. } if !at.is_on_map() => Err("move off the board"),
+            GameEvent::ShipPlaced {
+                ship_type,
+                at,
+                rotation,
+                ..
+            } => {
+                if !at.is_on_map() {
+                    return Err("ship placed off the board");
+                }
+                let max = get_max_grid_rotation(ship_type);
+                if !(-max..max).contains(rotation) {
+                    return Err("ship rotation out of range");
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl SanityCheck for GameState {
+    fn sanity_check(&self) -> Result<(), &'static str> {
+        if self.players.len() > 2 {
+            return Err("too many players");
+        }
+        for player in self.players.values() {
+            player.sanity_check()?;
+        }
+        for (ship_type, at, rotation) in self.player_ships.values().flatten() {
+            GameEvent::ShipPlaced {
+                player_id: 0,
+                ship_type: *ship_type,
+                at: *at,
+                rotation: *rotation,
+            }
+            .sanity_check()?;
+        }
+        for event in &self.history {
+            event.sanity_check()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hex::{CubeCoords, MAP_RADIUS};
+    use crate::ships::GameObject;
+
+    fn placed(at: CubeCoords, rotation: i32) -> GameEvent {
+        GameEvent::ShipPlaced {
+            player_id: 1,
+            ship_type: GameObject::Ship,
+            at,
+            rotation,
+        }
+    }
+
+    fn player(name_len: usize) -> Player {
+        Player {
+            name: "a".repeat(name_len),
+        }
+    }
+
+    const OFF_BOARD: CubeCoords = CubeCoords {
+        q: MAP_RADIUS + 1,
+        r: -MAP_RADIUS - 1,
+        s: 0,
+    };
+
+    #[test]
+    fn messages_round_trip() {
+        let message = ClientMessage::Command {
+            id: 3,
+            event: placed(CubeCoords::ZERO, 0),
+        };
+        let decoded: ClientMessage = decode(&encode(&message)).unwrap();
+        assert_eq!(decoded, message);
+    }
+
+    #[test]
+    fn oversized_messages_are_refused_before_decoding() {
+        let bytes = vec![0; MAX_MESSAGE_SIZE as usize + 1];
+        assert!(matches!(
+            decode::<ClientMessage>(&bytes),
+            Err(DecodeError::TooLarge { .. })
+        ));
+
+        // A length prefix may not make the decoder allocate beyond the limit either
+        let mut bytes = encode(&ChatMessage {
+            text: String::new(),
+        });
+        let len = bytes.len();
+        bytes[len - 8..].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            decode::<ChatMessage>(&bytes),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn trailing_bytes_are_refused() {
+        let mut bytes = encode(&ClientMessage::RequestSnapshot);
+        bytes.push(0);
+        assert!(matches!(
+            decode::<ClientMessage>(&bytes),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn other_versions_are_told_apart() {
+        let envelope = |version| {
+            bincode::serialize(&Envelope {
+                version,
+                message: ClientMessage::RequestSnapshot,
+            })
+            .unwrap()
+        };
+        let older = decode::<ClientMessage>(&envelope(PROTOCOL_VERSION - 1)).unwrap_err();
+        assert!(matches!(
+            older.handshake_error(),
+            Some(HandshakeError::ClientTooOld { .. })
+        ));
+        let newer = decode::<ClientMessage>(&envelope(PROTOCOL_VERSION + 1)).unwrap_err();
+        assert!(matches!(
+            newer.handshake_error(),
+            Some(HandshakeError::ServerTooOld { .. })
+        ));
+        // Not even the version can be read from a truncated message
+        assert!(matches!(
+            decode::<ClientMessage>(&[1, 0]),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn events_stay_on_the_board() {
+        assert!(placed(CubeCoords::ZERO, 11).sanity_check().is_ok());
+        assert!(placed(CubeCoords::ZERO, -12).sanity_check().is_ok());
+        assert!(placed(CubeCoords::ZERO, 12).sanity_check().is_err());
+        assert!(placed(CubeCoords::ZERO, i32::MIN).sanity_check().is_err());
+        assert!(placed(OFF_BOARD, 0).sanity_check().is_err());
+        // Coordinates must add up to zero
+        let skewed = CubeCoords { q: 1, r: 1, s: 1 };
+        assert!(placed(skewed, 0).sanity_check().is_err());
+        let shot = |at| GameEvent::ShipMove { player_id: 1, at };
+        assert!(shot(CubeCoords::ZERO).sanity_check().is_ok());
+        assert!(shot(OFF_BOARD).sanity_check().is_err());
+        let joined = |player_details| GameEvent::PlayerJoined {
+            player_id: 1,
+            player_details,
+        };
+        assert!(joined(player(MAX_NAME_LEN)).sanity_check().is_ok());
+        assert!(joined(player(MAX_NAME_LEN + 1)).sanity_check().is_err());
+    }
+
+    #[test]
+    fn chat_is_of_reasonable_length() {
+        let chat = |len| ChatMessage {
+            text: "a".repeat(len),
+        };
+        assert!(chat(MAX_CHAT_LEN * 4).sanity_check().is_ok());
+        assert!(chat(MAX_CHAT_LEN * 4 + 1).sanity_check().is_err());
+
+        let line = |name_len, text_len| ChatLine {
+            from: 1,
+            name: "a".repeat(name_len),
+            text: "a".repeat(text_len),
+        };
+        assert!(line(MAX_NAME_LEN, MAX_CHAT_LEN).sanity_check().is_ok());
+        assert!(line(MAX_NAME_LEN + 1, 0).sanity_check().is_err());
+        assert!(line(0, MAX_CHAT_LEN + 1).sanity_check().is_err());
+    }
+
+    #[test]
+    fn messages_check_what_they_carry() {
+        let command = |event| ClientMessage::Command { id: 1, event };
+        assert!(command(placed(CubeCoords::ZERO, 0)).sanity_check().is_ok());
+        assert!(command(placed(OFF_BOARD, 0)).sanity_check().is_err());
+        assert!(ClientMessage::RequestSnapshot.sanity_check().is_ok());
+
+        let event = |event| ServerMessage::Event {
+            seq: 1,
+            event,
+            checksum: 0,
+        };
+        assert!(event(placed(CubeCoords::ZERO, 0)).sanity_check().is_ok());
+        assert!(event(placed(OFF_BOARD, 0)).sanity_check().is_err());
+        let shutdown = |len| ServerMessage::Shutdown {
+            reason: "a".repeat(len),
+        };
+        assert!(shutdown(MAX_REASON_LEN).sanity_check().is_ok());
+        assert!(shutdown(MAX_REASON_LEN + 1).sanity_check().is_err());
+
+        let hello = |token: &str| ClientMessage::Hello {
+            role: Role::Player,
+            token: Some(token.to_string()),
+        };
+        assert!(hello(&"a".repeat(MAX_TOKEN_LEN)).sanity_check().is_ok());
+        assert!(hello(&"a".repeat(MAX_TOKEN_LEN + 1))
+            .sanity_check()
+            .is_err());
+        let issued = |token: &str| ServerMessage::TokenIssued {
+            token: token.to_string(),
+        };
+        assert!(issued(&"a".repeat(MIN_TOKEN_LEN)).sanity_check().is_ok());
+        assert!(issued("short").sanity_check().is_err());
+        assert!(issued(&"ä".repeat(MIN_TOKEN_LEN)).sanity_check().is_err());
+
+        let entry = |name: String| LeaderboardEntry {
+            rank: 1,
+            name,
+            rating: 1200,
+            games: 0,
+            wins: 0,
+        };
+        let leaderboard = |len, name_len| ServerMessage::Leaderboard {
+            top: vec![entry("a".repeat(MAX_NAME_LEN)); len],
+            own: Some(entry("a".repeat(name_len))),
+        };
+        assert!(leaderboard(LEADERBOARD_LEN, MAX_NAME_LEN)
+            .sanity_check()
+            .is_ok());
+        assert!(leaderboard(LEADERBOARD_LEN + 1, MAX_NAME_LEN)
+            .sanity_check()
+            .is_err());
+        assert!(leaderboard(1, MAX_NAME_LEN + 1).sanity_check().is_err());
+    }
+
+    #[test]
+    fn snapshots_check_the_whole_game() {
+        let snapshot = |state| ServerMessage::Snapshot { seq: 0, state };
+        assert!(snapshot(GameState::default()).sanity_check().is_ok());
+
+        let mut crowded = GameState::default();
+        for player_id in 0..3 {
+            crowded.players.insert(player_id, player(1));
+        }
+        assert!(snapshot(crowded).sanity_check().is_err());
+
+        let mut long_name = GameState::default();
+        long_name.players.insert(1, player(MAX_NAME_LEN + 1));
+        assert!(snapshot(long_name).sanity_check().is_err());
+
+        let mut off_board = GameState::default();
+        off_board
+            .player_ships
+            .insert(1, vec![(GameObject::Boat, OFF_BOARD, 0)]);
+        assert!(snapshot(off_board).sanity_check().is_err());
+
+        let mut bad_history = GameState::default();
+        bad_history.history.push(placed(OFF_BOARD, 0));
+        assert!(snapshot(bad_history).sanity_check().is_err());
+    }
+}
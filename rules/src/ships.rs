@@ -0,0 +1,117 @@
+//! Ship types and the hexes they cover.
+
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::hex::{
+    world_pos_to_coordinates, CubeCoords, Hexagon, HEX_CONFIG_PADDING, HEX_CONFIG_SIZE,
+};
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "bevy", derive(bevy_ecs::component::Component))]
+pub enum GameObject {
+    Boat,
+    Ship,
+    Cruizer,
+}
+
+/// The fleet every player places, in the order it leaves the garage (from the back).
+pub const SHIPS: [GameObject; 4] = [
+    GameObject::Cruizer,
+    GameObject::Ship,
+    GameObject::Boat,
+    GameObject::Boat,
+];
+
+pub fn get_max_grid_rotation(game_object: &GameObject) -> i32 {
+    match game_object {
+        GameObject::Boat => 6,
+        GameObject::Ship => 6 * 2,
+        GameObject::Cruizer => 6 * 3,
+    }
+}
+pub fn get_object_all_coords(
+    game_object: &GameObject,
+    rotation: i32,
+    at: &CubeCoords,
+) -> Vec<CubeCoords> {
+    match game_object {
+        GameObject::Boat => {
+            let end_cube = hex_end_rotate(rotation, 2);
+            line_coords(*at, end_cube + *at)
+        }
+        GameObject::Ship => {
+            let end_cube = hex_end_rotate(rotation, 3);
+            line_coords(*at, end_cube + *at)
+        }
+        GameObject::Cruizer => {
+            let end_cube = hex_end_rotate(rotation, 4);
+            line_coords(*at, end_cube + *at)
+        }
+    }
+}
+
+fn hex_end_rotate(rotation: i32, object_len: u32) -> CubeCoords {
+    let max_valid_rotations = 6 * (object_len - 1);
+    let mut u_rotation = rotation;
+    if u_rotation < 0 {
+        u_rotation += max_valid_rotations as i32;
+    }
+    let coord_vector = build_coordinate_vector(object_len);
+    let q = coord_vector[u_rotation as usize];
+    let r =
+        coord_vector[((u_rotation as u32 + 2 * (object_len - 1)) % max_valid_rotations) as usize];
+    let s = -r - q;
+    CubeCoords { q, r, s }
+}
+
+pub fn build_coordinate_vector(object_len: u32) -> Vec<i32> {
+    let displacement = object_len - 1;
+    let vector_len = 6 * displacement;
+    let mut first_counter: i32 = 0;
+    let mut direction = 1;
+    let mut coord_pos = Vec::with_capacity(vector_len as usize);
+    while coord_pos.len() < vector_len as usize {
+        if first_counter.abs() < displacement as i32 {
+            coord_pos.push(first_counter);
+            first_counter += direction;
+        }
+        if first_counter.abs() >= displacement as i32 {
+            for _ in 0..object_len {
+                coord_pos.push((object_len - 1) as i32 * direction);
+            }
+            direction *= -1;
+            first_counter += direction;
+        }
+    }
+    coord_pos
+}
+
+pub fn line_coords(coord_origin: CubeCoords, coord_end: CubeCoords) -> Vec<CubeCoords> {
+    let hex_origin = Hexagon::new(HEX_CONFIG_SIZE, HEX_CONFIG_PADDING, Some(coord_origin), 0.0);
+    let hex_end = Hexagon::new(HEX_CONFIG_SIZE, HEX_CONFIG_PADDING, Some(coord_end), 0.0);
+    let distance = (coord_end - coord_origin).magnitude();
+
+    let points: Vec<Vec3> = (0..=distance)
+        .map(|i| {
+            lerp(
+                hex_origin.world_pos(),
+                hex_end.world_pos(),
+                i as f32 / (distance) as f32,
+            )
+        })
+        .collect();
+    points
+        .into_iter()
+        .map(|pos| {
+            world_pos_to_coordinates(
+                HEX_CONFIG_SIZE + HEX_CONFIG_PADDING,
+                Vec2::new(pos.x, pos.y),
+            )
+        })
+        .collect()
+}
+
+fn lerp(start: Vec3, end: Vec3, distance: f32) -> Vec3 {
+    start + (end - start) * distance
+}
@@ -0,0 +1,238 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::{ClientTransport, NetworkStats, ServerTransport, ServerTransportEvent, TransportError};
+
+/// Messages in flight in one direction, per channel.
+type Queues = Arc<Mutex<HashMap<u8, VecDeque<Vec<u8>>>>>;
+
+/// Connections opened by clients that the server did not take in yet, with their user data.
+type Incoming = Arc<Mutex<Vec<(u64, Vec<u8>, Link)>>>;
+
+/// Both directions of one in-memory connection.
+#[derive(Clone, Default)]
+struct Link {
+    to_server: Queues,
+    to_client: Queues,
+    open: Arc<AtomicBool>,
+}
+
+fn push(queues: &Queues, channel: u8, message: Vec<u8>) {
+    let mut queues = queues.lock().unwrap();
+    queues.entry(channel).or_default().push_back(message);
+}
+
+fn pop(queues: &Queues, channel: u8) -> Option<Vec<u8>> {
+    let mut queues = queues.lock().unwrap();
+    queues.get_mut(&channel).and_then(|queue| queue.pop_front())
+}
+
+/// Server end of the in-memory transport. Clients connect through a [`MemoryConnector`].
+/// Messages are delivered reliably and in order, like on the reliable renet channels.
+#[derive(Default)]
+pub struct MemoryServer {
+    incoming: Incoming,
+    clients: HashMap<u64, Link>,
+    events: VecDeque<ServerTransportEvent>,
+}
+
+impl MemoryServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A handle that opens connections to this server, usable after the server was moved away.
+    pub fn connector(&self) -> MemoryConnector {
+        MemoryConnector {
+            incoming: self.incoming.clone(),
+        }
+    }
+}
+
+impl ServerTransport for MemoryServer {
+    fn update(&mut self, _delta: Duration) -> Result<(), TransportError> {
+        for (client_id, user_data, link) in self.incoming.lock().unwrap().drain(..) {
+            self.clients.insert(client_id, link);
+            self.events
+                .push_back(ServerTransportEvent::ClientConnected {
+                    client_id,
+                    user_data,
+                });
+        }
+
+        let closed: Vec<u64> = self
+            .clients
+            .iter()
+            .filter(|(_, link)| !link.open.load(Ordering::SeqCst))
+            .map(|(client_id, _)| *client_id)
+            .collect();
+        for client_id in closed {
+            self.clients.remove(&client_id);
+            self.events
+                .push_back(ServerTransportEvent::ClientDisconnected { client_id });
+        }
+        Ok(())
+    }
+
+    fn get_event(&mut self) -> Option<ServerTransportEvent> {
+        self.events.pop_front()
+    }
+
+    fn clients_id(&self) -> Vec<u64> {
+        self.clients.keys().copied().collect()
+    }
+
+    fn client_addr(&self, _client_id: u64) -> Option<SocketAddr> {
+        None
+    }
+
+    fn network_stats(&self, _client_id: u64) -> Option<NetworkStats> {
+        None
+    }
+
+    fn send_message(&mut self, client_id: u64, channel: u8, message: Vec<u8>) {
+        if let Some(link) = self.clients.get(&client_id) {
+            push(&link.to_client, channel, message);
+        }
+    }
+
+    fn receive_message(&mut self, client_id: u64, channel: u8) -> Option<Vec<u8>> {
+        self.clients
+            .get(&client_id)
+            .and_then(|link| pop(&link.to_server, channel))
+    }
+
+    fn send_packets(&mut self) -> Result<(), TransportError> {
+        Ok(())
+    }
+
+    fn disconnect(&mut self, client_id: u64) {
+        if let Some(link) = self.clients.get(&client_id) {
+            link.open.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Opens connections to a [`MemoryServer`].
+#[derive(Clone)]
+pub struct MemoryConnector {
+    incoming: Incoming,
+}
+
+impl MemoryConnector {
+    /// Connects a new client. The server sees it on its next update.
+    pub fn connect(&self, client_id: u64, user_data: Vec<u8>) -> MemoryClient {
+        let link = Link::default();
+        link.open.store(true, Ordering::SeqCst);
+        self.incoming
+            .lock()
+            .unwrap()
+            .push((client_id, user_data, link.clone()));
+        MemoryClient { client_id, link }
+    }
+}
+
+/// Client end of the in-memory transport.
+pub struct MemoryClient {
+    client_id: u64,
+    link: Link,
+}
+
+impl ClientTransport for MemoryClient {
+    fn client_id(&self) -> u64 {
+        self.client_id
+    }
+
+    fn update(&mut self, _delta: Duration) -> Result<(), TransportError> {
+        Ok(())
+    }
+
+    fn send_message(&mut self, channel: u8, message: Vec<u8>) {
+        if self.is_connected() {
+            push(&self.link.to_server, channel, message);
+        }
+    }
+
+    fn receive_message(&mut self, channel: u8) -> Option<Vec<u8>> {
+        pop(&self.link.to_client, channel)
+    }
+
+    fn send_packets(&mut self) -> Result<(), TransportError> {
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        self.link.open.load(Ordering::SeqCst)
+    }
+
+    fn disconnected(&self) -> Option<String> {
+        match self.is_connected() {
+            true => None,
+            false => Some("disconnected".to_string()),
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.link.open.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delivers_in_order_per_channel() {
+        let mut server = MemoryServer::new();
+        let mut client = server.connector().connect(7, b"name".to_vec());
+        server.update(Duration::ZERO).unwrap();
+        assert_eq!(
+            server.get_event(),
+            Some(ServerTransportEvent::ClientConnected {
+                client_id: 7,
+                user_data: b"name".to_vec(),
+            })
+        );
+
+        client.send_message(0, vec![1]);
+        client.send_message(1, vec![2]);
+        client.send_message(0, vec![3]);
+        assert_eq!(server.receive_message(7, 0), Some(vec![1]));
+        assert_eq!(server.receive_message(7, 0), Some(vec![3]));
+        assert_eq!(server.receive_message(7, 1), Some(vec![2]));
+        assert_eq!(server.receive_message(7, 0), None);
+
+        server.send_message(7, 0, vec![4]);
+        assert_eq!(client.receive_message(0), Some(vec![4]));
+    }
+
+    #[test]
+    fn disconnects_both_ways() {
+        let mut server = MemoryServer::new();
+        let connector = server.connector();
+        let mut leaving = connector.connect(1, Vec::new());
+        let kicked = connector.connect(2, Vec::new());
+        server.update(Duration::ZERO).unwrap();
+        while server.get_event().is_some() {}
+
+        leaving.disconnect();
+        server.disconnect(2);
+        assert!(!kicked.is_connected());
+        server.update(Duration::ZERO).unwrap();
+        let mut gone = Vec::new();
+        while let Some(ServerTransportEvent::ClientDisconnected { client_id }) = server.get_event()
+        {
+            gone.push(client_id);
+        }
+        gone.sort();
+        assert_eq!(gone, vec![1, 2]);
+        assert!(server.clients_id().is_empty());
+
+        // Nothing is delivered on a closed connection
+        leaving.send_message(0, vec![1]);
+        assert_eq!(server.receive_message(1, 0), None);
+    }
+}
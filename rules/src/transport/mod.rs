@@ -0,0 +1,119 @@
+//! How clients and the server exchange messages, independent of the network library.
+//! [`ClientTransport`] and [`ServerTransport`] are implemented for renet and for in-memory
+//! channels, the latter lets tests drive a whole server without sockets.
+
+mod memory;
+mod renet;
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+pub use self::memory::{MemoryClient, MemoryConnector, MemoryServer};
+
+/// Client side of a connection to the game server.
+pub trait ClientTransport: Send + Sync {
+    fn client_id(&self) -> u64;
+    /// Advances the connection by `delta` and takes in whatever arrived since.
+    fn update(&mut self, delta: Duration) -> Result<(), TransportError>;
+    fn send_message(&mut self, channel: u8, message: Vec<u8>);
+    fn receive_message(&mut self, channel: u8) -> Option<Vec<u8>>;
+    /// Actually sends the messages queued with [`ClientTransport::send_message`].
+    fn send_packets(&mut self) -> Result<(), TransportError>;
+    fn is_connected(&self) -> bool;
+    /// Why the connection was closed, `None` while it is still open or being opened.
+    fn disconnected(&self) -> Option<String>;
+    fn disconnect(&mut self);
+}
+
+/// Server side of the connections to every client.
+pub trait ServerTransport {
+    /// Advances every connection by `delta` and takes in whatever arrived since.
+    fn update(&mut self, delta: Duration) -> Result<(), TransportError>;
+    fn get_event(&mut self) -> Option<ServerTransportEvent>;
+    fn clients_id(&self) -> Vec<u64>;
+    /// Where a client connects from, if the transport has such a notion.
+    fn client_addr(&self, client_id: u64) -> Option<SocketAddr>;
+    /// How well the connection to a client is doing, if the transport measures it.
+    fn network_stats(&self, client_id: u64) -> Option<NetworkStats>;
+    fn send_message(&mut self, client_id: u64, channel: u8, message: Vec<u8>);
+    fn receive_message(&mut self, client_id: u64, channel: u8) -> Option<Vec<u8>>;
+    /// Actually sends the messages queued with [`ServerTransport::send_message`].
+    fn send_packets(&mut self) -> Result<(), TransportError>;
+    fn disconnect(&mut self, client_id: u64);
+}
+
+/// Lets a boxed transport, e.g. the one a game server owns, be passed on as `&mut dyn`.
+impl<T: ServerTransport + ?Sized> ServerTransport for Box<T> {
+    fn update(&mut self, delta: Duration) -> Result<(), TransportError> {
+        (**self).update(delta)
+    }
+
+    fn get_event(&mut self) -> Option<ServerTransportEvent> {
+        (**self).get_event()
+    }
+
+    fn clients_id(&self) -> Vec<u64> {
+        (**self).clients_id()
+    }
+
+    fn client_addr(&self, client_id: u64) -> Option<SocketAddr> {
+        (**self).client_addr(client_id)
+    }
+
+    fn network_stats(&self, client_id: u64) -> Option<NetworkStats> {
+        (**self).network_stats(client_id)
+    }
+
+    fn send_message(&mut self, client_id: u64, channel: u8, message: Vec<u8>) {
+        (**self).send_message(client_id, channel, message)
+    }
+
+    fn receive_message(&mut self, client_id: u64, channel: u8) -> Option<Vec<u8>> {
+        (**self).receive_message(client_id, channel)
+    }
+
+    fn send_packets(&mut self) -> Result<(), TransportError> {
+        (**self).send_packets()
+    }
+
+    fn disconnect(&mut self, client_id: u64) {
+        (**self).disconnect(client_id)
+    }
+}
+
+/// Clients coming and going, as seen by a [`ServerTransport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerTransportEvent {
+    /// `user_data` is whatever the client attached to its connection request.
+    ClientConnected {
+        client_id: u64,
+        user_data: Vec<u8>,
+    },
+    ClientDisconnected {
+        client_id: u64,
+    },
+}
+
+/// Connection quality of a single client, as measured by the transport.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NetworkStats {
+    /// Round trip time in milliseconds.
+    pub rtt: f64,
+    pub sent_kbps: f64,
+    pub received_kbps: f64,
+    /// Share of packets lost, between 0 and 1.
+    pub packet_loss: f64,
+}
+
+/// A transport that failed for good.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransportError(pub String);
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TransportError {}
@@ -0,0 +1,92 @@
+use ::renet::{RenetClient, RenetServer, ServerEvent};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use super::{ClientTransport, NetworkStats, ServerTransport, ServerTransportEvent, TransportError};
+
+impl ClientTransport for RenetClient {
+    fn client_id(&self) -> u64 {
+        RenetClient::client_id(self)
+    }
+
+    fn update(&mut self, delta: Duration) -> Result<(), TransportError> {
+        RenetClient::update(self, delta).map_err(|err| TransportError(err.to_string()))
+    }
+
+    fn send_message(&mut self, channel: u8, message: Vec<u8>) {
+        RenetClient::send_message(self, channel, message);
+    }
+
+    fn receive_message(&mut self, channel: u8) -> Option<Vec<u8>> {
+        RenetClient::receive_message(self, channel)
+    }
+
+    fn send_packets(&mut self) -> Result<(), TransportError> {
+        RenetClient::send_packets(self).map_err(|err| TransportError(err.to_string()))
+    }
+
+    fn is_connected(&self) -> bool {
+        RenetClient::is_connected(self)
+    }
+
+    fn disconnected(&self) -> Option<String> {
+        RenetClient::disconnected(self).map(|reason| reason.to_string())
+    }
+
+    fn disconnect(&mut self) {
+        RenetClient::disconnect(self);
+    }
+}
+
+impl ServerTransport for RenetServer {
+    fn update(&mut self, delta: Duration) -> Result<(), TransportError> {
+        RenetServer::update(self, delta).map_err(|err| TransportError(err.to_string()))
+    }
+
+    fn get_event(&mut self) -> Option<ServerTransportEvent> {
+        RenetServer::get_event(self).map(|event| match event {
+            ServerEvent::ClientConnected(client_id, user_data) => {
+                ServerTransportEvent::ClientConnected {
+                    client_id,
+                    user_data: user_data.to_vec(),
+                }
+            }
+            ServerEvent::ClientDisconnected(client_id) => {
+                ServerTransportEvent::ClientDisconnected { client_id }
+            }
+        })
+    }
+
+    fn clients_id(&self) -> Vec<u64> {
+        RenetServer::clients_id(self)
+    }
+
+    fn client_addr(&self, client_id: u64) -> Option<SocketAddr> {
+        RenetServer::client_addr(self, client_id)
+    }
+
+    fn network_stats(&self, client_id: u64) -> Option<NetworkStats> {
+        RenetServer::network_info(self, client_id).map(|info| NetworkStats {
+            rtt: info.rtt as f64,
+            sent_kbps: info.sent_kbps as f64,
+            received_kbps: info.received_kbps as f64,
+            packet_loss: info.packet_loss as f64,
+        })
+    }
+
+    fn send_message(&mut self, client_id: u64, channel: u8, message: Vec<u8>) {
+        RenetServer::send_message(self, client_id, channel, message);
+    }
+
+    fn receive_message(&mut self, client_id: u64, channel: u8) -> Option<Vec<u8>> {
+        RenetServer::receive_message(self, client_id, channel)
+    }
+
+    fn send_packets(&mut self) -> Result<(), TransportError> {
+        RenetServer::send_packets(self).map_err(|err| TransportError(err.to_string()))
+    }
+
+    fn disconnect(&mut self, client_id: u64) {
+        RenetServer::disconnect(self, client_id);
+    }
+}
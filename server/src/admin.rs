@@ -0,0 +1,404 @@
+//! Commands an operator types into the server's console.
+
+use log::warn;
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use crate::archive::{self, ExportFormat, GameId};
+use crate::{publish, send, GameServer};
+use rules::protocol::{
+    self, ChatLine, KickReason, RoomId, ServerMessage, CHAT_CHANNEL, MAX_CHAT_LEN, MAX_REASON_LEN,
+};
+use rules::{EndGameReason, GameEvent, GameStage};
+
+/// Name server notices appear under in the chat. Players can't pick it, names have no brackets.
+const NOTICE_NAME: &str = "[server]";
+
+const HELP: &str = "\
+commands:
+  clients                 list connected clients
+  games                   list rooms and their games
+  dump <room>             print a room's GameState
+  kick <client>           disconnect a client
+  ban <client>            disconnect a client and refuse its address from now on
+  unban <address>         lift a ban
+  end <room> <reason>     end a room's game, showing the reason to its players
+  say <text>              send a notice to every client's chat
+  archive                 list the archived games
+  ladder                  list the rated players
+  export <game|all> <json|csv> <file>
+                          write archived games to a file
+  shutdown                notify clients and stop the server
+  help                    show this";
+
+/// A parsed console line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminCommand {
+    Help,
+    Clients,
+    Games,
+    Dump {
+        room: RoomId,
+    },
+    Kick {
+        client: u64,
+    },
+    Ban {
+        client: u64,
+    },
+    Unban {
+        addr: IpAddr,
+    },
+    End {
+        room: RoomId,
+        reason: String,
+    },
+    Say {
+        text: String,
+    },
+    Archive,
+    Ladder,
+    /// Exports one archived game, or all of them.
+    Export {
+        game: Option<GameId>,
+        format: ExportFormat,
+        path: PathBuf,
+    },
+    /// Handled by the owner of the server, which decides how to stop.
+    Shutdown,
+}
+
+impl AdminCommand {
+    /// Parses a console line. Blank lines parse to `None`.
+    pub fn parse(line: &str) -> Result<Option<Self>, String> {
+        let line = line.trim();
+        let (name, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim();
+        let command = match name {
+            "" => return Ok(None),
+            "help" => AdminCommand::Help,
+            "clients" => AdminCommand::Clients,
+            "games" => AdminCommand::Games,
+            "dump" => AdminCommand::Dump {
+                room: parse_arg(rest, "room id")?,
+            },
+            "kick" => AdminCommand::Kick {
+                client: parse_arg(rest, "client id")?,
+            },
+            "ban" => AdminCommand::Ban {
+                client: parse_arg(rest, "client id")?,
+            },
+            "unban" => AdminCommand::Unban {
+                addr: parse_arg(rest, "address")?,
+            },
+            "end" => {
+                let (room, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+                let reason = match reason.trim() {
+                    "" => "Ended by the server's operator".to_string(),
+                    reason => reason.to_string(),
+                };
+                AdminCommand::End {
+                    room: parse_arg(room, "room id")?,
+                    reason,
+                }
+            }
+            "say" if !rest.is_empty() => AdminCommand::Say {
+                text: rest.to_string(),
+            },
+            "say" => return Err("usage: say <text>".to_string()),
+            "archive" => AdminCommand::Archive,
+            "ladder" => AdminCommand::Ladder,
+            "export" => {
+                let args: Vec<_> = rest.split_whitespace().collect();
+                let (game, format, path) = match args[..] {
+                    [game, format, path] => (game, format, path),
+                    _ => return Err("usage: export <game|all> <json|csv> <file>".to_string()),
+                };
+                AdminCommand::Export {
+                    game: match game {
+                        "all" => None,
+                        game => Some(parse_arg(game, "game id")?),
+                    },
+                    format: format.parse()?,
+                    path: path.into(),
+                }
+            }
+            "shutdown" => AdminCommand::Shutdown,
+            _ => return Err(format!("unknown command {:?}, try help", name)),
+        };
+        Ok(Some(command))
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(arg: &str, what: &str) -> Result<T, String> {
+    arg.parse()
+        .map_err(|_| format!("expected a {}, got {:?}", what, arg))
+}
+
+impl GameServer {
+    /// Runs a console command and returns what to print.
+    pub fn admin(&mut self, command: AdminCommand) -> Result<String, String> {
+        let mut out = String::new();
+        match command {
+            AdminCommand::Help => out.push_str(HELP),
+            AdminCommand::Clients => {
+                let mut clients = self.server.clients_id();
+                clients.sort_unstable();
+                for client_id in clients {
+                    let addr = self
+                        .server
+                        .client_addr(client_id)
+                        .map_or("-".to_string(), |addr| addr.to_string());
+                    let (room, role, name) = match self.client_rooms.get(&client_id) {
+                        Some(room_id) => {
+                            let room = &self.rooms[room_id];
+                            let role = match room.spectators.contains_key(&client_id) {
+                                true => "spectator",
+                                false => "player",
+                            };
+                            let name = room.name_of(client_id).unwrap_or_default();
+                            (room_id.to_string(), role, name.to_string())
+                        }
+                        None => ("-".to_string(), "handshake", String::new()),
+                    };
+                    let strikes = self
+                        .limiters
+                        .get(&client_id)
+                        .map_or(0, |limiter| limiter.strikes());
+                    writeln!(
+                        out,
+                        "{:>20}  {:<21}  room {:<4} {:<9}  {} strikes  {:?}",
+                        client_id, addr, room, role, strikes, name
+                    )
+                    .unwrap();
+                }
+                write!(out, "{} clients", self.server.clients_id().len()).unwrap();
+            }
+            AdminCommand::Games => {
+                let mut rooms: Vec<_> = self.rooms.values().collect();
+                rooms.sort_by_key(|room| room.id);
+                for room in &rooms {
+                    let players: Vec<_> = room
+                        .game_state
+                        .players
+                        .iter()
+                        .map(|(id, player)| format!("{} ({})", player.name, id))
+                        .collect();
+                    writeln!(
+                        out,
+                        "room {:<4} {:?}{}, {} events, {} spectators, players: {}",
+                        room.id,
+                        room.game_state.stage,
+                        match room.correspondence {
+                            Some(_) => " by correspondence",
+                            None => "",
+                        },
+                        room.game_state.history.len(),
+                        room.spectators.len(),
+                        players.join(", ")
+                    )
+                    .unwrap();
+                }
+                write!(out, "{} rooms", rooms.len()).unwrap();
+            }
+            AdminCommand::Dump { room } => {
+                let room = self.rooms.get(&room).ok_or("no such room")?;
+                write!(out, "{:#?}", room.game_state).unwrap();
+            }
+            AdminCommand::Kick { client } => {
+                self.kick(client, KickReason::ByAdmin)?;
+                write!(out, "kicked {}", client).unwrap();
+            }
+            AdminCommand::Ban { client } => {
+                let addr = self
+                    .server
+                    .client_addr(client)
+                    .ok_or("no address known for that client")?;
+                self.banned.insert(addr.ip());
+                self.kick(client, KickReason::Banned)?;
+                write!(out, "banned {} ({})", client, addr.ip()).unwrap();
+            }
+            AdminCommand::Unban { addr } => {
+                if !self.banned.remove(&addr) {
+                    return Err(format!("{} is not banned", addr));
+                }
+                write!(out, "unbanned {}", addr).unwrap();
+            }
+            AdminCommand::End { room, reason } => {
+                let now = self.clock.now();
+                let room = self.rooms.get_mut(&room).ok_or("no such room")?;
+                if room.game_state.stage == GameStage::Ended {
+                    return Err("that game is over already".to_string());
+                }
+                let reason: String = reason.chars().take(MAX_REASON_LEN).collect();
+                let event = GameEvent::EndGame {
+                    reason: EndGameReason::Aborted { reason },
+                };
+                publish(
+                    &mut self.server,
+                    room,
+                    event,
+                    &self.settings.spectators,
+                    now,
+                );
+                write!(out, "ended the game in room {}", room.id).unwrap();
+            }
+            AdminCommand::Say { text } => {
+                let line = ChatLine {
+                    from: 0,
+                    name: NOTICE_NAME.to_string(),
+                    text: text.chars().take(MAX_CHAT_LEN).collect(),
+                };
+                let encoded = protocol::encode(&line);
+                let clients = self.server.clients_id();
+                for client_id in &clients {
+                    self.server
+                        .send_message(*client_id, CHAT_CHANNEL, encoded.clone());
+                }
+                write!(out, "sent to {} clients", clients.len()).unwrap();
+            }
+            AdminCommand::Archive => {
+                let games = self
+                    .enabled_archive()?
+                    .list()
+                    .map_err(|err| err.to_string())?;
+                for game in &games {
+                    let players: Vec<_> = game
+                        .players
+                        .values()
+                        .map(|player| player.name.as_str())
+                        .collect();
+                    writeln!(
+                        out,
+                        "game {:<5} {}  {:>6.0}s  {} events  {}  {:?}",
+                        game.id,
+                        game.ended_at,
+                        game.duration_secs,
+                        game.history.len(),
+                        players.join(" vs "),
+                        game.result
+                    )
+                    .unwrap();
+                }
+                write!(out, "{} games", games.len()).unwrap();
+            }
+            AdminCommand::Ladder => {
+                let entries = self.ladder.entries();
+                for entry in &entries {
+                    writeln!(
+                        out,
+                        "{:>4}. {:<20} {:>5}  {} games, {} wins",
+                        entry.rank, entry.name, entry.rating, entry.games, entry.wins
+                    )
+                    .unwrap();
+                }
+                write!(out, "{} rated players", entries.len()).unwrap();
+            }
+            AdminCommand::Export { game, format, path } => {
+                let archive = self.enabled_archive()?;
+                let games = match game {
+                    Some(id) => vec![archive.fetch(id).map_err(|err| err.to_string())?],
+                    None => archive.list().map_err(|err| err.to_string())?,
+                };
+                let mut file = File::create(&path)
+                    .map(BufWriter::new)
+                    .map_err(|err| err.to_string())?;
+                archive::export(&games, format, &mut file)
+                    .and_then(|_| io::Write::flush(&mut file))
+                    .map_err(|err| err.to_string())?;
+                write!(out, "exported {} games to {:?}", games.len(), path).unwrap();
+            }
+            AdminCommand::Shutdown => {
+                return Err("shutdown has to be handled by the server's owner".to_string())
+            }
+        }
+        Ok(out)
+    }
+
+    fn enabled_archive(&self) -> Result<&archive::Archive, String> {
+        self.archive
+            .as_ref()
+            .ok_or_else(|| "the archive is off, start the server with --archive-dir".to_string())
+    }
+
+    /// Tells a client why it is being kicked and disconnects it on the next step.
+    fn kick(&mut self, client_id: u64, reason: KickReason) -> Result<(), String> {
+        if !self.server.clients_id().contains(&client_id) {
+            return Err("no such client".to_string());
+        }
+        warn!("Kicking client {}: {}", client_id, reason);
+        send(&mut self.server, client_id, &ServerMessage::Kicked(reason));
+        if !self.to_disconnect.contains(&client_id) {
+            self.to_disconnect.push(client_id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands_and_their_arguments() {
+        assert_eq!(AdminCommand::parse("  "), Ok(None));
+        assert_eq!(AdminCommand::parse("games"), Ok(Some(AdminCommand::Games)));
+        assert_eq!(
+            AdminCommand::parse("  kick   42 "),
+            Ok(Some(AdminCommand::Kick { client: 42 }))
+        );
+        assert_eq!(
+            AdminCommand::parse("unban 10.0.0.1"),
+            Ok(Some(AdminCommand::Unban {
+                addr: "10.0.0.1".parse().unwrap()
+            }))
+        );
+        assert_eq!(
+            AdminCommand::parse("say hello  there"),
+            Ok(Some(AdminCommand::Say {
+                text: "hello  there".to_string()
+            }))
+        );
+    }
+
+    #[test]
+    fn end_has_a_default_reason() {
+        assert_eq!(
+            AdminCommand::parse("end 3 cheating"),
+            Ok(Some(AdminCommand::End {
+                room: 3,
+                reason: "cheating".to_string()
+            }))
+        );
+        assert_eq!(
+            AdminCommand::parse("end 3"),
+            Ok(Some(AdminCommand::End {
+                room: 3,
+                reason: "Ended by the server's operator".to_string()
+            }))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        assert_eq!(
+            AdminCommand::parse("kick alice"),
+            Err("expected a client id, got \"alice\"".to_string())
+        );
+        assert_eq!(
+            AdminCommand::parse("dump"),
+            Err("expected a room id, got \"\"".to_string())
+        );
+        assert_eq!(
+            AdminCommand::parse("say"),
+            Err("usage: say <text>".to_string())
+        );
+        assert_eq!(
+            AdminCommand::parse("reboot"),
+            Err("unknown command \"reboot\", try help".to_string())
+        );
+    }
+}
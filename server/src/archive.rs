@@ -0,0 +1,313 @@
+//! Finished games kept on disk for later analysis, one JSON file per game.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Instant;
+
+use crate::audit::timestamp;
+use crate::metrics::event_kind;
+use crate::persist::save_json;
+use crate::room::{Room, SpectatorPolicy};
+use rules::hex::MAP_RADIUS;
+use rules::protocol::{RoomId, PROTOCOL_VERSION};
+use rules::ships::{GameObject, SHIPS};
+use rules::{EndGameReason, GameEvent, Player, PlayerId};
+
+/// Number of a game in the archive, counting up from 1.
+pub type GameId = u64;
+
+/// Everything there is to know about a finished game.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedGame {
+    pub id: GameId,
+    pub room: RoomId,
+    /// Everyone who played, including players that left before the end.
+    pub players: BTreeMap<PlayerId, Player>,
+    pub rules: GameRules,
+    pub history: Vec<GameEvent>,
+    pub result: EndGameReason,
+    /// When the game began, or the room opened if it never did. UTC, see `ended_at`.
+    pub started_at: String,
+    /// UTC, e.g. 2022-11-05T14:03:27.512Z
+    pub ended_at: String,
+    pub duration_secs: f64,
+}
+
+/// The rules a game was played under.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameRules {
+    pub protocol_version: u32,
+    pub map_radius: i32,
+    pub fleet: Vec<GameObject>,
+    pub spectator_delay_secs: f64,
+    pub reveal_fleets: bool,
+}
+
+impl ArchivedGame {
+    /// Describes the game a room finished at `now`. `None` if it has not ended.
+    pub fn from_room(room: &Room, policy: &SpectatorPolicy, now: Instant) -> Option<Self> {
+        let history = room.game_state.history.clone();
+        let result = history.iter().rev().find_map(|event| match event {
+            GameEvent::EndGame { reason } => Some(reason.clone()),
+            _ => None,
+        })?;
+        let players = room
+            .game_state
+            .joined_players()
+            .into_iter()
+            .map(|(player_id, player)| (player_id, player.clone()))
+            .collect();
+        let ended_at = room.wall_time(now);
+        Some(Self {
+            id: 0,
+            room: room.id,
+            players,
+            rules: GameRules {
+                protocol_version: PROTOCOL_VERSION,
+                map_radius: MAP_RADIUS,
+                fleet: SHIPS.to_vec(),
+                spectator_delay_secs: policy.delay.as_secs_f64(),
+                reveal_fleets: policy.reveal_fleets,
+            },
+            history,
+            result,
+            started_at: timestamp(room.started_at),
+            ended_at: timestamp(ended_at),
+            duration_secs: ended_at
+                .duration_since(room.started_at)
+                .unwrap_or_default()
+                .as_secs_f64(),
+        })
+    }
+}
+
+/// What games can be exported as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// An array of [`ArchivedGame`]s.
+    Json,
+    /// One row per event: game, seq, event, player, data. `data` is the event as JSON.
+    Csv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(format!("unknown export format {:?}, use json or csv", s)),
+        }
+    }
+}
+
+/// The games stored in `dir`, as `game-<id>.json` files.
+#[derive(Debug)]
+pub struct Archive {
+    dir: PathBuf,
+    /// Found by looking at the directory the first time a game is stored.
+    next_id: Option<GameId>,
+}
+
+impl Archive {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            next_id: None,
+        }
+    }
+
+    /// Stores a game under a new id and returns the id.
+    pub fn store(&mut self, mut game: ArchivedGame) -> io::Result<GameId> {
+        fs::create_dir_all(&self.dir)?;
+        let id = match self.next_id {
+            Some(id) => id,
+            None => self.ids()?.last().map_or(1, |id| id + 1),
+        };
+        game.id = id;
+
+        save_json(&self.path(id), &game)?;
+        self.next_id = Some(id + 1);
+        Ok(id)
+    }
+
+    pub fn fetch(&self, id: GameId) -> io::Result<ArchivedGame> {
+        let json = fs::read(self.path(id))?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    /// Every stored game, oldest first.
+    pub fn list(&self) -> io::Result<Vec<ArchivedGame>> {
+        self.ids()?.into_iter().map(|id| self.fetch(id)).collect()
+    }
+
+    fn path(&self, id: GameId) -> PathBuf {
+        self.dir.join(format!("game-{}.json", id))
+    }
+
+    /// Ids of the stored games, in ascending order.
+    fn ids(&self) -> io::Result<Vec<GameId>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut ids = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            let id = name.to_str().and_then(|name| {
+                name.strip_prefix("game-")?
+                    .strip_suffix(".json")?
+                    .parse::<GameId>()
+                    .ok()
+            });
+            ids.extend(id);
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+}
+
+/// Writes `games` to `out` in the given format.
+pub fn export(games: &[ArchivedGame], format: ExportFormat, out: &mut dyn Write) -> io::Result<()> {
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, games)?;
+            writeln!(out)
+        }
+        ExportFormat::Csv => {
+            writeln!(out, "game,seq,event,player,data")?;
+            for game in games {
+                for (index, event) in game.history.iter().enumerate() {
+                    let player = match event {
+                        GameEvent::BeginGame { first_player } => Some(*first_player),
+                        GameEvent::PlayerJoined { player_id, .. }
+                        | GameEvent::PlayerDisconnected { player_id }
+                        | GameEvent::ShipMove { player_id, .. }
+                        | GameEvent::ShipPlaced { player_id, .. } => Some(*player_id),
+                        GameEvent::SetupBoard | GameEvent::EndGame { .. } => None,
+                    };
+                    writeln!(
+                        out,
+                        "{},{},{},{},{}",
+                        game.id,
+                        index + 1,
+                        event_kind(event),
+                        player.map_or(String::new(), |player| player.to_string()),
+                        csv_field(&serde_json::to_string(event)?)
+                    )?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Quotes a CSV field, doubling the quotes inside it.
+fn csv_field(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// A game in which the second player left right after the board was set up.
+    fn finished_game() -> ArchivedGame {
+        let start = Instant::now();
+        let mut room = Room::new(7, start);
+        let events = [
+            GameEvent::PlayerJoined {
+                player_id: 1,
+                player_details: Player {
+                    name: "\"ace\"".to_string(),
+                },
+            },
+            GameEvent::PlayerJoined {
+                player_id: 2,
+                player_details: Player {
+                    name: "bob".to_string(),
+                },
+            },
+            GameEvent::SetupBoard,
+            GameEvent::EndGame {
+                reason: EndGameReason::PlayerLeft { player_id: 2 },
+            },
+        ];
+        for event in &events {
+            room.game_state.consume(event);
+        }
+        let policy = SpectatorPolicy {
+            delay: Duration::ZERO,
+            reveal_fleets: false,
+        };
+        ArchivedGame::from_room(&room, &policy, start + Duration::from_secs(90)).unwrap()
+    }
+
+    #[test]
+    fn describes_finished_games() {
+        let game = finished_game();
+        assert_eq!(game.room, 7);
+        assert_eq!(game.players.len(), 2);
+        assert_eq!(game.result, EndGameReason::PlayerLeft { player_id: 2 });
+        assert_eq!(game.duration_secs, 90.0);
+
+        let ongoing = Room::new(8, Instant::now());
+        let policy = SpectatorPolicy {
+            delay: Duration::ZERO,
+            reveal_fleets: false,
+        };
+        assert!(ArchivedGame::from_room(&ongoing, &policy, Instant::now()).is_none());
+    }
+
+    #[test]
+    fn exports_one_csv_row_per_event() {
+        let mut game = finished_game();
+        game.id = 3;
+        let mut out = Vec::new();
+        export(&[game], ExportFormat::Csv, &mut out).unwrap();
+
+        let csv = String::from_utf8(out).unwrap();
+        let rows: Vec<_> = csv.lines().collect();
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[0], "game,seq,event,player,data");
+        assert_eq!(
+            rows[1],
+            r#"3,1,PlayerJoined,1,"{""PlayerJoined"":{""player_id"":1,""player_details"":{""name"":""\""ace\""""}}}""#
+        );
+        assert_eq!(rows[3], r#"3,3,SetupBoard,,"""SetupBoard""""#);
+        assert!(rows[4].starts_with("3,4,EndGame,,"));
+    }
+
+    #[test]
+    fn stores_games_under_increasing_ids() {
+        let dir = std::env::temp_dir().join(format!("archive-{}", std::process::id()));
+        let mut archive = Archive::new(&dir);
+        assert_eq!(archive.store(finished_game()).unwrap(), 1);
+        assert_eq!(archive.store(finished_game()).unwrap(), 2);
+
+        // A new archive on the same directory carries on counting
+        let mut reopened = Archive::new(&dir);
+        let id = reopened.store(finished_game()).unwrap();
+        let games = reopened.list().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(id, 3);
+        assert_eq!(
+            games.iter().map(|game| game.id).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn parses_export_formats() {
+        assert_eq!("CSV".parse(), Ok(ExportFormat::Csv));
+        assert_eq!("json".parse(), Ok(ExportFormat::Json));
+        assert!("xml".parse::<ExportFormat>().is_err());
+    }
+}
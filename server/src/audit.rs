@@ -0,0 +1,220 @@
+//! A JSON-lines record of every command received and every event published, one file per day.
+
+use log::warn;
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rules::protocol::{CommandId, EventSeq, RejectReason, RoomId};
+use rules::GameEvent;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// One line of the audit log.
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    /// UTC, e.g. 2022-11-05T14:03:27.512Z
+    timestamp: String,
+    #[serde(flatten)]
+    entry: Entry<'a>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Entry<'a> {
+    /// A command a client sent and what came of it.
+    Command {
+        room: Option<RoomId>,
+        client: u64,
+        id: CommandId,
+        event: &'a GameEvent,
+        accepted: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<RejectReason>,
+        /// Events the command caused to be published.
+        emitted: &'a [(EventSeq, GameEvent)],
+    },
+    /// An event the server published on its own, e.g. a player joining or leaving.
+    Event {
+        room: RoomId,
+        seq: EventSeq,
+        event: &'a GameEvent,
+    },
+}
+
+/// Writes the audit log into `dir`, as `audit-YYYY-MM-DD.jsonl` files named after the UTC day.
+/// Files are opened lazily and appended to. Failing to write only logs a warning,
+/// the game goes on without its audit log.
+#[derive(Debug)]
+pub struct AuditLog {
+    dir: PathBuf,
+    /// The open file and the day it belongs to, in days since the Unix epoch.
+    file: Option<(u64, File)>,
+}
+
+impl AuditLog {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            file: None,
+        }
+    }
+
+    pub fn command(
+        &mut self,
+        room: Option<RoomId>,
+        client: u64,
+        id: CommandId,
+        event: &GameEvent,
+        reason: Option<RejectReason>,
+        emitted: &[(EventSeq, GameEvent)],
+    ) {
+        self.write(Entry::Command {
+            room,
+            client,
+            id,
+            event,
+            accepted: reason.is_none(),
+            reason,
+            emitted,
+        });
+    }
+
+    pub fn events(&mut self, room: RoomId, events: &[(EventSeq, GameEvent)]) {
+        for (seq, event) in events {
+            self.write(Entry::Event {
+                room,
+                seq: *seq,
+                event,
+            });
+        }
+    }
+
+    fn write(&mut self, entry: Entry) {
+        let now = SystemTime::now();
+        let record = Record {
+            timestamp: timestamp(now),
+            entry,
+        };
+        let mut line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(err) => {
+                warn!("Could not serialize an audit record: {}", err);
+                return;
+            }
+        };
+        line.push('\n');
+
+        let day = unix_secs(now) / SECS_PER_DAY;
+        let result = self
+            .file_for(day)
+            .and_then(|file| file.write_all(line.as_bytes()));
+        if let Err(err) = result {
+            warn!(
+                "Could not write to the audit log in {:?}: {}",
+                self.dir, err
+            );
+            // Try again with a freshly opened file next time
+            self.file = None;
+        }
+    }
+
+    /// The file for `day`, rotating to a new one when the day changed.
+    fn file_for(&mut self, day: u64) -> io::Result<&mut File> {
+        if !matches!(self.file, Some((open_day, _)) if open_day == day) {
+            fs::create_dir_all(&self.dir)?;
+            let (year, month, day_of_month) = civil_date(day);
+            let path = self.dir.join(format!(
+                "audit-{:04}-{:02}-{:02}.jsonl",
+                year, month, day_of_month
+            ));
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            self.file = Some((day, file));
+        }
+        Ok(&mut self.file.as_mut().unwrap().1)
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// `time` in UTC, e.g. 2022-11-05T14:03:27.512Z
+pub(crate) fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_date(secs / SECS_PER_DAY);
+    let secs_of_day = secs % SECS_PER_DAY;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Year, month and day of the month of a day counted from the Unix epoch, in the proleptic
+/// Gregorian calendar. See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(59), (1970, 3, 1));
+        // 2000 was a leap year, 2100 won't be
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+        assert_eq!(civil_date(47_540), (2100, 2, 28));
+        assert_eq!(civil_date(47_541), (2100, 3, 1));
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_millis(1_700_000_000_042)),
+            "2023-11-14T22:13:20.042Z".to_string()
+        );
+    }
+
+    #[test]
+    fn rotates_files_by_day() {
+        let dir = std::env::temp_dir().join(format!("audit-rotation-{}", std::process::id()));
+        let mut log = AuditLog::new(&dir);
+        for day in [19_000, 19_000, 19_001] {
+            log.file_for(day).unwrap().write_all(b"{}\n").unwrap();
+        }
+
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        let first = fs::read_to_string(dir.join(&files[0])).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            files,
+            vec!["audit-2022-01-08.jsonl", "audit-2022-01-09.jsonl"]
+        );
+        assert_eq!(first, "{}\n{}\n");
+    }
+}
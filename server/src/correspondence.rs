@@ -0,0 +1,174 @@
+//! Correspondence games: slow games whose players come and go as they please, as long as
+//! they move before the deadline of every move.
+
+use log::info;
+use std::time::{Instant, UNIX_EPOCH};
+
+use crate::{publish, GameServer};
+use rules::protocol::{OpenGame, RoomId, MAX_OPEN_GAMES};
+use rules::{EndGameReason, GameEvent, GameStage};
+
+impl GameServer {
+    /// The correspondence games the player holding `token` takes part in and that are not
+    /// over yet. Games waiting for the player come first, then the closest deadlines.
+    pub(crate) fn open_games(&self, token: &str) -> Vec<OpenGame> {
+        let mut games: Vec<_> = self
+            .rooms
+            .values()
+            .filter(|room| {
+                room.correspondence.is_some() && room.game_state.stage != GameStage::Ended
+            })
+            .filter_map(|room| {
+                let player_id = room
+                    .player_with_token(token)
+                    .filter(|player_id| room.game_state.players.contains_key(player_id))?;
+                Some(OpenGame {
+                    room: room.id,
+                    opponent: room
+                        .game_state
+                        .players
+                        .iter()
+                        .find(|(id, _)| **id != player_id)
+                        .map(|(_, player)| player.name.clone()),
+                    stage: room.game_state.stage,
+                    your_turn: room.game_state.players_to_move().contains(&player_id),
+                    deadline: room.move_deadline.map(|deadline| {
+                        deadline
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs()
+                    }),
+                })
+            })
+            .collect();
+        games.sort_by_key(|game| {
+            (
+                !game.your_turn,
+                game.deadline.unwrap_or(u64::MAX),
+                game.room,
+            )
+        });
+        games.truncate(MAX_OPEN_GAMES);
+        games
+    }
+
+    /// The most urgent open game keeping a seat for the player holding `token`.
+    pub(crate) fn awaiting_game(&self, token: &str) -> Option<RoomId> {
+        self.open_games(token)
+            .into_iter()
+            .map(|game| game.room)
+            .find(|room_id| self.rooms[room_id].awaiting.contains_key(token))
+    }
+
+    /// The game still waiting for an opponent that keeps a seat for the player holding `token`.
+    pub(crate) fn waiting_game(&self, token: &str) -> Option<RoomId> {
+        self.rooms
+            .values()
+            .find(|room| {
+                room.correspondence.is_some()
+                    && room.game_state.stage == GameStage::Lobby
+                    && room.awaiting.contains_key(token)
+            })
+            .map(|room| room.id)
+    }
+
+    /// Moves a correspondence player to another of its open games. Players can leave a game
+    /// that is over or a correspondence game, which keeps their seat.
+    pub(crate) fn switch_game(&mut self, client_id: u64, room_id: RoomId, now: Instant) -> bool {
+        let token = match self.tokens.get(&client_id) {
+            Some(token) => token,
+            None => return false,
+        };
+        let current = self.client_rooms.get(&client_id).copied();
+        if current == Some(room_id) {
+            return true;
+        }
+        let may_leave = current.is_none_or(|current| {
+            let room = &self.rooms[&current];
+            room.correspondence.is_some()
+                || room.spectators.contains_key(&client_id)
+                || room.game_state.stage == GameStage::Ended
+        });
+        let kept = self
+            .rooms
+            .get(&room_id)
+            .is_some_and(|room| room.correspondence.is_some() && room.awaiting.contains_key(token));
+        if !may_leave || !kept {
+            return false;
+        }
+        self.leave_room(client_id, now);
+        self.take_seat(client_id, room_id);
+        true
+    }
+
+    /// Ends the correspondence games whose deadline passed, against whoever they waited for.
+    pub(crate) fn expire_moves(&mut self, now: Instant) {
+        let expired: Vec<RoomId> = self
+            .rooms
+            .values()
+            .filter(|room| {
+                matches!(room.move_deadline, Some(deadline) if deadline <= room.wall_time(now))
+            })
+            .map(|room| room.id)
+            .collect();
+        for room_id in expired {
+            let room = self.rooms.get_mut(&room_id).unwrap();
+            room.move_deadline = None;
+            let event = match room.game_state.players_to_move()[..] {
+                [player_id] => {
+                    info!("Room {}: player {} ran out of time", room_id, player_id);
+                    GameEvent::EndGame {
+                        reason: EndGameReason::TimedOut { player_id },
+                    }
+                }
+                // The game began as soon as both fleets were in place
+                [] => continue,
+                _ => GameEvent::EndGame {
+                    reason: EndGameReason::Aborted {
+                        reason: "Neither player placed their fleet in time".to_string(),
+                    },
+                },
+            };
+            if room.game_state.validade(&event) {
+                publish(
+                    &mut self.server,
+                    room,
+                    event,
+                    &self.settings.spectators,
+                    now,
+                );
+            }
+        }
+    }
+
+    /// Lets go of the players that were away when their correspondence game ended, and
+    /// closes the rooms nobody is left in.
+    pub(crate) fn release_finished_games(&mut self, now: Instant) {
+        let finished: Vec<RoomId> = self
+            .rooms
+            .values()
+            .filter(|room| {
+                room.correspondence.is_some()
+                    && room.game_state.stage == GameStage::Ended
+                    && !room.awaiting.is_empty()
+            })
+            .map(|room| room.id)
+            .collect();
+        for room_id in finished {
+            let room = self.rooms.get_mut(&room_id).unwrap();
+            for (_, player_id) in room.awaiting.drain().collect::<Vec<_>>() {
+                let event = GameEvent::PlayerDisconnected { player_id };
+                publish(
+                    &mut self.server,
+                    room,
+                    event,
+                    &self.settings.spectators,
+                    now,
+                );
+            }
+            if room.is_empty() {
+                self.close_room(room_id, now);
+            }
+        }
+    }
+}
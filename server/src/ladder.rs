@@ -0,0 +1,339 @@
+//! Tokens issued to players, their Elo ratings and the leaderboard.
+
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::PathBuf;
+
+use crate::persist::{load_json, save_json};
+
+use rules::protocol::{LeaderboardEntry, PlayerToken, ServerMessage, LEADERBOARD_LEN};
+use rules::{EndGameReason, GameEvent, GameState, PlayerId};
+
+/// Rating of a player's first game.
+const INITIAL_RATING: f64 = 1200.0;
+
+/// Most points a single game moves a rating by.
+const K_FACTOR: f64 = 32.0;
+
+/// Length of the tokens the server issues, in characters.
+const TOKEN_LEN: usize = 32;
+
+/// A rated player, keyed by their token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Rating {
+    /// The name of their last rated game.
+    name: String,
+    rating: f64,
+    games: u32,
+    wins: u32,
+}
+
+impl Rating {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            rating: INITIAL_RATING,
+            games: 0,
+            wins: 0,
+        }
+    }
+}
+
+/// Winner and loser of a finished game, if the way it ended counts for the ladder.
+/// Resigning or leaving loses the game once it began, before that nobody played yet.
+/// Games ended by the server's operator are not rated.
+pub fn rated_result(game_state: &GameState) -> Option<(PlayerId, PlayerId)> {
+    let (first, second) = match game_state.joined_players()[..] {
+        [(first, _), (second, _)] => (first, second),
+        _ => return None,
+    };
+    let history = &game_state.history;
+    let other = |player_id: PlayerId| match player_id == first {
+        true => second,
+        false => first,
+    };
+    let began = history
+        .iter()
+        .any(|event| matches!(event, GameEvent::BeginGame { .. }));
+
+    let reason = history.iter().rev().find_map(|event| match event {
+        GameEvent::EndGame { reason } => Some(reason),
+        _ => None,
+    })?;
+    match reason {
+        EndGameReason::PlayerWon { winner } => Some((*winner, other(*winner))),
+        EndGameReason::PlayerResigned { player_id }
+        | EndGameReason::PlayerLeft { player_id }
+        | EndGameReason::TimedOut { player_id }
+            if began =>
+        {
+            Some((other(*player_id), *player_id))
+        }
+        EndGameReason::PlayerResigned { .. }
+        | EndGameReason::PlayerLeft { .. }
+        | EndGameReason::TimedOut { .. }
+        | EndGameReason::Aborted { .. } => None,
+    }
+}
+
+/// Every issued token and rated player, saved to a JSON file whenever they change if the
+/// ladder has one.
+#[derive(Debug, Default)]
+pub struct Ladder {
+    path: Option<PathBuf>,
+    saved: Saved,
+}
+
+/// What goes into the file of a [`Ladder`].
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Saved {
+    tokens: HashSet<PlayerToken>,
+    /// Players with a rated game, a subset of `tokens`.
+    ratings: HashMap<PlayerToken, Rating>,
+}
+
+impl Ladder {
+    /// A ladder that is forgotten when the server stops.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Loads the ladder saved at `path`, or starts a new one there if there is no file yet.
+    pub fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        Ok(Self {
+            saved: load_json(&path)?,
+            path: Some(path),
+        })
+    }
+
+    /// Makes up a new token for a player.
+    pub fn issue_token(&mut self) -> PlayerToken {
+        let token: PlayerToken = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(TOKEN_LEN)
+            .map(char::from)
+            .collect();
+        self.saved.tokens.insert(token.clone());
+        token
+    }
+
+    /// Takes `token` as issued by this ladder, e.g. a token a player still holds from before
+    /// the server restarted without saving its ladder.
+    pub fn accept_token(&mut self, token: PlayerToken) {
+        self.saved.tokens.insert(token);
+    }
+
+    /// Whether `token` was issued by this ladder.
+    pub fn knows(&self, token: &str) -> bool {
+        self.saved.tokens.contains(token)
+    }
+
+    /// Rates a game `winner` won against `loser`, given as token and name.
+    /// Returns the rating changes of the winner and the loser.
+    pub fn record(&mut self, winner: (&str, &str), loser: (&str, &str)) -> (i32, i32) {
+        let (winner_token, winner_name) = winner;
+        let (loser_token, loser_name) = loser;
+        let winner_rating = self.rating_of(winner_token);
+        let loser_rating = self.rating_of(loser_token);
+
+        // Chance of the winner to win, as the ratings predicted it
+        let expected = 1.0 / (1.0 + 10f64.powf((loser_rating - winner_rating) / 400.0));
+        let change = K_FACTOR * (1.0 - expected);
+
+        let winner = self
+            .saved
+            .ratings
+            .entry(winner_token.to_string())
+            .or_insert_with(|| Rating::new(winner_name));
+        winner.name = winner_name.to_string();
+        winner.rating += change;
+        winner.games += 1;
+        winner.wins += 1;
+        let loser = self
+            .saved
+            .ratings
+            .entry(loser_token.to_string())
+            .or_insert_with(|| Rating::new(loser_name));
+        loser.name = loser_name.to_string();
+        loser.rating -= change;
+        loser.games += 1;
+
+        (change.round() as i32, -change.round() as i32)
+    }
+
+    fn rating_of(&self, token: &str) -> f64 {
+        self.saved
+            .ratings
+            .get(token)
+            .map_or(INITIAL_RATING, |rating| rating.rating)
+    }
+
+    /// Writes the ladder to its file, if it has one.
+    pub fn save(&self) -> io::Result<()> {
+        match &self.path {
+            Some(path) => save_json(path, &self.saved),
+            None => Ok(()),
+        }
+    }
+
+    /// Players ordered by rating, best first.
+    fn ranked(&self) -> Vec<(&PlayerToken, &Rating)> {
+        let mut ranked: Vec<_> = self.saved.ratings.iter().collect();
+        ranked.sort_by(|(_, a), (_, b)| b.rating.total_cmp(&a.rating));
+        ranked
+    }
+
+    /// The leaderboard as seen by the player holding `token`.
+    pub fn leaderboard(&self, token: Option<&str>) -> ServerMessage {
+        let ranked = self.ranked();
+        ServerMessage::Leaderboard {
+            top: ranked
+                .iter()
+                .take(LEADERBOARD_LEN)
+                .enumerate()
+                .map(|(rank, (_, rating))| entry(rank, rating))
+                .collect(),
+            own: token.and_then(|token| {
+                let rank = ranked
+                    .iter()
+                    .position(|(other, _)| other.as_str() == token)?;
+                Some(entry(rank, ranked[rank].1))
+            }),
+        }
+    }
+
+    /// Every rated player, best first.
+    pub fn entries(&self) -> Vec<LeaderboardEntry> {
+        self.ranked()
+            .into_iter()
+            .enumerate()
+            .map(|(rank, (_, rating))| entry(rank, rating))
+            .collect()
+    }
+}
+
+/// `rank` counts from 0 here, and from 1 on the leaderboard.
+fn entry(rank: usize, rating: &Rating) -> LeaderboardEntry {
+    LeaderboardEntry {
+        rank: rank as u32 + 1,
+        name: rating.name.clone(),
+        rating: rating.rating.round() as i32,
+        games: rating.games,
+        wins: rating.wins,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rules::Player;
+
+    fn joined(player_id: PlayerId) -> GameEvent {
+        GameEvent::PlayerJoined {
+            player_id,
+            player_details: Player {
+                name: format!("player {}", player_id),
+            },
+        }
+    }
+
+    fn ended(reason: EndGameReason) -> GameEvent {
+        GameEvent::EndGame { reason }
+    }
+
+    fn rated(history: &[GameEvent]) -> Option<(PlayerId, PlayerId)> {
+        let mut game_state = GameState::default();
+        for event in history {
+            game_state.consume(event);
+        }
+        rated_result(&game_state)
+    }
+
+    #[test]
+    fn equal_ratings_move_by_half_the_k_factor() {
+        let mut ladder = Ladder::in_memory();
+        assert_eq!(ladder.record(("a", "alice"), ("b", "bob")), (16, -16));
+        // Beating a weaker player is worth less
+        assert_eq!(ladder.record(("a", "alice"), ("b", "bob")), (15, -15));
+        // Beating a stronger one more
+        assert_eq!(ladder.record(("b", "bob"), ("a", "alice")), (19, -19));
+
+        let entries = ladder.entries();
+        assert_eq!(entries[0].name, "alice");
+        assert_eq!((entries[0].games, entries[0].wins), (3, 2));
+        assert_eq!(entries[0].rating + entries[1].rating, 2 * 1200);
+    }
+
+    #[test]
+    fn rates_wins_and_games_given_up_once_begun() {
+        let setup = vec![joined(1), joined(2), GameEvent::SetupBoard];
+        let begun = [
+            setup.clone(),
+            vec![GameEvent::BeginGame { first_player: 2 }],
+        ]
+        .concat();
+        let with_end = |history: &[GameEvent], reason| {
+            let mut history = history.to_vec();
+            history.push(ended(reason));
+            history
+        };
+
+        let won = with_end(&begun, EndGameReason::PlayerWon { winner: 2 });
+        assert_eq!(rated(&won), Some((2, 1)));
+        let resigned = with_end(&begun, EndGameReason::PlayerResigned { player_id: 2 });
+        assert_eq!(rated(&resigned), Some((1, 2)));
+        let left = with_end(&begun, EndGameReason::PlayerLeft { player_id: 1 });
+        assert_eq!(rated(&left), Some((2, 1)));
+
+        // Before the game began nobody played yet
+        let left_early = with_end(&setup, EndGameReason::PlayerLeft { player_id: 1 });
+        assert_eq!(rated(&left_early), None);
+        let aborted = with_end(
+            &begun,
+            EndGameReason::Aborted {
+                reason: "maintenance".to_string(),
+            },
+        );
+        assert_eq!(rated(&aborted), None);
+        assert_eq!(rated(&begun), None);
+    }
+
+    #[test]
+    fn leaderboard_ranks_and_finds_the_viewer() {
+        let mut ladder = Ladder::in_memory();
+        for loser in 0..LEADERBOARD_LEN + 5 {
+            ladder.record(("champion", "champion"), (&loser.to_string(), "loser"));
+        }
+        match ladder.leaderboard(Some("3")) {
+            ServerMessage::Leaderboard { top, own } => {
+                assert_eq!(top.len(), LEADERBOARD_LEN);
+                assert_eq!(top[0].name, "champion");
+                assert_eq!(top[0].rank, 1);
+                let own = own.unwrap();
+                assert_eq!((own.name.as_str(), own.games, own.wins), ("loser", 1, 0));
+            }
+            other => panic!("expected a leaderboard, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn issued_tokens_are_saved_with_the_ratings() {
+        let path = std::env::temp_dir().join(format!("ladder-{}.json", std::process::id()));
+        let mut ladder = Ladder::load(&path).unwrap();
+        let alice = ladder.issue_token();
+        let bob = ladder.issue_token();
+        assert!(rules::protocol::is_valid_token(&alice));
+        assert_ne!(alice, bob);
+        ladder.record((&alice, "alice"), (&bob, "bob"));
+        ladder.save().unwrap();
+
+        let loaded = Ladder::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.knows(&alice) && loaded.knows(&bob));
+        assert!(!loaded.knows("madeUpTokenOfAlice1234"));
+        assert_eq!(loaded.entries(), ladder.entries());
+    }
+}
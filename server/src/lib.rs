@@ -0,0 +1,1174 @@
+mod admin;
+mod archive;
+mod audit;
+mod clock;
+mod correspondence;
+mod ladder;
+mod limits;
+mod metrics;
+mod persist;
+mod profiles;
+pub mod room;
+mod scheduler;
+mod snapshot;
+
+use log::{debug, info, trace, warn};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+pub use admin::AdminCommand;
+pub use archive::{export, Archive, ArchivedGame, ExportFormat, GameId, GameRules};
+use audit::AuditLog;
+pub use clock::{Clock, ManualClock, SystemClock};
+use ladder::rated_result;
+pub use ladder::Ladder;
+use limits::ClientLimiter;
+pub use limits::RateLimits;
+pub use metrics::serve as serve_metrics;
+use metrics::Metrics;
+pub use profiles::Profiles;
+use room::Room;
+pub use room::SpectatorPolicy;
+use rules::names::{self, NameError};
+use rules::protocol::{
+    self, ChatLine, ChatMessage, ClientMessage, CommandId, EventSeq, HandshakeError, KickReason,
+    PlayerToken, RejectReason, Role, RoomId, ServerMessage, CHAT_CHANNEL, GAME_CHANNEL,
+    MAX_CHAT_LEN,
+};
+use rules::transport::{ServerTransport, ServerTransportEvent, TransportError};
+use rules::{EndGameReason, GameEvent, GameStage, GameState, Player, Viewer};
+pub use scheduler::TickScheduler;
+pub use snapshot::{RoomSnapshot, ServerSnapshot, RECONNECT_GRACE};
+
+/// The whole game server: rooms, handshakes and message handling on top of a [`ServerTransport`].
+/// Nothing happens on its own, the owner calls [`GameServer::step`] regularly,
+/// e.g. through a [`TickScheduler`].
+pub struct GameServer {
+    server: Box<dyn ServerTransport>,
+    settings: ServerSettings,
+    clock: Box<dyn Clock>,
+    rooms: HashMap<RoomId, Room>,
+    client_rooms: HashMap<u64, RoomId>,
+    next_room_id: RoomId,
+    // Clients that connected but have not completed the handshake yet, with their validated name
+    pending: HashMap<u64, Result<String, NameError>>,
+    // Names clients chose, before they were made unique within their room
+    names: HashMap<u64, String>,
+    // Tokens clients identified themselves with, or were issued
+    tokens: HashMap<u64, PlayerToken>,
+    // Id of the last command each client sent
+    last_commands: HashMap<u64, CommandId>,
+    // Rate limits and strikes of every client
+    limiters: HashMap<u64, ClientLimiter>,
+    // Clients to disconnect once their last messages have been sent
+    to_disconnect: Vec<u64>,
+    // Why the server is shutting down, once it is
+    shutdown: Option<String>,
+    // Whether the shutdown is a restart, the games stay as they were snapshotted
+    restarting: bool,
+    // Addresses banned from the admin console
+    banned: HashSet<IpAddr>,
+    metrics: Metrics,
+    audit: Option<AuditLog>,
+    archive: Option<Archive>,
+    ladder: Ladder,
+    profiles: Profiles,
+}
+
+/// Everything about a [`GameServer`] that can be configured.
+#[derive(Debug, Clone)]
+pub struct ServerSettings {
+    pub spectators: SpectatorPolicy,
+    pub limits: RateLimits,
+    /// Directory to write the audit log to, if any.
+    pub audit_dir: Option<PathBuf>,
+    /// Directory to keep finished games in, if any.
+    pub archive_dir: Option<PathBuf>,
+    /// Time correspondence players have for every move.
+    pub move_deadline: Duration,
+}
+
+impl GameServer {
+    pub fn new(
+        transport: impl ServerTransport + 'static,
+        settings: ServerSettings,
+        clock: impl Clock + 'static,
+    ) -> Self {
+        let audit = settings.audit_dir.clone().map(AuditLog::new);
+        let archive = settings.archive_dir.clone().map(Archive::new);
+        Self {
+            server: Box::new(transport),
+            settings,
+            clock: Box::new(clock),
+            rooms: HashMap::new(),
+            client_rooms: HashMap::new(),
+            next_room_id: 0,
+            pending: HashMap::new(),
+            names: HashMap::new(),
+            tokens: HashMap::new(),
+            last_commands: HashMap::new(),
+            limiters: HashMap::new(),
+            to_disconnect: Vec::new(),
+            shutdown: None,
+            restarting: false,
+            banned: HashSet::new(),
+            metrics: Metrics::default(),
+            audit,
+            archive,
+            ladder: Ladder::in_memory(),
+            profiles: Profiles::in_memory(),
+        }
+    }
+
+    /// Rates players on `ladder` instead of a ladder that is forgotten when the server stops.
+    pub fn with_ladder(mut self, ladder: Ladder) -> Self {
+        self.ladder = ladder;
+        self
+    }
+
+    /// Keeps career statistics in `profiles` instead of forgetting them when the server stops.
+    pub fn with_profiles(mut self, profiles: Profiles) -> Self {
+        self.profiles = profiles;
+        self
+    }
+
+    /// Advances the server by `delta`: handles connections and messages received since the
+    /// last step, releases due spectator events and sends everything out.
+    pub fn step(&mut self, delta: Duration) -> Result<(), TransportError> {
+        let now = self.clock.now();
+        self.server.update(delta)?;
+        // Renet measures rates per connection, the metrics add them up over time
+        for client_id in self.server.clients_id() {
+            if let Some(stats) = self.server.network_stats(client_id) {
+                self.metrics.transferred(&stats, delta);
+            }
+        }
+
+        self.handle_connection_events(now);
+
+        // Receive messages from clients. Broadcast valid events.
+        for client_id in self.server.clients_id().into_iter() {
+            self.receive_game_messages(client_id, now);
+            self.relay_chat(client_id, now);
+        }
+
+        self.abort_unclaimed_games(now);
+        self.expire_moves(now);
+        self.release_finished_games(now);
+
+        // Whatever was published besides the clients' commands
+        for room_id in self.rooms.keys().copied().collect::<Vec<_>>() {
+            self.audit_events(room_id);
+            self.record_if_ended(room_id, now);
+        }
+
+        // Hand spectators whatever the delay now allows them to see
+        for room in self.rooms.values_mut() {
+            for message in room.due_spectator_events(now, &self.settings.spectators.viewer()) {
+                let encoded = protocol::encode(&message);
+                for id in room.spectators.keys() {
+                    self.server.send_message(*id, GAME_CHANNEL, encoded.clone());
+                }
+            }
+        }
+
+        self.server.send_packets()?;
+        for client_id in self.to_disconnect.drain(..) {
+            self.server.disconnect(client_id);
+        }
+        Ok(())
+    }
+
+    pub fn rooms(&self) -> impl Iterator<Item = &Room> {
+        self.rooms.values()
+    }
+
+    /// Number of connected clients, including those that have not completed the handshake.
+    pub fn client_count(&self) -> usize {
+        self.server.clients_id().len()
+    }
+
+    /// Tells every client that the server is going away and turns away new ones.
+    /// Keep stepping the server for a moment afterwards so the message gets delivered,
+    /// then call [`GameServer::disconnect_all`].
+    pub fn begin_shutdown(&mut self, reason: impl Into<String>) {
+        let reason = reason.into();
+        info!("Shutting down: {}", reason);
+        let message = ServerMessage::Shutdown {
+            reason: reason.clone(),
+        };
+        for client_id in self.server.clients_id() {
+            send(&mut self.server, client_id, &message);
+        }
+        self.shutdown = Some(reason);
+    }
+
+    /// Like [`GameServer::begin_shutdown`], but tells clients to reconnect in a moment.
+    /// Commands are rejected from now on, so a [`GameServer::snapshot`] taken afterwards
+    /// holds the games in progress as they were left, and they will wait for their players
+    /// once the server is back.
+    pub fn begin_restart(&mut self) {
+        info!("Restarting");
+        for client_id in self.server.clients_id() {
+            send(&mut self.server, client_id, &ServerMessage::Restarting);
+        }
+        self.shutdown = Some("The server is restarting, try again in a moment.".to_string());
+        self.restarting = true;
+    }
+
+    /// Disconnects every remaining client and sends out the last packets.
+    pub fn disconnect_all(&mut self) -> Result<(), TransportError> {
+        for client_id in self.server.clients_id() {
+            self.server.disconnect(client_id);
+        }
+        self.server.send_packets()
+    }
+
+    /// Counts a received message against the client's rate limit. Messages over the limit
+    /// are dropped and count as a strike, as does anything sent by a client being kicked.
+    fn admit(&mut self, client_id: u64, now: Instant) -> bool {
+        if self.to_disconnect.contains(&client_id) {
+            return false;
+        }
+        let limits = &self.settings.limits;
+        let limiter = self
+            .limiters
+            .entry(client_id)
+            .or_insert_with(|| ClientLimiter::new(limits, now));
+        if limiter.allow(limits, now) {
+            return true;
+        }
+        trace!("Client {} is over the rate limit", client_id);
+        self.strike(client_id, KickReason::Flooding, now);
+        false
+    }
+
+    /// Counts a strike against a client and kicks it once it has too many.
+    fn strike(&mut self, client_id: u64, reason: KickReason, now: Instant) {
+        let limits = &self.settings.limits;
+        let limiter = self
+            .limiters
+            .entry(client_id)
+            .or_insert_with(|| ClientLimiter::new(limits, now));
+        if limiter.strike(limits, now) && !self.to_disconnect.contains(&client_id) {
+            warn!(
+                "Kicking client {} after {} strikes: {}",
+                client_id,
+                limiter.strikes(),
+                reason
+            );
+            send(&mut self.server, client_id, &ServerMessage::Kicked(reason));
+            self.to_disconnect.push(client_id);
+        }
+    }
+
+    fn handle_connection_events(&mut self, now: Instant) {
+        while let Some(event) = self.server.get_event() {
+            match event {
+                ServerTransportEvent::ClientConnected {
+                    client_id: id,
+                    user_data,
+                } => {
+                    let banned = self.server.client_addr(id).map(|addr| addr.ip());
+                    if let Some(addr) = banned.filter(|ip| self.banned.contains(ip)) {
+                        info!("Client {} connected from banned address {}", id, addr);
+                        send(
+                            &mut self.server,
+                            id,
+                            &ServerMessage::Kicked(KickReason::Banned),
+                        );
+                        self.to_disconnect.push(id);
+                        continue;
+                    }
+
+                    // The player only joins a room once it said hello with a compatible version
+                    let name = names::name_from_user_data(&user_data);
+                    match &name {
+                        Ok(name) => info!("Client {} connected as {:?}.", id, name),
+                        Err(err) => info!("Client {} connected with a bad name: {:?}", id, err),
+                    }
+                    self.pending.insert(id, name);
+
+                    if let Some(reason) = &self.shutdown {
+                        // Whoever reconnects too early tries again once the server is back
+                        let message = match self.restarting {
+                            true => ServerMessage::Restarting,
+                            false => ServerMessage::Shutdown {
+                                reason: reason.clone(),
+                            },
+                        };
+                        send(&mut self.server, id, &message);
+                        self.to_disconnect.push(id);
+                    }
+                }
+                ServerTransportEvent::ClientDisconnected { client_id: id } => {
+                    info!("Client {} disconnected", id);
+                    self.pending.remove(&id);
+                    self.last_commands.remove(&id);
+                    self.limiters.remove(&id);
+                    self.names.remove(&id);
+                    self.leave_room(id, now);
+                    self.tokens.remove(&id);
+
+                    // NOTE: Only correspondence games and games restored after a restart wait for
+                    // their players to come back. A player that drops otherwise left its game for good.
+                }
+            }
+        }
+    }
+
+    fn receive_game_messages(&mut self, client_id: u64, now: Instant) {
+        while let Some(message) = self.server.receive_message(client_id, GAME_CHANNEL) {
+            if !self.admit(client_id, now) {
+                continue;
+            }
+            let message = match protocol::decode::<ClientMessage>(&message) {
+                Ok(message) => message,
+                Err(err) => {
+                    debug!("Client {} sent an undecodable message: {}", client_id, err);
+                    if let Some(reason) = err.handshake_error() {
+                        let message = ServerMessage::HelloRejected(reason);
+                        send(&mut self.server, client_id, &message);
+                        self.to_disconnect.push(client_id);
+                    } else {
+                        self.strike(client_id, KickReason::TooManyStrikes, now);
+                    }
+                    continue;
+                }
+            };
+
+            match message {
+                ClientMessage::Hello { role, token } => {
+                    let name = match self.pending.remove(&client_id) {
+                        Some(Ok(name)) => name,
+                        Some(Err(err)) => {
+                            let message =
+                                ServerMessage::HelloRejected(HandshakeError::InvalidName(err));
+                            send(&mut self.server, client_id, &message);
+                            self.to_disconnect.push(client_id);
+                            continue;
+                        }
+                        None => continue,
+                    };
+                    // Only tokens issued here identify a player, anyone else gets a new one
+                    let token = match token {
+                        Some(token) if self.ladder.knows(&token) => token,
+                        _ => {
+                            let token = self.ladder.issue_token();
+                            if let Err(err) = self.ladder.save() {
+                                warn!("Could not save the ladder: {}", err);
+                            }
+                            let message = ServerMessage::TokenIssued {
+                                token: token.clone(),
+                            };
+                            send(&mut self.server, client_id, &message);
+                            token
+                        }
+                    };
+                    self.tokens.insert(client_id, token);
+                    self.names.insert(client_id, name);
+                    if self.reclaim_seat(client_id, role) {
+                        continue;
+                    }
+                    self.enter_room(client_id, role, None, now);
+                }
+                ClientMessage::Command { id, event } => {
+                    // Retransmits and replays of a command are only applied once
+                    let last_id = self.last_commands.entry(client_id).or_default();
+                    if id <= *last_id {
+                        trace!("Client {} replayed command {}", client_id, id);
+                        let reason = RejectReason::Duplicate;
+                        self.metrics.rejected(&event, reason);
+                        if let Some(audit) = &mut self.audit {
+                            let room_id = self.client_rooms.get(&client_id).copied();
+                            audit.command(room_id, client_id, id, &event, Some(reason), &[]);
+                        }
+                        let message = ServerMessage::Rejected { id, reason };
+                        send(&mut self.server, client_id, &message);
+                        continue;
+                    }
+                    *last_id = id;
+
+                    let reason = match self.client_rooms.get(&client_id).copied() {
+                        None => RejectReason::NotJoined,
+                        Some(_) if self.restarting => RejectReason::Restarting,
+                        Some(room_id) => {
+                            // Whatever the room published before belongs to no command
+                            self.audit_events(room_id);
+                            let room = self.rooms.get_mut(&room_id).unwrap();
+                            if room.spectators.contains_key(&client_id) {
+                                RejectReason::Spectator
+                            } else if may_send(client_id, &event)
+                                && room.game_state.validade(&event)
+                            {
+                                trace!("Player {} sent: \n\t{:#?}", client_id, event);
+                                self.metrics.accepted(&event);
+                                send(&mut self.server, client_id, &ServerMessage::Accepted { id });
+                                let policy = &self.settings.spectators;
+                                publish(&mut self.server, room, event.clone(), policy, now);
+                                advance_game(&mut self.server, room, &event, policy, now);
+                                let emitted = room.take_published();
+                                if let Some(audit) = &mut self.audit {
+                                    audit.command(
+                                        Some(room.id),
+                                        client_id,
+                                        id,
+                                        &event,
+                                        None,
+                                        &emitted,
+                                    );
+                                }
+                                continue;
+                            } else {
+                                RejectReason::InvalidEvent
+                            }
+                        }
+                    };
+                    debug!(
+                        "Client {} sent a refused command ({:?}): {:?}",
+                        client_id, reason, event
+                    );
+                    self.metrics.rejected(&event, reason);
+                    if let Some(audit) = &mut self.audit {
+                        let room_id = self.client_rooms.get(&client_id).copied();
+                        audit.command(room_id, client_id, id, &event, Some(reason), &[]);
+                    }
+                    send(
+                        &mut self.server,
+                        client_id,
+                        &ServerMessage::Rejected { id, reason },
+                    );
+                    if reason != RejectReason::Restarting {
+                        self.strike(client_id, KickReason::TooManyStrikes, now);
+                    }
+                }
+                ClientMessage::RequestSnapshot => {
+                    if let Some(room_id) = self.client_rooms.get(&client_id) {
+                        let snapshot =
+                            self.rooms[room_id].snapshot_for(client_id, &self.settings.spectators);
+                        send(&mut self.server, client_id, &snapshot);
+                    }
+                }
+                ClientMessage::RequestLeaderboard => {
+                    let token = self.tokens.get(&client_id).map(String::as_str);
+                    let leaderboard = self.ladder.leaderboard(token);
+                    send(&mut self.server, client_id, &leaderboard);
+                }
+                ClientMessage::RequestProfile => {
+                    let profile = self
+                        .tokens
+                        .get(&client_id)
+                        .and_then(|token| self.profiles.profile(token));
+                    send(
+                        &mut self.server,
+                        client_id,
+                        &ServerMessage::Profile(profile),
+                    );
+                }
+                ClientMessage::ListGames => {
+                    let games = match self.tokens.get(&client_id) {
+                        Some(token) => self.open_games(token),
+                        None => Vec::new(),
+                    };
+                    send(
+                        &mut self.server,
+                        client_id,
+                        &ServerMessage::OpenGames(games),
+                    );
+                }
+                ClientMessage::SwitchGame { room } => {
+                    if !self.switch_game(client_id, room, now) {
+                        debug!(
+                            "Client {} tried to switch to room {} without a seat there",
+                            client_id, room
+                        );
+                        self.strike(client_id, KickReason::TooManyStrikes, now);
+                    }
+                }
+                ClientMessage::Ping { sent_at } => {
+                    send(
+                        &mut self.server,
+                        client_id,
+                        &ServerMessage::Pong { sent_at },
+                    );
+                }
+                ClientMessage::Rematch => {
+                    let room = match self.client_rooms.get(&client_id) {
+                        Some(room_id) => self.rooms.get_mut(room_id).unwrap(),
+                        None => continue,
+                    };
+                    if room.game_state.stage != GameStage::Ended
+                        || !room.game_state.players.contains_key(&client_id)
+                    {
+                        debug!("Client {} asked for a rematch out of place", client_id);
+                        self.strike(client_id, KickReason::TooManyStrikes, now);
+                        continue;
+                    }
+                    if !room.rematch.insert(client_id) {
+                        continue;
+                    }
+                    let message = ServerMessage::RematchRequested { by: client_id };
+                    for id in room.clients() {
+                        send(&mut self.server, id, &message);
+                    }
+                    if room.game_state.players.len() == 2 && room.rematch.len() == 2 {
+                        restart_room(&mut self.server, room, &self.settings.spectators, now);
+                    }
+                }
+                ClientMessage::LeaveRoom => {
+                    // Spectators may move on at any time, players once their game is over.
+                    // Correspondence games keep the seat of players that move on to another.
+                    let room_id = self.client_rooms.get(&client_id).copied();
+                    let allowed = room_id.is_some_and(|room_id| {
+                        let room = &self.rooms[&room_id];
+                        room.spectators.contains_key(&client_id)
+                            || room.correspondence.is_some()
+                            || room.game_state.stage == GameStage::Ended
+                    });
+                    if !allowed {
+                        debug!("Client {} tried to leave its room mid-game", client_id);
+                        self.strike(client_id, KickReason::TooManyStrikes, now);
+                        continue;
+                    }
+                    if let Some(role) = self.leave_room(client_id, now) {
+                        self.enter_room(client_id, role, room_id, now);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Puts a client in a room, never the one it just `left`, under a name unique to that room.
+    /// Correspondence players wait for an opponent in one game at most, and go back to it.
+    fn enter_room(&mut self, client_id: u64, role: Role, left: Option<RoomId>, now: Instant) {
+        if role == Role::Correspondence {
+            let waiting = self
+                .tokens
+                .get(&client_id)
+                .and_then(|token| self.waiting_game(token));
+            if let Some(room_id) = waiting {
+                self.take_seat(client_id, room_id);
+                return;
+            }
+        }
+        let name = self.names.get(&client_id).cloned().unwrap_or_default();
+        let room_id = pick_room(
+            &mut self.rooms,
+            &mut self.next_room_id,
+            role,
+            left,
+            self.tokens.get(&client_id).map(String::as_str),
+            self.settings.move_deadline,
+            now,
+        );
+        let room = self.rooms.get_mut(&room_id).unwrap();
+        let player = Player {
+            name: room.unique_name(&name),
+        };
+        if player.name != name {
+            info!(
+                "Client {} goes by {:?}, {:?} is taken",
+                client_id, player.name, name
+            );
+        }
+        if let (Role::Player | Role::Correspondence, Some(token)) =
+            (role, self.tokens.get(&client_id))
+        {
+            room.tokens.insert(client_id, token.clone());
+        }
+        match role {
+            Role::Player | Role::Correspondence => join_room(
+                &mut self.server,
+                room,
+                client_id,
+                role,
+                player,
+                &self.settings.spectators,
+                now,
+            ),
+            Role::Spectator => spectate_room(
+                &mut self.server,
+                room,
+                client_id,
+                player,
+                &self.settings.spectators,
+            ),
+        }
+        self.client_rooms.insert(client_id, room_id);
+    }
+
+    /// Seats a client back in a game that awaits its token, if there is one: a game restored
+    /// after a restart for players, their most urgent correspondence game for correspondence
+    /// players.
+    fn reclaim_seat(&mut self, client_id: u64, role: Role) -> bool {
+        let token = match self.tokens.get(&client_id) {
+            Some(token) => token,
+            None => return false,
+        };
+        let room_id = match role {
+            Role::Player => self
+                .rooms
+                .values()
+                .find(|room| room.correspondence.is_none() && room.awaiting.contains_key(token))
+                .map(|room| room.id),
+            Role::Correspondence => self.awaiting_game(token),
+            Role::Spectator => None,
+        };
+        match room_id {
+            Some(room_id) => {
+                self.take_seat(client_id, room_id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Gives a client the seat `room_id` keeps for its token.
+    fn take_seat(&mut self, client_id: u64, room_id: RoomId) {
+        let token = &self.tokens[&client_id];
+        let room = self.rooms.get_mut(&room_id).unwrap();
+        let old_id = room.reclaim_seat(token, client_id).unwrap();
+        info!(
+            "Client {} is back in room {}, it was {} before",
+            client_id, room.id, old_id
+        );
+        self.client_rooms.insert(client_id, room.id);
+        let welcome = ServerMessage::Welcome {
+            player_id: client_id,
+            room: room.id,
+            role: match room.correspondence {
+                Some(_) => Role::Correspondence,
+                None => Role::Player,
+            },
+        };
+        send(&mut self.server, client_id, &welcome);
+        // The player's id changed, everyone starts over from the renamed game
+        for id in room.clients() {
+            send(
+                &mut self.server,
+                id,
+                &room.snapshot_for(id, &self.settings.spectators),
+            );
+        }
+    }
+
+    /// Ends the restored games whose players did not all come back in time.
+    fn abort_unclaimed_games(&mut self, now: Instant) {
+        let expired: Vec<RoomId> = self
+            .rooms
+            .values()
+            .filter(|room| matches!(room.awaiting_until, Some(until) if until <= now))
+            .map(|room| room.id)
+            .collect();
+        for room_id in expired {
+            let room = self.rooms.get_mut(&room_id).unwrap();
+            info!(
+                "Room {}: {} players did not come back after the restart",
+                room_id,
+                room.awaiting.len()
+            );
+            room.awaiting_until = None;
+            for (_, player_id) in room.awaiting.drain().collect::<Vec<_>>() {
+                let event = GameEvent::PlayerDisconnected { player_id };
+                publish(
+                    &mut self.server,
+                    room,
+                    event,
+                    &self.settings.spectators,
+                    now,
+                );
+            }
+            if room.game_state.stage != GameStage::Ended {
+                let event = GameEvent::EndGame {
+                    reason: EndGameReason::Aborted {
+                        reason: "Your opponent did not come back after the server restarted"
+                            .to_string(),
+                    },
+                };
+                publish(
+                    &mut self.server,
+                    room,
+                    event,
+                    &self.settings.spectators,
+                    now,
+                );
+            }
+
+            if room.is_empty() {
+                self.close_room(room_id, now);
+            }
+        }
+    }
+
+    /// Takes a client out of its room and returns the role it had there.
+    /// A player leaving a game that is not over yet ends it, unless it is a correspondence
+    /// game: those keep the seat until the player comes back.
+    fn leave_room(&mut self, client_id: u64, now: Instant) -> Option<Role> {
+        let room_id = self.client_rooms.remove(&client_id)?;
+        let room = self.rooms.get_mut(&room_id).unwrap();
+        let kept_for = self
+            .tokens
+            .get(&client_id)
+            .filter(|_| room.correspondence.is_some() && room.game_state.stage != GameStage::Ended);
+
+        let role = if room.spectators.remove(&client_id).is_some() {
+            Role::Spectator
+        } else if let Some(token) = kept_for {
+            room.keep_seat(client_id, token);
+            info!(
+                "Client {} left room {}, its seat is kept",
+                client_id, room_id
+            );
+            Role::Correspondence
+        } else {
+            room.rematch.remove(&client_id);
+            // First consume a disconnect event
+            let event = GameEvent::PlayerDisconnected {
+                player_id: client_id,
+            };
+            publish(
+                &mut self.server,
+                room,
+                event,
+                &self.settings.spectators,
+                now,
+            );
+
+            // Then end the game
+            if room.game_state.stage != GameStage::Ended {
+                let event = GameEvent::EndGame {
+                    reason: EndGameReason::PlayerLeft {
+                        player_id: client_id,
+                    },
+                };
+                publish(
+                    &mut self.server,
+                    room,
+                    event,
+                    &self.settings.spectators,
+                    now,
+                );
+            }
+            match room.correspondence {
+                Some(_) => Role::Correspondence,
+                None => Role::Player,
+            }
+        };
+
+        if room.is_empty() {
+            self.close_room(room_id, now);
+        }
+        Some(role)
+    }
+
+    /// Removes a room nobody is left in, after logging and recording what happened last.
+    fn close_room(&mut self, room_id: RoomId, now: Instant) {
+        self.audit_events(room_id);
+        self.record_if_ended(room_id, now);
+        self.rooms.remove(&room_id);
+        trace!("Room {} closed", room_id);
+    }
+
+    /// Writes the events a room published since the last time to the audit log.
+    fn audit_events(&mut self, room_id: RoomId) {
+        if let (Some(audit), Some(room)) = (&mut self.audit, self.rooms.get_mut(&room_id)) {
+            audit.events(room_id, &room.take_published());
+        }
+    }
+
+    /// Stores a room's game in the archive and rates it on the ladder once it is over, which
+    /// it was at `now`.
+    fn record_if_ended(&mut self, room_id: RoomId, now: Instant) {
+        let room = match self.rooms.get_mut(&room_id) {
+            Some(room) if !room.recorded && room.game_state.stage == GameStage::Ended => room,
+            _ => return,
+        };
+        room.recorded = true;
+
+        if let Some(archive) = &mut self.archive {
+            if let Some(game) = ArchivedGame::from_room(room, &self.settings.spectators, now) {
+                match archive.store(game) {
+                    Ok(id) => info!("Archived the game of room {} as game {}", room_id, id),
+                    Err(err) => warn!("Could not archive the game of room {}: {}", room_id, err),
+                }
+            }
+        }
+
+        // Playing against yourself from a second connection counts for neither the profiles
+        // nor the ladder
+        let players: Vec<_> = room
+            .game_state
+            .joined_players()
+            .into_iter()
+            .filter_map(|(player_id, player)| {
+                Some((
+                    player_id,
+                    player.name.as_str(),
+                    room.tokens.get(&player_id)?,
+                ))
+            })
+            .collect();
+        if let [(_, _, first), (_, _, second)] = players[..] {
+            if first == second {
+                return;
+            }
+        }
+        let result = rated_result(&room.game_state);
+
+        for (player_id, _, token) in &players {
+            self.profiles
+                .record(token, *player_id, &room.game_state, result);
+        }
+        if !players.is_empty() {
+            if let Err(err) = self.profiles.save() {
+                warn!("Could not save the profiles: {}", err);
+            }
+        }
+
+        let (winner, loser) = match result {
+            Some((winner, loser)) => (winner, loser),
+            None => return,
+        };
+        let identity = |player_id| {
+            players
+                .iter()
+                .find(|(id, _, _)| *id == player_id)
+                .map(|(_, name, token)| (token.as_str(), *name))
+        };
+        let (winner_identity, loser_identity) = match (identity(winner), identity(loser)) {
+            (Some(winner), Some(loser)) => (winner, loser),
+            _ => return,
+        };
+        let (won, lost) = self.ladder.record(winner_identity, loser_identity);
+        info!(
+            "Rated the game of room {}: {} {:+}, {} {:+}",
+            room_id, winner, won, loser, lost
+        );
+        if let Err(err) = self.ladder.save() {
+            warn!("Could not save the ladder: {}", err);
+        }
+    }
+
+    /// Relays chat to the sender's room.
+    fn relay_chat(&mut self, client_id: u64, now: Instant) {
+        while let Some(message) = self.server.receive_message(client_id, CHAT_CHANNEL) {
+            if !self.admit(client_id, now) {
+                continue;
+            }
+            let chat = match protocol::decode::<ChatMessage>(&message) {
+                Ok(chat) => chat,
+                Err(err) => {
+                    debug!(
+                        "Client {} sent an undecodable chat message: {}",
+                        client_id, err
+                    );
+                    self.strike(client_id, KickReason::TooManyStrikes, now);
+                    continue;
+                }
+            };
+            let room = match self.client_rooms.get(&client_id) {
+                Some(room_id) => &self.rooms[room_id],
+                None => continue,
+            };
+            let text: String = chat.text.trim().chars().take(MAX_CHAT_LEN).collect();
+            if text.is_empty() {
+                continue;
+            }
+            let line = ChatLine {
+                from: client_id,
+                name: room.name_of(client_id).unwrap_or_default().to_string(),
+                text,
+            };
+            let encoded = protocol::encode(&line);
+            for id in room.clients() {
+                self.server.send_message(id, CHAT_CHANNEL, encoded.clone());
+            }
+        }
+    }
+}
+
+/// Picks the room a client joins. Players take the first free seat and spectators watch
+/// the newest room with players. Correspondence players only meet each other, never in a game
+/// they already play with their `token`. A new room is opened at `now` when none fits,
+/// correspondence rooms giving `move_deadline` for every move. Never picks `skip`.
+fn pick_room(
+    rooms: &mut HashMap<RoomId, Room>,
+    next_room_id: &mut RoomId,
+    role: Role,
+    skip: Option<RoomId>,
+    token: Option<&str>,
+    move_deadline: Duration,
+    now: Instant,
+) -> RoomId {
+    let mut candidates = rooms.values().filter(|room| {
+        Some(room.id) != skip && room.correspondence.is_some() == (role == Role::Correspondence)
+    });
+    let existing = match role {
+        Role::Player => candidates.find(|room| room.has_free_seat()),
+        Role::Correspondence => candidates.find(|room| {
+            room.has_free_seat()
+                && token.is_none_or(|token| room.player_with_token(token).is_none())
+        }),
+        Role::Spectator => candidates
+            .clone()
+            .filter(|room| !room.game_state.players.is_empty())
+            .max_by_key(|room| room.id)
+            .or_else(|| candidates.find(|room| room.has_free_seat())),
+    };
+    match existing {
+        Some(room) => room.id,
+        None => {
+            *next_room_id += 1;
+            let room = match role {
+                Role::Correspondence => Room::correspondence(*next_room_id, move_deadline, now),
+                Role::Player | Role::Spectator => Room::new(*next_room_id, now),
+            };
+            rooms.insert(*next_room_id, room);
+            trace!("Room {} opened", next_room_id);
+            *next_room_id
+        }
+    }
+}
+
+/// Seats a client that completed the handshake as a player, `role` telling which kind.
+fn join_room(
+    server: &mut dyn ServerTransport,
+    room: &mut Room,
+    id: u64,
+    role: Role,
+    player: Player,
+    policy: &SpectatorPolicy,
+    now: Instant,
+) {
+    let welcome = ServerMessage::Welcome {
+        player_id: id,
+        room: room.id,
+        role,
+    };
+    send(server, id, &welcome);
+
+    // Catch the recently joined player up with the game so far
+    let message = ServerMessage::Snapshot {
+        seq: room.game_state.history.len() as EventSeq,
+        state: room.game_state.redacted_for(&Viewer::Player(id)),
+    };
+    send(server, id, &message);
+
+    // Add the new player to the game and tell everyone about it
+    let event = GameEvent::PlayerJoined {
+        player_id: id,
+        player_details: player,
+    };
+    publish(server, room, event, policy, now);
+    info!("Client {} joined room {}", id, room.id);
+
+    // Begin game with two players
+    // TODO: implement "start button in lobby"
+    if room.game_state.players.len() == 2 {
+        publish(server, room, GameEvent::SetupBoard, policy, now);
+        trace!("Player setup ship positions");
+    }
+}
+
+/// Adds a client that completed the handshake as a spectator and catches it up
+/// with a snapshot of everything the spectator delay already lets it see.
+fn spectate_room(
+    server: &mut dyn ServerTransport,
+    room: &mut Room,
+    id: u64,
+    player: Player,
+    policy: &SpectatorPolicy,
+) {
+    let welcome = ServerMessage::Welcome {
+        player_id: id,
+        room: room.id,
+        role: Role::Spectator,
+    };
+    send(server, id, &welcome);
+    room.spectators.insert(id, player);
+    send(server, id, &room.snapshot_for(id, policy));
+    info!("Client {} is spectating room {}", id, room.id);
+}
+
+/// Whether a client may ask for `event` at all. Players place their ships, shoot and resign
+/// for themselves, everything else only happens on the server's behalf.
+fn may_send(client_id: u64, event: &GameEvent) -> bool {
+    match event {
+        GameEvent::ShipPlaced { player_id, .. }
+        | GameEvent::ShipMove { player_id, .. }
+        | GameEvent::EndGame {
+            reason: EndGameReason::PlayerResigned { player_id },
+        } => *player_id == client_id,
+        _ => false,
+    }
+}
+
+/// Publishes what the rules make of a player's `event`: the game begins once the last ship
+/// of both fleets is placed, the player who placed it moving second, and ends once a fleet
+/// is sunk.
+fn advance_game(
+    server: &mut dyn ServerTransport,
+    room: &mut Room,
+    event: &GameEvent,
+    policy: &SpectatorPolicy,
+    now: Instant,
+) {
+    let follow_up = match event {
+        GameEvent::ShipPlaced { player_id, .. } => GameEvent::BeginGame {
+            first_player: *player_id,
+        },
+        GameEvent::ShipMove { .. } => match room.game_state.determine_winner() {
+            Some(winner) => GameEvent::EndGame {
+                reason: EndGameReason::PlayerWon { winner },
+            },
+            None => return,
+        },
+        _ => return,
+    };
+    if room.game_state.validade(&follow_up) {
+        info!("Room {}: {:?}", room.id, follow_up);
+        publish(server, room, follow_up, policy, now);
+    }
+}
+
+/// Starts a new game in a room whose players both asked for a rematch.
+fn restart_room(
+    server: &mut dyn ServerTransport,
+    room: &mut Room,
+    policy: &SpectatorPolicy,
+    now: Instant,
+) {
+    info!("Room {} starts a rematch", room.id);
+    let clients = room.clients();
+    let mut players: Vec<_> = room.restart(now).into_iter().collect();
+    players.sort_by_key(|(player_id, _)| *player_id);
+
+    // Everyone starts over from an empty game, then the players join it again
+    let fresh = ServerMessage::Snapshot {
+        seq: 0,
+        state: GameState::default(),
+    };
+    for client_id in clients {
+        send(server, client_id, &fresh);
+    }
+    for (player_id, player_details) in players {
+        let event = GameEvent::PlayerJoined {
+            player_id,
+            player_details,
+        };
+        publish(server, room, event, policy, now);
+    }
+    publish(server, room, GameEvent::SetupBoard, policy, now);
+}
+
+/// Consumes a validated event and sends it to the room:
+/// players that may see it get it right away, spectators once the spectator delay has passed.
+/// When the game ends players also get a snapshot revealing every fleet.
+fn publish(
+    server: &mut dyn ServerTransport,
+    room: &mut Room,
+    event: GameEvent,
+    policy: &SpectatorPolicy,
+    now: Instant,
+) {
+    room.game_state.consume(&event);
+    room.record_published(event.clone(), now);
+    let ends_game = matches!(event, GameEvent::EndGame { .. });
+    for client_id in room.players() {
+        let viewer = Viewer::Player(client_id);
+        if event.is_visible_to(&viewer) {
+            let message = ServerMessage::Event {
+                seq: room.game_state.history.len() as EventSeq,
+                event: event.clone(),
+                checksum: room::view_of(&room.game_state, &viewer).checksum(),
+            };
+            send(server, client_id, &message);
+        }
+    }
+    if ends_game {
+        for client_id in room.players() {
+            send(server, client_id, &room.snapshot_for(client_id, policy));
+        }
+    }
+    room.queue_for_spectators(now + policy.delay, event);
+}
+
+fn send(server: &mut dyn ServerTransport, client_id: u64, message: &ServerMessage) {
+    server.send_message(client_id, GAME_CHANNEL, protocol::encode(message));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rules::hex::CubeCoords;
+    use rules::ships::GameObject;
+
+    const ALICE: u64 = 1;
+    const BOB: u64 = 2;
+
+    fn placed(player_id: u64) -> GameEvent {
+        GameEvent::ShipPlaced {
+            player_id,
+            ship_type: GameObject::Boat,
+            at: CubeCoords::ZERO,
+            rotation: 0,
+        }
+    }
+
+    fn moved(player_id: u64) -> GameEvent {
+        GameEvent::ShipMove {
+            player_id,
+            at: CubeCoords::ZERO,
+        }
+    }
+
+    fn resigned(player_id: u64) -> GameEvent {
+        GameEvent::EndGame {
+            reason: EndGameReason::PlayerResigned { player_id },
+        }
+    }
+
+    #[test]
+    fn players_place_shoot_and_resign_for_themselves() {
+        assert!(may_send(ALICE, &placed(ALICE)));
+        assert!(may_send(ALICE, &moved(ALICE)));
+        assert!(may_send(ALICE, &resigned(ALICE)));
+        assert!(!may_send(ALICE, &placed(BOB)));
+        assert!(!may_send(ALICE, &moved(BOB)));
+        assert!(!may_send(ALICE, &resigned(BOB)));
+    }
+
+    #[test]
+    fn everything_else_happens_on_the_servers_behalf() {
+        let forbidden = [
+            GameEvent::PlayerJoined {
+                player_id: ALICE,
+                player_details: Player {
+                    name: "alice".into(),
+                },
+            },
+            GameEvent::SetupBoard,
+            GameEvent::BeginGame {
+                first_player: ALICE,
+            },
+            GameEvent::EndGame {
+                reason: EndGameReason::PlayerWon { winner: ALICE },
+            },
+            GameEvent::EndGame {
+                reason: EndGameReason::PlayerLeft { player_id: BOB },
+            },
+            GameEvent::EndGame {
+                reason: EndGameReason::Aborted {
+                    reason: "cheating".into(),
+                },
+            },
+            GameEvent::PlayerDisconnected { player_id: ALICE },
+            GameEvent::PlayerDisconnected { player_id: BOB },
+        ];
+        for event in forbidden {
+            assert!(!may_send(ALICE, &event), "{:?}", event);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::time::{Duration, Instant};
+
+/// How much a single client may send before the server stops listening to it.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimits {
+    /// Messages per second a client may send on average, game and chat together.
+    pub messages_per_sec: u32,
+    /// Messages a client may send at once after having been quiet.
+    pub burst: u32,
+    /// Strikes after which a client is kicked. Every refused command or undecodable message
+    /// is a strike, and so is every message over the rate limit.
+    pub max_strikes: u32,
+    /// How long it takes for one strike to be forgiven.
+    pub strike_decay: Duration,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            messages_per_sec: 20,
+            burst: 40,
+            max_strikes: 10,
+            strike_decay: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Rate limit and strike counter of one client.
+#[derive(Debug)]
+pub struct ClientLimiter {
+    /// Messages the client may still send right now, refilled over time.
+    tokens: f64,
+    strikes: u32,
+    updated: Instant,
+    last_strike: Instant,
+}
+
+impl ClientLimiter {
+    pub fn new(limits: &RateLimits, now: Instant) -> Self {
+        Self {
+            tokens: limits.burst as f64,
+            strikes: 0,
+            updated: now,
+            last_strike: now,
+        }
+    }
+
+    /// Takes a token for a received message. Returns false if the client is over the limit,
+    /// in which case the message should be dropped.
+    pub fn allow(&mut self, limits: &RateLimits, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * limits.messages_per_sec as f64).min(limits.burst as f64);
+        self.updated = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// Counts a strike against the client. Returns true once it has to be kicked.
+    pub fn strike(&mut self, limits: &RateLimits, now: Instant) -> bool {
+        if !limits.strike_decay.is_zero() {
+            let forgiven = (now
+                .saturating_duration_since(self.last_strike)
+                .as_secs_f64()
+                / limits.strike_decay.as_secs_f64()) as u32;
+            if forgiven < self.strikes {
+                // The part of a period that has passed still counts towards the next strike
+                self.strikes -= forgiven;
+                self.last_strike += limits.strike_decay * forgiven;
+            } else {
+                self.strikes = 0;
+                self.last_strike = now;
+            }
+        }
+        self.strikes += 1;
+        self.strikes >= limits.max_strikes
+    }
+
+    pub fn strikes(&self) -> u32 {
+        self.strikes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: RateLimits = RateLimits {
+        messages_per_sec: 10,
+        burst: 3,
+        max_strikes: 3,
+        strike_decay: Duration::from_secs(10),
+    };
+
+    #[test]
+    fn allows_bursts_and_refills_over_time() {
+        let start = Instant::now();
+        let mut limiter = ClientLimiter::new(&LIMITS, start);
+        for _ in 0..LIMITS.burst {
+            assert!(limiter.allow(&LIMITS, start));
+        }
+        assert!(!limiter.allow(&LIMITS, start));
+
+        let later = start + Duration::from_millis(100);
+        assert!(limiter.allow(&LIMITS, later));
+        assert!(!limiter.allow(&LIMITS, later));
+    }
+
+    #[test]
+    fn kicks_after_too_many_strikes() {
+        let start = Instant::now();
+        let mut limiter = ClientLimiter::new(&LIMITS, start);
+        assert!(!limiter.strike(&LIMITS, start));
+        assert!(!limiter.strike(&LIMITS, start));
+        assert!(limiter.strike(&LIMITS, start));
+        assert_eq!(limiter.strikes(), 3);
+    }
+
+    #[test]
+    fn forgives_one_strike_per_whole_decay_period() {
+        let start = Instant::now();
+        let mut limiter = ClientLimiter::new(&LIMITS, start);
+        limiter.strike(&LIMITS, start);
+        limiter.strike(&LIMITS, start);
+
+        // One and a half periods forgive a single strike...
+        limiter.strike(&LIMITS, start + LIMITS.strike_decay * 3 / 2);
+        assert_eq!(limiter.strikes(), 2);
+        // ...and the half period left over counts towards the next one
+        limiter.strike(&LIMITS, start + LIMITS.strike_decay * 2);
+        assert_eq!(limiter.strikes(), 2);
+
+        // Quiet clients start over
+        limiter.strike(&LIMITS, start + LIMITS.strike_decay * 10);
+        assert_eq!(limiter.strikes(), 1);
+    }
+}
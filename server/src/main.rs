@@ -1,18 +1,101 @@
+use clap::Parser;
 use log::{info, trace, warn, LevelFilter};
-use renet::{
-    RenetConnectionConfig, RenetServer, ServerAuthentication, ServerConfig, ServerEvent,
-    NETCODE_USER_DATA_BYTES,
-};
+use polling::{Event, Poller};
+use renet::{RenetServer, ServerAuthentication, ServerConfig};
 use std::net::{SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 use std::time::{Duration, Instant, SystemTime};
 
-use store::{EndGameReason, Player};
+use rules::protocol::{self, PROTOCOL_ID};
+use server::{
+    AdminCommand, GameServer, Ladder, Profiles, RateLimits, ServerSettings, ServerSnapshot,
+    SpectatorPolicy, SystemClock, TickScheduler,
+};
+
+/// How long clients get to receive the shutdown notice before they are disconnected.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(1);
+
+/// How often the metrics served over HTTP are brought up to date.
+const METRICS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Parser, Debug)]
+#[command(name = "server", about = "BattleGrounds! server")]
+struct Args {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:5000")]
+    addr: SocketAddr,
+
+    /// Seconds spectators lag behind the players
+    #[arg(long, default_value_t = 0)]
+    spectator_delay: u64,
+
+    /// Show ship placements to spectators
+    #[arg(long)]
+    reveal_fleets: bool,
+
+    /// Simulation ticks per second. Packets are handled as they arrive regardless
+    #[arg(long, default_value_t = 20)]
+    tick_rate: u32,
+
+    /// Messages per second a client may send on average
+    #[arg(long, default_value_t = 20)]
+    max_messages_per_sec: u32,
+
+    /// Messages a client may send at once after having been quiet
+    #[arg(long, default_value_t = 40)]
+    message_burst: u32,
+
+    /// Strikes (refused commands, garbage or flooding) after which a client is kicked
+    #[arg(long, default_value_t = 10)]
+    max_strikes: u32,
+
+    /// Seconds it takes for one strike to be forgiven
+    #[arg(long, default_value_t = 30)]
+    strike_decay: u64,
 
-// Only clients that can provide the same PROTOCOL_ID that the server is using will be able to connect.
-// This can be used to make sure players use the most recent version of the client for instance.
-pub const PROTOCOL_ID: u64 = 1208;
+    /// Serve Prometheus metrics on http://<addr>/metrics, e.g. 127.0.0.1:9100
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Write a JSON-lines audit log of every command and game event to this directory,
+    /// one file per day
+    #[arg(long)]
+    audit_dir: Option<PathBuf>,
+
+    /// Keep every finished game in this directory, see the archive and export console commands
+    #[arg(long)]
+    archive_dir: Option<PathBuf>,
+
+    /// Keep issued player tokens and ratings in this file, otherwise they are lost when the
+    /// server stops
+    #[arg(long)]
+    ladder_file: Option<PathBuf>,
+
+    /// Keep player career statistics in this file, otherwise they are lost when the server stops
+    #[arg(long)]
+    profiles_file: Option<PathBuf>,
+
+    /// Save the games in progress to this file and resume them from it on startup.
+    /// Stopping the server then tells clients to reconnect instead of leaving.
+    /// Correspondence games only outlive the server with a snapshot file
+    #[arg(long)]
+    snapshot_file: Option<PathBuf>,
+
+    /// Seconds between two snapshots of the games in progress
+    #[arg(long, default_value_t = 10)]
+    snapshot_interval: u64,
+
+    /// Hours correspondence players have for every move before they lose the game
+    #[arg(long, default_value_t = 72)]
+    move_deadline_hours: u64,
+}
+
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
 
-fn main() {
     let target = env_logger::Target::Stdout;
     let mut builder = env_logger::Builder::from_default_env();
     builder
@@ -20,8 +103,10 @@ fn main() {
         .filter(None, LevelFilter::Trace)
         .init();
 
-    let server_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
-    let mut server: RenetServer = RenetServer::new(
+    let server_addr = args.addr;
+    // Renet reads from one handle, the poller waits on the other for packets to arrive
+    let socket = UdpSocket::bind(server_addr)?;
+    let server: RenetServer = RenetServer::new(
         SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap(),
@@ -29,114 +114,162 @@ fn main() {
             max_clients: 10,
             protocol_id: PROTOCOL_ID,
             public_addr: server_addr,
+            // Players identify themselves with a token issued by the server, see `PlayerToken`
             authentication: ServerAuthentication::Unsecure,
         },
-        RenetConnectionConfig::default(),
-        UdpSocket::bind(server_addr).unwrap(),
-    )
-    .unwrap();
+        protocol::connection_config(),
+        socket.try_clone()?,
+    )?;
 
     trace!("GW server listening on {}", server_addr);
+    info!("Type help for the admin console's commands");
+
+    let settings = ServerSettings {
+        spectators: SpectatorPolicy {
+            delay: Duration::from_secs(args.spectator_delay),
+            reveal_fleets: args.reveal_fleets,
+        },
+        limits: RateLimits {
+            messages_per_sec: args.max_messages_per_sec,
+            burst: args.message_burst,
+            max_strikes: args.max_strikes,
+            strike_decay: Duration::from_secs(args.strike_decay),
+        },
+        audit_dir: args.audit_dir,
+        archive_dir: args.archive_dir,
+        move_deadline: Duration::from_secs(args.move_deadline_hours * 60 * 60),
+    };
+    let mut game_server = GameServer::new(server, settings, SystemClock);
+    if let Some(path) = &args.ladder_file {
+        game_server = game_server.with_ladder(Ladder::load(path)?);
+    }
+    if let Some(path) = &args.profiles_file {
+        game_server = game_server.with_profiles(Profiles::load(path)?);
+    }
+    if let Some(path) = &args.snapshot_file {
+        game_server = game_server.with_snapshot(ServerSnapshot::load(path)?);
+    }
+    let snapshot_interval = Duration::from_secs(args.snapshot_interval.max(1));
+    let mut snapshot_taken = Instant::now();
+    let tick = Duration::from_secs(1) / args.tick_rate.max(1);
+    let mut scheduler = TickScheduler::new(tick, Instant::now());
 
-    let mut last_updated = Instant::now();
-    let mut game_state = store::GameState::default();
+    let poller = Arc::new(Poller::new()?);
+    poller.add(&socket, Event::readable(0))?;
 
-    loop {
-        // Update server time
-        let now = Instant::now();
-        server.update(now - last_updated).unwrap();
-        last_updated = now;
-
-        // Receive connection events from clients
-        while let Some(event) = server.get_event() {
-            match event {
-                ServerEvent::ClientConnected(id, user_data) => {
-                    // Tell the recently joined player about the other player
-                    for (player_id, player) in game_state.players.iter() {
-                        let event = store::GameEvent::PlayerJoined {
-                            player_id: *player_id,
-                            player_details: player.clone(),
-                        };
-                        server.send_message(id, 0, bincode::serialize(&event).unwrap());
-                    }
-
-                    // Add the new player to the game
-                    let event = store::GameEvent::PlayerJoined {
-                        player_id: id,
-                        player_details: name_from_user_data(&user_data),
-                    };
-                    game_state.consume(&event);
-
-                    // Tell all players that a new player has joined
-                    server.broadcast_message(0, bincode::serialize(&event).unwrap());
-
-                    info!("Client {} connected.", id);
-
-                    // Begin game with two players
-                    // TODO: implement "start button in lobby"
-                    // TODO: players may join in the middle of a game
-                    if game_state.players.len() == 2 {
-                        let event = store::GameEvent::SetupBoard;
-                        game_state.consume(&event);
-                        server.broadcast_message(0, bincode::serialize(&event).unwrap());
-                        trace!("Player setup ship positions");
-                    }
-                }
-                ServerEvent::ClientDisconnected(id) => {
-                    // First consume a disconnect event
-                    let event = store::GameEvent::PlayerDisconnected { player_id: id };
-                    game_state.consume(&event);
-                    server.broadcast_message(0, bincode::serialize(&event).unwrap());
-                    info!("Client {} disconnected", id);
-
-                    // Then end the game
-                    let event = store::GameEvent::EndGame {
-                        reason: EndGameReason::PlayerLeft { player_id: id },
-                    };
-                    game_state.consume(&event);
-                    server.broadcast_message(0, bincode::serialize(&event).unwrap());
-
-                    // NOTE: Since we don't authenticate users we can't do any reconnection attempts.
-                    // We simply have no way to know if the next user is the same as the one that disconnected.
+    // Ctrl-C and SIGTERM only raise a flag and wake up the loop, which then shuts down cleanly
+    let stop = Arc::new(AtomicBool::new(false));
+    {
+        let stop = stop.clone();
+        let poller = poller.clone();
+        ctrlc::set_handler(move || {
+            stop.store(true, Ordering::SeqCst);
+            let _ = poller.notify();
+        })
+        .map_err(std::io::Error::other)?;
+    }
+    let mut shutdown_deadline = None;
+
+    // Rendered by the loop, served as is by the metrics thread
+    let metrics = match args.metrics_addr {
+        Some(addr) => {
+            let metrics = Arc::new(Mutex::new(String::new()));
+            server::serve_metrics(addr, metrics.clone())?;
+            Some(metrics)
+        }
+        None => None,
+    };
+    let mut metrics_rendered = Instant::now() - METRICS_INTERVAL;
+
+    // Console commands are read on their own thread and wake up the loop like packets do
+    let (console, commands) = mpsc::channel();
+    {
+        let poller = poller.clone();
+        thread::spawn(move || {
+            for line in std::io::stdin().lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if console.send(line).is_err() {
+                    break;
                 }
+                let _ = poller.notify();
+            }
+        });
+    }
+
+    let mut events = Vec::new();
+    loop {
+        // Sleep until a packet arrives or the next tick is due
+        events.clear();
+        poller.wait(&mut events, Some(scheduler.timeout(Instant::now())))?;
+        if !events.is_empty() {
+            poller.modify(&socket, Event::readable(0))?;
+        }
+
+        for line in commands.try_iter() {
+            match AdminCommand::parse(&line) {
+                Ok(None) => {}
+                Ok(Some(AdminCommand::Shutdown)) => stop.store(true, Ordering::SeqCst),
+                Ok(Some(command)) => match game_server.admin(command) {
+                    Ok(output) => println!("{}", output),
+                    Err(err) => println!("error: {}", err),
+                },
+                Err(err) => println!("error: {}", err),
             }
         }
 
-        // Receive GameEvents from clients. Broadcast valid events.
-        for client_id in server.clients_id().into_iter() {
-            while let Some(message) = server.receive_message(client_id, 0) {
-                if let Ok(event) = bincode::deserialize::<store::GameEvent>(&message) {
-                    if game_state.validade(&event) {
-                        game_state.consume(&event);
-                        trace!("Player {} sent: \n\t{:#?}", client_id, event);
-                        server.broadcast_message(0, bincode::serialize(&event).unwrap());
-
-                        // Determine if a player has won the game
-                        // if let Some(winner) = game_state.determine_winner() {
-                        //     let event = store::GameEvent::EndGame {
-                        //         reason: store::EndGameReason::PlayerWon { winner },
-                        //     };
-                        //     server.broadcast_message(0, bincode::serialize(&event).unwrap());
-                        // }
-                    } else {
-                        warn!("Player {} sent invalid event:\n\t{:#?}", client_id, event);
-                    }
+        if shutdown_deadline.is_none() && stop.load(Ordering::SeqCst) {
+            match &args.snapshot_file {
+                // Nothing moves once the restart began, and saved before anyone leaves,
+                // leaving would end the games
+                Some(path) => {
+                    game_server.begin_restart();
+                    save_snapshot(&game_server, path);
                 }
+                None => game_server.begin_shutdown("The server is shutting down."),
+            }
+            shutdown_deadline = Some(Instant::now() + SHUTDOWN_GRACE);
+        }
+
+        scheduler
+            .step(&mut game_server, Instant::now())
+            .map_err(std::io::Error::other)?;
+
+        if let Some(path) = &args.snapshot_file {
+            if shutdown_deadline.is_none() && snapshot_taken.elapsed() >= snapshot_interval {
+                save_snapshot(&game_server, path);
+                snapshot_taken = Instant::now();
+            }
+        }
+
+        if let Some(metrics) = &metrics {
+            if metrics_rendered.elapsed() >= METRICS_INTERVAL {
+                let mut text = String::new();
+                game_server.render_metrics(&mut text);
+                scheduler.render_metrics(&mut text);
+                *metrics.lock().unwrap() = text;
+                metrics_rendered = Instant::now();
             }
         }
 
-        server.send_packets().unwrap();
-        std::thread::sleep(Duration::from_millis(50));
+        // Clients leave on their own once they got the notice, stop when all did or time is up
+        if let Some(deadline) = shutdown_deadline {
+            if game_server.client_count() == 0 || Instant::now() >= deadline {
+                game_server
+                    .disconnect_all()
+                    .map_err(std::io::Error::other)?;
+                info!("Server stopped");
+                return Ok(());
+            }
+        }
     }
 }
 
-fn name_from_user_data(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> Player {
-    let mut buffer = [0u8; 8];
-    buffer.copy_from_slice(&user_data[0..8]);
-    let mut len = u64::from_le_bytes(buffer) as usize;
-    len = len.min(NETCODE_USER_DATA_BYTES - 8);
-    let data = user_data[8..len + 8].to_vec();
-    Player {
-        name: String::from_utf8(data).unwrap(),
+/// Saves the games in progress. A failed snapshot only costs the games a restart, keep going.
+fn save_snapshot(game_server: &GameServer, path: &Path) {
+    if let Err(err) = game_server.snapshot().save(path) {
+        warn!("Could not save a snapshot to {:?}: {}", path, err);
     }
 }
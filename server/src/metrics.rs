@@ -0,0 +1,273 @@
+//! Counters for operators, rendered in the Prometheus text format and served over plain HTTP.
+
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write as _};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::GameServer;
+use rules::protocol::RejectReason;
+use rules::transport::NetworkStats;
+use rules::{GameEvent, GameStage};
+
+/// Prefix of every metric name.
+const NAMESPACE: &str = "battlegrounds";
+
+/// Upper bounds of the step duration histogram, in seconds.
+const STEP_BUCKETS: [f64; 8] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1];
+
+/// What the [`GameServer`] counts while handling commands.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    accepted: BTreeMap<&'static str, u64>,
+    rejected: BTreeMap<(&'static str, &'static str), u64>,
+    sent_bytes: f64,
+    received_bytes: f64,
+}
+
+impl Metrics {
+    pub fn accepted(&mut self, event: &GameEvent) {
+        *self.accepted.entry(event_kind(event)).or_default() += 1;
+    }
+
+    pub fn rejected(&mut self, event: &GameEvent, reason: RejectReason) {
+        let reason = match reason {
+            RejectReason::NotJoined => "NotJoined",
+            RejectReason::InvalidEvent => "InvalidEvent",
+            RejectReason::Spectator => "Spectator",
+            RejectReason::Duplicate => "Duplicate",
+            RejectReason::Restarting => "Restarting",
+        };
+        *self
+            .rejected
+            .entry((event_kind(event), reason))
+            .or_default() += 1;
+    }
+
+    /// Adds what went over a connection during `delta` at the rates the transport measured.
+    pub fn transferred(&mut self, stats: &NetworkStats, delta: Duration) {
+        let secs = delta.as_secs_f64();
+        self.sent_bytes += stats.sent_kbps * 1000.0 / 8.0 * secs;
+        self.received_bytes += stats.received_kbps * 1000.0 / 8.0 * secs;
+    }
+}
+
+pub fn event_kind(event: &GameEvent) -> &'static str {
+    match event {
+        GameEvent::SetupBoard => "SetupBoard",
+        GameEvent::BeginGame { .. } => "BeginGame",
+        GameEvent::EndGame { .. } => "EndGame",
+        GameEvent::PlayerJoined { .. } => "PlayerJoined",
+        GameEvent::PlayerDisconnected { .. } => "PlayerDisconnected",
+        GameEvent::ShipMove { .. } => "ShipMove",
+        GameEvent::ShipPlaced { .. } => "ShipPlaced",
+    }
+}
+
+/// A Prometheus histogram with fixed buckets.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    buckets: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn steps() -> Self {
+        Self {
+            buckets: &STEP_BUCKETS,
+            counts: vec![0; STEP_BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        for (bound, count) in self.buckets.iter().zip(&mut self.counts) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn render(&self, out: &mut String, name: &str, help: &str) {
+        header(out, name, help, "histogram");
+        for (bound, count) in self.buckets.iter().zip(&self.counts) {
+            writeln!(
+                out,
+                "{}_{}_bucket{{le=\"{}\"}} {}",
+                NAMESPACE, name, bound, count
+            )
+            .unwrap();
+        }
+        writeln!(
+            out,
+            "{}_{}_bucket{{le=\"+Inf\"}} {}",
+            NAMESPACE, name, self.count
+        )
+        .unwrap();
+        writeln!(out, "{}_{}_sum {}", NAMESPACE, name, self.sum).unwrap();
+        writeln!(out, "{}_{}_count {}", NAMESPACE, name, self.count).unwrap();
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(out, "# HELP {}_{} {}", NAMESPACE, name, help).unwrap();
+    writeln!(out, "# TYPE {}_{} {}", NAMESPACE, name, kind).unwrap();
+}
+
+/// Appends a metric without labels.
+pub fn single(out: &mut String, name: &str, help: &str, kind: &str, value: impl Display) {
+    header(out, name, help, kind);
+    writeln!(out, "{}_{} {}", NAMESPACE, name, value).unwrap();
+}
+
+impl GameServer {
+    /// Appends the server's metrics to `out`.
+    pub fn render_metrics(&self, out: &mut String) {
+        let clients = self.server.clients_id();
+        single(out, "clients", "Connected clients.", "gauge", clients.len());
+
+        header(out, "games", "Rooms by the stage of their game.", "gauge");
+        let stages = [
+            GameStage::Lobby,
+            GameStage::PreGame,
+            GameStage::InGame,
+            GameStage::Paused,
+            GameStage::Ended,
+        ];
+        for stage in stages {
+            let count = self
+                .rooms
+                .values()
+                .filter(|room| room.game_state.stage == stage)
+                .count();
+            writeln!(
+                out,
+                "{}_games{{stage=\"{:?}\"}} {}",
+                NAMESPACE, stage, count
+            )
+            .unwrap();
+        }
+
+        header(
+            out,
+            "events_accepted_total",
+            "Commands accepted, by event type.",
+            "counter",
+        );
+        for (event, count) in &self.metrics.accepted {
+            writeln!(
+                out,
+                "{}_events_accepted_total{{event=\"{}\"}} {}",
+                NAMESPACE, event, count
+            )
+            .unwrap();
+        }
+        header(
+            out,
+            "events_rejected_total",
+            "Commands rejected, by event type and reason.",
+            "counter",
+        );
+        for ((event, reason), count) in &self.metrics.rejected {
+            writeln!(
+                out,
+                "{}_events_rejected_total{{event=\"{}\",reason=\"{}\"}} {}",
+                NAMESPACE, event, reason, count
+            )
+            .unwrap();
+        }
+
+        single(
+            out,
+            "network_sent_bytes_total",
+            "Bytes sent to all clients, from the rates the transport measured.",
+            "counter",
+            self.metrics.sent_bytes.floor(),
+        );
+        single(
+            out,
+            "network_received_bytes_total",
+            "Bytes received from all clients, from the rates the transport measured.",
+            "counter",
+            self.metrics.received_bytes.floor(),
+        );
+    }
+}
+
+/// Serves whatever `metrics` holds on `GET /metrics` from a background thread.
+pub fn serve(addr: SocketAddr, metrics: Arc<Mutex<String>>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("Serving metrics on http://{}/metrics", addr);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| respond(stream, &metrics));
+            if let Err(err) = result {
+                warn!("Metrics request failed: {}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, metrics: &Mutex<String>) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request = [0u8; 1024];
+    let len = stream.read(&mut request)?;
+    let request = String::from_utf8_lossy(&request[..len]);
+
+    let (status, body) = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => ("200 OK", metrics.lock().unwrap().clone()),
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::steps();
+        histogram.observe(0.002);
+        histogram.observe(0.2);
+
+        let mut out = String::new();
+        histogram.render(&mut out, "step_seconds", "Time spent in a step");
+        assert!(out.contains("# TYPE battlegrounds_step_seconds histogram\n"));
+        assert!(out.contains("battlegrounds_step_seconds_bucket{le=\"0.001\"} 0\n"));
+        assert!(out.contains("battlegrounds_step_seconds_bucket{le=\"0.0025\"} 1\n"));
+        assert!(out.contains("battlegrounds_step_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(out.contains("battlegrounds_step_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(out.contains("battlegrounds_step_seconds_count 2\n"));
+    }
+
+    #[test]
+    fn traffic_adds_up_over_time() {
+        let mut metrics = Metrics::default();
+        let stats = NetworkStats {
+            sent_kbps: 8.0,
+            received_kbps: 16.0,
+            ..Default::default()
+        };
+        metrics.transferred(&stats, Duration::from_secs(2));
+        metrics.transferred(&stats, Duration::from_secs(1));
+        assert_eq!(metrics.sent_bytes, 3000.0);
+        assert_eq!(metrics.received_bytes, 6000.0);
+    }
+}
@@ -0,0 +1,23 @@
+//! Reading and writing the JSON files the server keeps its data in.
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Reads the value saved at `path`, or the default value if there is no file yet.
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> io::Result<T> {
+    match fs::read(path) {
+        Ok(json) => Ok(serde_json::from_slice(&json)?),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Saves `value` at `path`. The file is replaced in one go, so a crash can't leave half of it.
+pub fn save_json<T: Serialize + ?Sized>(path: &Path, value: &T) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    fs::write(&partial, serde_json::to_vec_pretty(value)?)?;
+    fs::rename(partial, path)
+}
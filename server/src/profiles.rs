@@ -0,0 +1,237 @@
+//! Career statistics of the players, by the token the server issued them, computed from the
+//! histories of their games.
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+
+use crate::persist::{load_json, save_json};
+use rules::protocol::{PlayerProfile, PlayerToken, ShipPlacement, MAX_FAVOURITE_PLACEMENTS};
+use rules::ships::get_object_all_coords;
+use rules::{GameEvent, GameState, PlayerId};
+
+/// Everything a [`PlayerProfile`] is computed from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Career {
+    /// The name of their last game.
+    name: String,
+    games: u32,
+    wins: u32,
+    losses: u32,
+    shots: u32,
+    hits: u32,
+    /// Turns taken in the games they won, summed up.
+    turns_in_wins: u32,
+    /// Every placement they used, with how often.
+    placements: Vec<ShipPlacement>,
+}
+
+/// The careers of every player who finished a game, saved to a JSON file after every game
+/// if there is one.
+#[derive(Debug, Default)]
+pub struct Profiles {
+    path: Option<PathBuf>,
+    careers: HashMap<PlayerToken, Career>,
+}
+
+impl Profiles {
+    /// Profiles that are forgotten when the server stops.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Loads the profiles saved at `path`, or starts new ones there if there is no file yet.
+    pub fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        Ok(Self {
+            careers: load_json(&path)?,
+            path: Some(path),
+        })
+    }
+
+    /// Writes the profiles to their file, if they have one.
+    pub fn save(&self) -> io::Result<()> {
+        match &self.path {
+            Some(path) => save_json(path, &self.careers),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds a finished game to the career of the player who played it as `player_id`.
+    /// `result` is the winner and the loser, if the game had one. Games that never began
+    /// are left out.
+    pub fn record(
+        &mut self,
+        token: &str,
+        player_id: PlayerId,
+        game_state: &GameState,
+        result: Option<(PlayerId, PlayerId)>,
+    ) {
+        let history = &game_state.history;
+        if !history
+            .iter()
+            .any(|event| matches!(event, GameEvent::BeginGame { .. }))
+        {
+            return;
+        }
+
+        // Hexes covered by the opponent's fleet
+        let targets: Vec<_> = history
+            .iter()
+            .filter_map(|event| match event {
+                GameEvent::ShipPlaced {
+                    player_id: owner,
+                    ship_type,
+                    at,
+                    rotation,
+                } if *owner != player_id => Some(get_object_all_coords(ship_type, *rotation, at)),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+
+        let career = self.careers.entry(token.to_string()).or_default();
+        if let Some((_, player)) = game_state
+            .joined_players()
+            .into_iter()
+            .find(|(id, _)| *id == player_id)
+        {
+            career.name = player.name.clone();
+        }
+        career.games += 1;
+
+        let mut turns = 0;
+        for event in history {
+            match event {
+                GameEvent::ShipMove { player_id: id, at } if *id == player_id => {
+                    turns += 1;
+                    career.shots += 1;
+                    if targets.contains(at) {
+                        career.hits += 1;
+                    }
+                }
+                GameEvent::ShipPlaced {
+                    player_id: id,
+                    ship_type,
+                    at,
+                    rotation,
+                } if *id == player_id => {
+                    let used = career.placements.iter_mut().find(|placement| {
+                        placement.ship == *ship_type
+                            && placement.at == *at
+                            && placement.rotation == *rotation
+                    });
+                    match used {
+                        Some(placement) => placement.times += 1,
+                        None => career.placements.push(ShipPlacement {
+                            ship: *ship_type,
+                            at: *at,
+                            rotation: *rotation,
+                            times: 1,
+                        }),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        match result {
+            Some((winner, _)) if winner == player_id => {
+                career.wins += 1;
+                career.turns_in_wins += turns;
+            }
+            Some((_, loser)) if loser == player_id => career.losses += 1,
+            _ => {}
+        }
+    }
+
+    /// The profile of the player holding `token`, if they finished a game yet.
+    pub fn profile(&self, token: &str) -> Option<PlayerProfile> {
+        let career = self.careers.get(token)?;
+        let mut favourites = career.placements.clone();
+        favourites.sort_by_key(|placement| Reverse(placement.times));
+        favourites.truncate(MAX_FAVOURITE_PLACEMENTS);
+        Some(PlayerProfile {
+            name: career.name.clone(),
+            games: career.games,
+            wins: career.wins,
+            losses: career.losses,
+            shots: career.shots,
+            hits: career.hits,
+            average_turns_to_win: match career.wins {
+                0 => None,
+                wins => Some(career.turns_in_wins as f32 / wins as f32),
+            },
+            favourite_placements: favourites,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rules::hex::CubeCoords;
+    use rules::ships::GameObject;
+    use rules::Player;
+
+    fn placed(player_id: PlayerId, ship_type: GameObject, r: i32) -> GameEvent {
+        GameEvent::ShipPlaced {
+            player_id,
+            ship_type,
+            at: CubeCoords { q: 0, r, s: -r },
+            rotation: 0,
+        }
+    }
+
+    /// A game in which both players placed `ships` and player 1 resigned right away.
+    fn game(ships: &[(GameObject, i32)], began: bool) -> GameState {
+        let mut game_state = GameState::default();
+        let mut history = vec![];
+        for player_id in [1, 2] {
+            history.push(GameEvent::PlayerJoined {
+                player_id,
+                player_details: Player {
+                    name: format!("player {}", player_id),
+                },
+            });
+        }
+        history.push(GameEvent::SetupBoard);
+        for player_id in [1, 2] {
+            for (ship_type, r) in ships {
+                history.push(placed(player_id, *ship_type, *r));
+            }
+        }
+        if began {
+            history.push(GameEvent::BeginGame { first_player: 2 });
+        }
+        for event in &history {
+            game_state.consume(event);
+        }
+        game_state
+    }
+
+    #[test]
+    fn favourite_placements_are_the_most_used() {
+        let mut profiles = Profiles::in_memory();
+        let boats: Vec<_> = (0..MAX_FAVOURITE_PLACEMENTS as i32 + 2)
+            .map(|r| (GameObject::Boat, r))
+            .collect();
+        profiles.record("token", 1, &game(&boats, true), Some((2, 1)));
+        profiles.record("token", 1, &game(&boats[..1], true), Some((2, 1)));
+        // Games that never began are left out
+        profiles.record("token", 1, &game(&boats[..2], false), None);
+
+        let profile = profiles.profile("token").unwrap();
+        assert_eq!(profile.name, "player 1");
+        assert_eq!((profile.games, profile.wins, profile.losses), (2, 0, 2));
+        assert_eq!(profile.favourite_placements.len(), MAX_FAVOURITE_PLACEMENTS);
+        assert_eq!(profile.favourite_placements[0].times, 2);
+        assert_eq!(profile.favourite_placements[0].at, CubeCoords::ZERO);
+        assert!(profile.favourite_placements[1..]
+            .iter()
+            .all(|placement| placement.times == 1));
+        assert!(profiles.profile("someone else").is_none());
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant, SystemTime};
+
+use rules::names;
+use rules::protocol::{EventSeq, PlayerToken, RoomId, ServerMessage};
+use rules::{GameEvent, GameStage, GameState, Player, Viewer};
+
+/// What spectators get to see of a match.
+#[derive(Debug, Clone, Copy)]
+pub struct SpectatorPolicy {
+    /// How far spectators lag behind the players.
+    pub delay: Duration,
+    /// Whether spectators see where ships were placed.
+    pub reveal_fleets: bool,
+}
+
+impl SpectatorPolicy {
+    pub fn viewer(&self) -> Viewer {
+        Viewer::Spectator {
+            reveal_fleets: self.reveal_fleets,
+        }
+    }
+}
+
+/// A single match and the clients taking part in it.
+pub struct Room {
+    pub id: RoomId,
+    pub game_state: GameState,
+    pub spectators: HashMap<u64, Player>,
+    /// The game as far as spectators have seen it.
+    pub spectator_state: GameState,
+    /// Events that spectators will receive once the spectator delay has passed, oldest first.
+    spectator_queue: VecDeque<(Instant, GameEvent)>,
+    /// Players that asked for a rematch since the game ended.
+    pub rematch: HashSet<u64>,
+    /// Events published since the audit log last took them, with their sequence numbers.
+    published: Vec<(EventSeq, GameEvent)>,
+    /// When the current game began, or the room opened or restarted if it did not yet.
+    pub started_at: SystemTime,
+    /// Tokens of the players, to rate their game.
+    pub tokens: HashMap<u64, PlayerToken>,
+    /// Whether the finished game went to the archive and the ladder already.
+    pub recorded: bool,
+    /// A time of the server's clock and the wall time it stood for, to tell the wall time
+    /// of later times of the clock.
+    clock_origin: (Instant, SystemTime),
+    /// Seats whose players are not connected, by token, with the id the player had when they
+    /// left: players of a game restored from a snapshot, or of a correspondence game.
+    pub awaiting: HashMap<PlayerToken, u64>,
+    /// When to give up on the `awaiting` players, unless they have until the move deadline.
+    pub awaiting_until: Option<Instant>,
+    /// Time given for every move if this room hosts correspondence games.
+    pub correspondence: Option<Duration>,
+    /// When the players the correspondence game waits for run out of time.
+    pub move_deadline: Option<SystemTime>,
+}
+
+impl Room {
+    /// An empty room, opened at `now` on the server's clock.
+    pub fn new(id: RoomId, now: Instant) -> Self {
+        let opened_at = SystemTime::now();
+        Self {
+            id,
+            game_state: GameState::default(),
+            spectators: HashMap::new(),
+            spectator_state: GameState::default(),
+            spectator_queue: VecDeque::new(),
+            rematch: HashSet::new(),
+            published: Vec::new(),
+            started_at: opened_at,
+            tokens: HashMap::new(),
+            recorded: false,
+            clock_origin: (now, opened_at),
+            awaiting: HashMap::new(),
+            awaiting_until: None,
+            correspondence: None,
+            move_deadline: None,
+        }
+    }
+
+    /// A room for correspondence games that gives `move_time` for every move.
+    /// Opened at `now` on the server's clock.
+    pub fn correspondence(id: RoomId, move_time: Duration, now: Instant) -> Self {
+        let mut room = Self::new(id, now);
+        room.correspondence = Some(move_time);
+        room
+    }
+
+    /// A room holding a game saved before the server restarted, opened again at `now` and
+    /// waiting until `until` for its players to come back. `tokens` are the players' tokens
+    /// by their old ids.
+    pub fn restored(
+        id: RoomId,
+        game_state: GameState,
+        tokens: HashMap<u64, PlayerToken>,
+        started_at: SystemTime,
+        now: Instant,
+        until: Instant,
+    ) -> Self {
+        let mut room = Self::new(id, now);
+        // Spectators may have missed some of it, but they start over anyway
+        room.spectator_state = game_state.clone();
+        room.game_state = game_state;
+        room.awaiting = tokens
+            .iter()
+            .map(|(player_id, token)| (token.clone(), *player_id))
+            .collect();
+        room.awaiting_until = Some(until);
+        room.tokens = tokens;
+        room.started_at = started_at;
+        room
+    }
+
+    /// Gives the seat awaiting `token` to `client_id`, which becomes the player's id in the
+    /// game. Returns the id the player had before.
+    pub fn reclaim_seat(&mut self, token: &str, client_id: u64) -> Option<u64> {
+        let old_id = self.awaiting.remove(token)?;
+        self.game_state = self.game_state.with_player_renamed(old_id, client_id);
+        self.spectator_state = self.spectator_state.with_player_renamed(old_id, client_id);
+        for (_, event) in self.spectator_queue.iter_mut() {
+            *event = event.with_player_renamed(old_id, client_id);
+        }
+        self.tokens.remove(&old_id);
+        self.tokens.insert(client_id, token.to_string());
+        if self.awaiting.is_empty() {
+            self.awaiting_until = None;
+        }
+        Some(old_id)
+    }
+
+    /// Keeps the seat of a correspondence player that left, for when they come back with
+    /// `token`.
+    pub fn keep_seat(&mut self, client_id: u64, token: &str) {
+        self.rematch.remove(&client_id);
+        self.awaiting.insert(token.to_string(), client_id);
+    }
+
+    /// The id of the player holding `token`, whether they are connected or not.
+    pub fn player_with_token(&self, token: &str) -> Option<u64> {
+        self.tokens
+            .iter()
+            .find(|(_, player_token)| *player_token == token)
+            .map(|(player_id, _)| *player_id)
+    }
+
+    /// Forgets the finished game at `now`, returning its players so they can join the next
+    /// one. Spectators skip whatever the delay still held back of the old game.
+    pub fn restart(&mut self, now: Instant) -> HashMap<u64, Player> {
+        let players = std::mem::take(&mut self.game_state.players);
+        self.game_state = GameState::default();
+        self.spectator_state = GameState::default();
+        self.spectator_queue.clear();
+        self.rematch.clear();
+        self.started_at = self.wall_time(now);
+        self.recorded = false;
+        self.move_deadline = None;
+        self.tokens
+            .retain(|client_id, _| players.contains_key(client_id));
+        players
+    }
+
+    /// Whether a newly connected player can take a seat in this room.
+    pub fn has_free_seat(&self) -> bool {
+        self.game_state.stage == GameStage::Lobby && self.game_state.players.len() < 2
+    }
+
+    pub fn players(&self) -> Vec<u64> {
+        self.game_state.players.keys().copied().collect()
+    }
+
+    /// A name for a newly arrived client that nobody in the room is using yet.
+    pub fn unique_name(&self, name: &str) -> String {
+        let taken = self
+            .game_state
+            .players
+            .values()
+            .chain(self.spectators.values())
+            .map(|player| player.name.as_str());
+        names::unique_name(name, taken)
+    }
+
+    /// Every client in the room, players and spectators.
+    pub fn clients(&self) -> Vec<u64> {
+        let mut clients = self.players();
+        clients.extend(self.spectators.keys());
+        clients
+    }
+
+    pub fn name_of(&self, client_id: u64) -> Option<&str> {
+        self.game_state
+            .players
+            .get(&client_id)
+            .or_else(|| self.spectators.get(&client_id))
+            .map(|player| player.name.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.game_state.players.is_empty() && self.spectators.is_empty()
+    }
+
+    /// The wall time `at` on the server's clock stands for.
+    pub fn wall_time(&self, at: Instant) -> SystemTime {
+        let (origin, wall_origin) = self.clock_origin;
+        match at.checked_duration_since(origin) {
+            Some(since) => wall_origin + since,
+            None => wall_origin - origin.duration_since(at),
+        }
+    }
+
+    /// Remembers an event consumed at `now` for the audit log. Events handing the move to
+    /// somebody else restart the clock of a correspondence game.
+    pub fn record_published(&mut self, event: GameEvent, now: Instant) {
+        let seq = self.game_state.history.len() as EventSeq;
+        if let GameEvent::BeginGame { .. } = event {
+            self.started_at = self.wall_time(now);
+        }
+        if let Some(move_time) = self.correspondence {
+            match event {
+                GameEvent::SetupBoard
+                | GameEvent::BeginGame { .. }
+                | GameEvent::ShipMove { .. } => {
+                    self.move_deadline = Some(self.wall_time(now) + move_time);
+                }
+                GameEvent::EndGame { .. } => self.move_deadline = None,
+                _ => {}
+            }
+        }
+        self.published.push((seq, event));
+    }
+
+    /// Removes and returns the events published since the last call.
+    pub fn take_published(&mut self) -> Vec<(EventSeq, GameEvent)> {
+        std::mem::take(&mut self.published)
+    }
+
+    /// Holds a consumed event back for spectators until `release_at`.
+    pub fn queue_for_spectators(&mut self, release_at: Instant, event: GameEvent) {
+        self.spectator_queue.push_back((release_at, event));
+    }
+
+    /// Removes the queued events spectators may see at `now` and consumes them into the
+    /// spectator state. Returns the messages for the events `viewer` is allowed to see.
+    pub fn due_spectator_events(&mut self, now: Instant, viewer: &Viewer) -> Vec<ServerMessage> {
+        let mut due = Vec::new();
+        while let Some((release_at, _)) = self.spectator_queue.front() {
+            if *release_at > now {
+                break;
+            }
+            let event = self.spectator_queue.pop_front().unwrap().1;
+            self.spectator_state.consume(&event);
+            let ends_game = matches!(event, GameEvent::EndGame { .. });
+            if event.is_visible_to(viewer) {
+                due.push(ServerMessage::Event {
+                    seq: self.spectator_state.history.len() as EventSeq,
+                    event,
+                    checksum: view_of(&self.spectator_state, viewer).checksum(),
+                });
+            }
+            if ends_game {
+                due.push(reveal(&self.spectator_state, viewer));
+            }
+        }
+        due
+    }
+
+    /// A snapshot of what `client_id` may see: players see the live game without the
+    /// opponent's fleet, spectators the game as far as the spectator delay allows.
+    pub fn snapshot_for(&self, client_id: u64, policy: &SpectatorPolicy) -> ServerMessage {
+        let (state, viewer) = match self.game_state.players.contains_key(&client_id) {
+            true => (&self.game_state, Viewer::Player(client_id)),
+            false => (&self.spectator_state, policy.viewer()),
+        };
+        reveal(state, &viewer)
+    }
+}
+
+/// What `viewer` may see of `state`, which is everything once the game has ended.
+/// Checksums sent along with events are taken from this view, so they match the state the
+/// viewer holds.
+pub fn view_of(state: &GameState, viewer: &Viewer) -> GameState {
+    match state.stage {
+        GameStage::Ended => state.clone(),
+        _ => state.redacted_for(viewer),
+    }
+}
+
+/// A snapshot of `state` for `viewer`. Once the game has ended nothing is hidden anymore,
+/// so this is how every fleet gets revealed.
+fn reveal(state: &GameState, viewer: &Viewer) -> ServerMessage {
+    ServerMessage::Snapshot {
+        seq: state.history.len() as EventSeq,
+        state: view_of(state, viewer),
+    }
+}
@@ -0,0 +1,167 @@
+use log::info;
+use std::time::{Duration, Instant};
+
+use crate::metrics::{self, Histogram};
+use crate::GameServer;
+use rules::transport::TransportError;
+
+/// How often tick timings are logged.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Decides when the [`GameServer`] steps. The owner waits for incoming packets for at most
+/// [`TickScheduler::timeout`] and calls [`TickScheduler::step`] whenever it wakes up, so packets
+/// are handled as soon as they arrive while timers still advance on a fixed tick when idle.
+pub struct TickScheduler {
+    tick: Duration,
+    last_step: Instant,
+    next_tick: Instant,
+    stats: TickStats,
+    // Totals since start, for the metrics endpoint
+    step_durations: Histogram,
+    ticks_total: u64,
+}
+
+impl TickScheduler {
+    pub fn new(tick: Duration, now: Instant) -> Self {
+        Self {
+            tick,
+            last_step: now,
+            next_tick: now + tick,
+            stats: TickStats::new(now),
+            step_durations: Histogram::steps(),
+            ticks_total: 0,
+        }
+    }
+
+    /// How long the owner may wait for packets before the next tick is due.
+    pub fn timeout(&self, now: Instant) -> Duration {
+        self.next_tick.saturating_duration_since(now)
+    }
+
+    /// Steps the server by the time since its last step.
+    pub fn step(&mut self, server: &mut GameServer, now: Instant) -> Result<(), TransportError> {
+        server.step(now - self.last_step)?;
+        self.last_step = now;
+
+        let tick = now >= self.next_tick;
+        if tick {
+            // Skip ticks we were too late for instead of running them back to back
+            while self.next_tick <= now {
+                self.next_tick += self.tick;
+            }
+        }
+
+        let finished = Instant::now();
+        self.stats.record(finished - now, tick);
+        self.step_durations.observe((finished - now).as_secs_f64());
+        self.ticks_total += tick as u64;
+        if finished - self.stats.since >= REPORT_INTERVAL {
+            self.stats.report(server, finished);
+        }
+        Ok(())
+    }
+
+    /// Appends step timings to `out`, see [`GameServer::render_metrics`].
+    pub fn render_metrics(&self, out: &mut String) {
+        self.step_durations
+            .render(out, "step_duration_seconds", "Time spent in a server step.");
+        metrics::single(
+            out,
+            "ticks_total",
+            "Fixed ticks run.",
+            "counter",
+            self.ticks_total,
+        );
+    }
+}
+
+/// Step timings collected between two reports.
+struct TickStats {
+    since: Instant,
+    steps: u32,
+    ticks: u32,
+    total: Duration,
+    slowest: Duration,
+}
+
+impl TickStats {
+    fn new(since: Instant) -> Self {
+        Self {
+            since,
+            steps: 0,
+            ticks: 0,
+            total: Duration::ZERO,
+            slowest: Duration::ZERO,
+        }
+    }
+
+    fn record(&mut self, took: Duration, tick: bool) {
+        self.steps += 1;
+        self.ticks += tick as u32;
+        self.total += took;
+        self.slowest = self.slowest.max(took);
+    }
+
+    fn report(&mut self, server: &GameServer, now: Instant) {
+        info!(
+            "{} steps ({} ticks) in {:.1?}, step took {:.2?} on average and {:.2?} at most, {} rooms open",
+            self.steps,
+            self.ticks,
+            now - self.since,
+            self.total / self.steps.max(1),
+            self.slowest,
+            server.rooms().count(),
+        );
+        *self = Self::new(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ManualClock, RateLimits, ServerSettings, SpectatorPolicy};
+    use rules::transport::MemoryServer;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    fn game_server() -> GameServer {
+        let settings = ServerSettings {
+            spectators: SpectatorPolicy {
+                delay: Duration::ZERO,
+                reveal_fleets: false,
+            },
+            limits: RateLimits::default(),
+            audit_dir: None,
+            archive_dir: None,
+            move_deadline: Duration::from_secs(60 * 60),
+        };
+        GameServer::new(MemoryServer::new(), settings, ManualClock::new())
+    }
+
+    #[test]
+    fn waits_for_packets_until_the_next_tick() {
+        let mut server = game_server();
+        let start = Instant::now();
+        let mut scheduler = TickScheduler::new(TICK, start);
+        assert_eq!(scheduler.timeout(start), TICK);
+
+        // Packets arriving early do not move the tick
+        let early = start + Duration::from_millis(20);
+        scheduler.step(&mut server, early).unwrap();
+        assert_eq!(scheduler.timeout(early), Duration::from_millis(30));
+
+        scheduler.step(&mut server, start + TICK).unwrap();
+        assert_eq!(scheduler.timeout(start + TICK), TICK);
+    }
+
+    #[test]
+    fn skips_the_ticks_it_was_too_late_for() {
+        let start = Instant::now();
+        let mut scheduler = TickScheduler::new(TICK, start);
+
+        let late = start + TICK * 3 + Duration::from_millis(10);
+        scheduler.step(&mut game_server(), late).unwrap();
+        assert_eq!(scheduler.timeout(late), Duration::from_millis(40));
+        assert_eq!(scheduler.timeout(late + TICK), Duration::ZERO);
+    }
+}
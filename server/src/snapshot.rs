@@ -0,0 +1,131 @@
+//! Saving the games in progress to disk, so they survive the server restarting.
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::persist::{load_json, save_json};
+use crate::room::Room;
+use crate::GameServer;
+use rules::protocol::{PlayerToken, RoomId};
+use rules::{GameStage, GameState};
+
+/// How long restored games wait for their players to reconnect before they are aborted.
+pub const RECONNECT_GRACE: Duration = Duration::from_secs(5 * 60);
+
+/// The games in progress whose players can come back after a restart.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServerSnapshot {
+    pub next_room_id: RoomId,
+    pub rooms: Vec<RoomSnapshot>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomSnapshot {
+    pub id: RoomId,
+    /// Everything, hidden fleets included.
+    pub game_state: GameState,
+    /// Every player's token by their id, to recognize them when they reconnect.
+    pub tokens: HashMap<u64, PlayerToken>,
+    pub started_at: SystemTime,
+    /// Time given for every move, for correspondence games.
+    #[serde(default)]
+    pub correspondence: Option<Duration>,
+    #[serde(default)]
+    pub move_deadline: Option<SystemTime>,
+}
+
+impl ServerSnapshot {
+    /// Reads the snapshot saved at `path`, or an empty one if there is none.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        load_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        save_json(path, self)
+    }
+}
+
+impl GameServer {
+    /// The games in progress, and the correspondence games still waiting for an opponent.
+    /// Games with a player that has no token are left out, there would be no telling who is
+    /// who when they reconnect.
+    pub fn snapshot(&self) -> ServerSnapshot {
+        let rooms = self
+            .rooms
+            .values()
+            .filter(|room| {
+                let in_progress = match room.game_state.stage {
+                    GameStage::PreGame | GameStage::InGame | GameStage::Paused => true,
+                    GameStage::Lobby => {
+                        room.correspondence.is_some() && !room.game_state.players.is_empty()
+                    }
+                    GameStage::Ended => false,
+                };
+                in_progress
+                    && room
+                        .game_state
+                        .players
+                        .keys()
+                        .all(|player_id| room.tokens.contains_key(player_id))
+            })
+            .map(|room| RoomSnapshot {
+                id: room.id,
+                game_state: room.game_state.clone(),
+                tokens: room
+                    .game_state
+                    .players
+                    .keys()
+                    .map(|player_id| (*player_id, room.tokens[player_id].clone()))
+                    .collect(),
+                started_at: room.started_at,
+                correspondence: room.correspondence,
+                move_deadline: room.move_deadline,
+            })
+            .collect();
+        ServerSnapshot {
+            next_room_id: self.next_room_id,
+            rooms,
+        }
+    }
+
+    /// Restores the games of a snapshot taken before the server restarted. They wait
+    /// [`RECONNECT_GRACE`] for their players to reconnect, each to their own seat.
+    /// Correspondence games wait until their move deadline, as they always do.
+    pub fn with_snapshot(mut self, snapshot: ServerSnapshot) -> Self {
+        let now = self.clock.now();
+        let until = now + RECONNECT_GRACE;
+        self.next_room_id = self.next_room_id.max(snapshot.next_room_id);
+        for room in snapshot.rooms {
+            info!(
+                "Restored room {} at stage {:?}, waiting for {} players",
+                room.id,
+                room.game_state.stage,
+                room.tokens.len()
+            );
+            self.next_room_id = self.next_room_id.max(room.id);
+            // Their tokens are the players' only way back into their seats
+            for token in room.tokens.values() {
+                self.ladder.accept_token(token.clone());
+            }
+            let mut restored = Room::restored(
+                room.id,
+                room.game_state,
+                room.tokens,
+                room.started_at,
+                now,
+                until,
+            );
+            if room.correspondence.is_some() {
+                restored.correspondence = room.correspondence;
+                restored.move_deadline = room.move_deadline;
+                restored.awaiting_until = None;
+            }
+            self.rooms.insert(room.id, restored);
+        }
+        self
+    }
+}
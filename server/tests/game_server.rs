@@ -0,0 +1,902 @@
+//! Drives a whole [`GameServer`] through the in-memory transport, one step at a time.
+
+use std::time::Duration;
+
+use rules::hex::CubeCoords;
+use rules::names::{self, NameError};
+use rules::protocol::{
+    self, ClientMessage, CommandId, HandshakeError, LeaderboardEntry, RejectReason, Role,
+    ServerMessage, GAME_CHANNEL, MAX_FAVOURITE_PLACEMENTS,
+};
+use rules::ships::{get_object_all_coords, SHIPS};
+use rules::transport::{ClientTransport, MemoryClient, MemoryConnector, MemoryServer};
+use rules::{EndGameReason, GameEvent, GameStage, Viewer};
+use server::{
+    GameServer, ManualClock, RateLimits, ServerSettings, ServerSnapshot, SpectatorPolicy,
+    RECONNECT_GRACE,
+};
+
+const TICK: Duration = Duration::from_millis(50);
+
+struct Harness {
+    server: GameServer,
+    connector: MemoryConnector,
+    clock: ManualClock,
+}
+
+/// Settings without spectator delay nor files, giving an hour for correspondence moves.
+fn settings() -> ServerSettings {
+    ServerSettings {
+        spectators: SpectatorPolicy {
+            delay: Duration::ZERO,
+            reveal_fleets: false,
+        },
+        limits: RateLimits::default(),
+        audit_dir: None,
+        archive_dir: None,
+        move_deadline: Duration::from_secs(60 * 60),
+    }
+}
+
+impl Harness {
+    fn new() -> Self {
+        Self::with_settings(settings())
+    }
+
+    fn with_settings(settings: ServerSettings) -> Self {
+        let transport = MemoryServer::new();
+        let connector = transport.connector();
+        let clock = ManualClock::new();
+        Self {
+            server: GameServer::new(transport, settings, clock.clone()),
+            connector,
+            clock,
+        }
+    }
+
+    /// A server back from a restart with the games of `snapshot`.
+    fn restored(snapshot: ServerSnapshot) -> Self {
+        let Self {
+            server,
+            connector,
+            clock,
+        } = Self::new();
+        Self {
+            server: server.with_snapshot(snapshot),
+            connector,
+            clock,
+        }
+    }
+
+    fn step(&mut self) {
+        self.clock.advance(TICK);
+        self.server.step(TICK).unwrap();
+    }
+
+    /// Opens a connection without saying hello yet.
+    fn connect(&mut self, client_id: u64, name: &str) -> TestClient {
+        let user_data = names::name_to_user_data(name).to_vec();
+        TestClient {
+            transport: self.connector.connect(client_id, user_data),
+            last_command: 0,
+        }
+    }
+
+    /// Connects a client that says hello as `role`, presenting `token` if any.
+    fn hello(&mut self, client_id: u64, name: &str, role: Role, token: Option<&str>) -> TestClient {
+        let mut client = self.connect(client_id, name);
+        client.send(&ClientMessage::Hello {
+            role,
+            token: token.map(str::to_string),
+        });
+        self.step();
+        client
+    }
+
+    /// Connects a client that says hello as a player without a token.
+    fn join(&mut self, client_id: u64, name: &str) -> TestClient {
+        self.hello(client_id, name, Role::Player, None)
+    }
+}
+
+struct TestClient {
+    transport: MemoryClient,
+    last_command: CommandId,
+}
+
+impl TestClient {
+    fn id(&self) -> u64 {
+        self.transport.client_id()
+    }
+
+    fn send(&mut self, message: &ClientMessage) {
+        self.transport
+            .send_message(GAME_CHANNEL, protocol::encode(message));
+    }
+
+    fn command(&mut self, event: GameEvent) -> CommandId {
+        self.last_command += 1;
+        let id = self.last_command;
+        self.send(&ClientMessage::Command { id, event });
+        id
+    }
+
+    /// Everything the server sent since the last call.
+    fn received(&mut self) -> Vec<ServerMessage> {
+        std::iter::from_fn(|| self.transport.receive_message(GAME_CHANNEL))
+            .map(|bytes| protocol::decode(&bytes).unwrap())
+            .collect()
+    }
+
+    /// The token the server issued in the messages since the last call.
+    fn issued_token(&mut self) -> String {
+        self.received()
+            .into_iter()
+            .find_map(|message| match message {
+                ServerMessage::TokenIssued { token } => Some(token),
+                _ => None,
+            })
+            .expect("no token was issued")
+    }
+
+    /// The events among everything the server sent since the last call.
+    fn events(&mut self) -> Vec<GameEvent> {
+        self.received()
+            .into_iter()
+            .filter_map(|message| match message {
+                ServerMessage::Event { event, .. } => Some(event),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Where a player's fleet goes: one ship per row, none of them touching.
+fn fleet(player_id: u64) -> Vec<GameEvent> {
+    SHIPS
+        .iter()
+        .enumerate()
+        .map(|(row, ship_type)| {
+            let r = row as i32 * 2 - 3;
+            GameEvent::ShipPlaced {
+                player_id,
+                ship_type: *ship_type,
+                at: CubeCoords { q: 0, r, s: -r },
+                rotation: 0,
+            }
+        })
+        .collect()
+}
+
+/// Every hex covered by a player's fleet.
+fn fleet_hexes(player_id: u64) -> Vec<CubeCoords> {
+    fleet(player_id)
+        .iter()
+        .flat_map(|event| match event {
+            GameEvent::ShipPlaced {
+                ship_type,
+                at,
+                rotation,
+                ..
+            } => get_object_all_coords(ship_type, *rotation, at),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// Two players in the same room, past the setup of the board.
+fn two_players(harness: &mut Harness) -> (TestClient, TestClient) {
+    let mut alice = harness.join(1, "alice");
+    let mut bob = harness.join(2, "bob");
+    alice.received();
+    bob.received();
+    (alice, bob)
+}
+
+/// Places both fleets, `last` placing its ships after `first`, which begins the game.
+fn place_fleets(harness: &mut Harness, first: &mut TestClient, last: &mut TestClient) {
+    for client in [&mut *first, &mut *last] {
+        for event in fleet(client.id()) {
+            client.command(event);
+        }
+        harness.step();
+    }
+    first.received();
+    last.received();
+}
+
+/// Plays the game in which `winner` sinks the fleet of `loser` without being hit once.
+fn sink_fleet(harness: &mut Harness, winner: &mut TestClient, loser: &mut TestClient) {
+    let targets = fleet_hexes(loser.id());
+    for (turn, at) in targets.iter().enumerate() {
+        winner.command(GameEvent::ShipMove {
+            player_id: winner.id(),
+            at: *at,
+        });
+        harness.step();
+        if turn + 1 < targets.len() {
+            loser.command(GameEvent::ShipMove {
+                player_id: loser.id(),
+                at: CubeCoords { q: 6, r: 0, s: -6 },
+            });
+            harness.step();
+        }
+    }
+}
+
+#[test]
+fn handshake_welcomes_and_catches_up() {
+    let mut harness = Harness::new();
+    let mut alice = harness.join(1, "alice");
+
+    let received = alice.received();
+    assert!(matches!(
+        &received[0],
+        ServerMessage::TokenIssued { token } if protocol::is_valid_token(token)
+    ));
+    assert!(matches!(
+        received[1],
+        ServerMessage::Welcome {
+            player_id: 1,
+            role: Role::Player,
+            ..
+        }
+    ));
+    assert!(matches!(
+        received[2],
+        ServerMessage::Snapshot { seq: 0, .. }
+    ));
+    assert!(matches!(
+        &received[3],
+        ServerMessage::Event {
+            seq: 1,
+            event: GameEvent::PlayerJoined { player_id: 1, player_details },
+            ..
+        } if player_details.name == "alice"
+    ));
+    assert_eq!(harness.server.client_count(), 1);
+}
+
+#[test]
+fn issued_tokens_identify_players_again() {
+    let mut harness = Harness::new();
+    let mut alice = harness.join(1, "alice");
+    let token = alice.issued_token();
+    alice.transport.disconnect();
+    harness.step();
+
+    let mut alice = harness.hello(2, "alice", Role::Player, Some(&token));
+    assert!(matches!(alice.received()[0], ServerMessage::Welcome { .. }));
+    // Tokens the server did not issue can't be made up
+    let mut mallory = harness.hello(3, "mallory", Role::Player, Some("madeUpTokenOfAlice1234"));
+    assert!(matches!(
+        &mallory.received()[0],
+        ServerMessage::TokenIssued { token: issued } if *issued != token
+    ));
+}
+
+#[test]
+fn commands_before_hello_are_not_joined() {
+    let mut harness = Harness::new();
+    let mut alice = harness.connect(1, "alice");
+    alice.command(GameEvent::SetupBoard);
+    harness.step();
+
+    assert_eq!(
+        alice.received(),
+        vec![ServerMessage::Rejected {
+            id: 1,
+            reason: RejectReason::NotJoined
+        }]
+    );
+}
+
+#[test]
+fn invalid_names_are_rejected() {
+    let mut harness = Harness::new();
+    let mut mallory = harness.join(1, "<mallory>");
+
+    assert_eq!(
+        mallory.received(),
+        vec![ServerMessage::HelloRejected(HandshakeError::InvalidName(
+            NameError::InvalidCharacter('<')
+        ))]
+    );
+    assert_eq!(harness.server.rooms().count(), 0);
+}
+
+#[test]
+fn second_player_sets_up_the_board() {
+    let mut harness = Harness::new();
+    let mut alice = harness.join(1, "alice");
+    alice.received();
+    harness.join(2, "alice");
+
+    // Names are unique within a room
+    assert_eq!(
+        alice.events(),
+        vec![
+            GameEvent::PlayerJoined {
+                player_id: 2,
+                player_details: rules::Player {
+                    name: "alice (2)".to_string()
+                },
+            },
+            GameEvent::SetupBoard,
+        ]
+    );
+    let room = harness.server.rooms().next().unwrap();
+    assert_eq!(room.game_state.stage, GameStage::PreGame);
+}
+
+#[test]
+fn replayed_commands_are_rejected_as_duplicates() {
+    let mut harness = Harness::new();
+    let (mut alice, mut bob) = two_players(&mut harness);
+
+    let placement = fleet(1).remove(0);
+    let id = alice.command(placement.clone());
+    alice.send(&ClientMessage::Command {
+        id,
+        event: placement,
+    });
+    harness.step();
+
+    let received = alice.received();
+    assert_eq!(received[0], ServerMessage::Accepted { id });
+    assert_eq!(
+        received.last(),
+        Some(&ServerMessage::Rejected {
+            id,
+            reason: RejectReason::Duplicate
+        })
+    );
+    assert_eq!(
+        harness
+            .server
+            .rooms()
+            .next()
+            .unwrap()
+            .game_state
+            .player_ships[&1]
+            .len(),
+        1
+    );
+    // Placements stay hidden from the opponent
+    assert!(bob.received().is_empty());
+}
+
+#[test]
+fn placements_are_checksummed_as_the_player_sees_them() {
+    let mut harness = Harness::new();
+    let (mut alice, _bob) = two_players(&mut harness);
+
+    let placement = fleet(1).remove(0);
+    let id = alice.command(placement.clone());
+    harness.step();
+
+    let checksum = harness
+        .server
+        .rooms()
+        .next()
+        .unwrap()
+        .game_state
+        .redacted_for(&Viewer::Player(1))
+        .checksum();
+    assert_eq!(
+        alice.received(),
+        vec![
+            ServerMessage::Accepted { id },
+            ServerMessage::Event {
+                seq: 4,
+                event: placement,
+                checksum,
+            },
+        ]
+    );
+}
+
+#[test]
+fn last_ship_placed_begins_the_game() {
+    let mut harness = Harness::new();
+    let (mut alice, mut bob) = two_players(&mut harness);
+    for event in fleet(1) {
+        alice.command(event);
+    }
+    harness.step();
+    alice.received();
+    assert_eq!(
+        harness.server.rooms().next().unwrap().game_state.stage,
+        GameStage::PreGame
+    );
+
+    for event in fleet(2) {
+        bob.command(event);
+    }
+    harness.step();
+    assert_eq!(
+        alice.events(),
+        vec![GameEvent::BeginGame { first_player: 2 }]
+    );
+    // Players may not begin the game themselves
+    let id = bob.command(GameEvent::BeginGame { first_player: 2 });
+    harness.step();
+    assert!(bob.received().contains(&ServerMessage::Rejected {
+        id,
+        reason: RejectReason::InvalidEvent
+    }));
+}
+
+#[test]
+fn players_only_move_for_themselves() {
+    let mut harness = Harness::new();
+    let (mut alice, mut bob) = two_players(&mut harness);
+    place_fleets(&mut harness, &mut alice, &mut bob);
+
+    let id = bob.command(GameEvent::ShipMove {
+        player_id: 1,
+        at: CubeCoords::ZERO,
+    });
+    harness.step();
+    assert_eq!(
+        bob.received(),
+        vec![ServerMessage::Rejected {
+            id,
+            reason: RejectReason::InvalidEvent
+        }]
+    );
+    assert!(alice.received().is_empty());
+}
+
+#[test]
+fn sinking_the_fleet_wins() {
+    let mut harness = Harness::new();
+    let (mut alice, mut bob) = two_players(&mut harness);
+    place_fleets(&mut harness, &mut alice, &mut bob);
+    sink_fleet(&mut harness, &mut alice, &mut bob);
+
+    let won = GameEvent::EndGame {
+        reason: EndGameReason::PlayerWon { winner: 1 },
+    };
+    assert_eq!(bob.events().last(), Some(&won));
+    let room = harness.server.rooms().next().unwrap();
+    assert_eq!(room.game_state.stage, GameStage::Ended);
+    assert_eq!(room.game_state.history.last(), Some(&won));
+}
+
+#[test]
+fn ended_games_are_checksummed_in_full() {
+    let mut harness = Harness::new();
+    let (mut alice, mut bob) = two_players(&mut harness);
+    place_fleets(&mut harness, &mut alice, &mut bob);
+    sink_fleet(&mut harness, &mut alice, &mut bob);
+
+    // The snapshot revealing every fleet follows the end of the game
+    let received = alice.received();
+    let (checksum, mut state) = match &received[received.len() - 2..] {
+        [ServerMessage::Event {
+            event: GameEvent::EndGame { .. },
+            checksum,
+            ..
+        }, ServerMessage::Snapshot { state, .. }] => (*checksum, state.clone()),
+        other => panic!("expected the end of the game, got {:?}", other),
+    };
+    assert_eq!(state.player_ships[&2].len(), SHIPS.len());
+    assert_eq!(state.checksum(), checksum);
+
+    // Later events build on the revealed game
+    bob.transport.disconnect();
+    harness.step();
+    let received = alice.received();
+    match &received[..] {
+        [ServerMessage::Event {
+            event, checksum, ..
+        }] => {
+            state.consume(event);
+            assert_eq!(state.checksum(), *checksum);
+        }
+        other => panic!("expected bob leaving, got {:?}", other),
+    }
+}
+
+#[test]
+fn won_games_are_rated() {
+    let mut harness = Harness::new();
+    let (mut alice, mut bob) = two_players(&mut harness);
+    place_fleets(&mut harness, &mut alice, &mut bob);
+    sink_fleet(&mut harness, &mut alice, &mut bob);
+    harness.step();
+    alice.received();
+
+    alice.send(&ClientMessage::RequestLeaderboard);
+    harness.step();
+    let entry = |rank, name: &str, rating, wins| LeaderboardEntry {
+        rank,
+        name: name.to_string(),
+        rating,
+        games: 1,
+        wins,
+    };
+    assert_eq!(
+        alice.received(),
+        vec![ServerMessage::Leaderboard {
+            top: vec![entry(1, "alice", 1216, 1), entry(2, "bob", 1184, 0)],
+            own: Some(entry(1, "alice", 1216, 1)),
+        }]
+    );
+}
+
+#[test]
+fn finished_games_make_the_profiles() {
+    let mut harness = Harness::new();
+    let (mut alice, mut bob) = two_players(&mut harness);
+    place_fleets(&mut harness, &mut alice, &mut bob);
+    sink_fleet(&mut harness, &mut alice, &mut bob);
+    alice.received();
+    bob.received();
+
+    let profile = |client: &mut TestClient, harness: &mut Harness| {
+        client.send(&ClientMessage::RequestProfile);
+        harness.step();
+        match &client.received()[..] {
+            [ServerMessage::Profile(Some(profile))] => profile.clone(),
+            other => panic!("expected a profile, got {:?}", other),
+        }
+    };
+    let shots = fleet_hexes(2).len() as u32;
+    let winner = profile(&mut alice, &mut harness);
+    assert_eq!((winner.games, winner.wins, winner.losses), (1, 1, 0));
+    assert_eq!((winner.shots, winner.hits), (shots, shots));
+    assert_eq!(winner.average_turns_to_win, Some(shots as f32));
+    assert_eq!(
+        winner.favourite_placements.len(),
+        SHIPS.len().min(MAX_FAVOURITE_PLACEMENTS)
+    );
+    let loser = profile(&mut bob, &mut harness);
+    assert_eq!((loser.games, loser.wins, loser.losses), (1, 0, 1));
+    assert_eq!((loser.shots, loser.hits), (shots - 1, 0));
+    assert_eq!(loser.average_turns_to_win, None);
+}
+
+#[test]
+fn players_resign_for_themselves() {
+    let mut harness = Harness::new();
+    let (mut alice, mut bob) = two_players(&mut harness);
+    place_fleets(&mut harness, &mut alice, &mut bob);
+
+    let resigned = |player_id| GameEvent::EndGame {
+        reason: EndGameReason::PlayerResigned { player_id },
+    };
+    let id = bob.command(resigned(1));
+    harness.step();
+    assert_eq!(
+        bob.received(),
+        vec![ServerMessage::Rejected {
+            id,
+            reason: RejectReason::InvalidEvent
+        }]
+    );
+
+    bob.command(resigned(2));
+    harness.step();
+    assert_eq!(alice.events(), vec![resigned(2)]);
+    let room = harness.server.rooms().next().unwrap();
+    assert_eq!(room.game_state.stage, GameStage::Ended);
+}
+
+#[test]
+fn rematch_starts_over_once_both_players_ask() {
+    let mut harness = Harness::new();
+    let (mut alice, mut bob) = two_players(&mut harness);
+    place_fleets(&mut harness, &mut alice, &mut bob);
+    sink_fleet(&mut harness, &mut alice, &mut bob);
+    alice.received();
+    bob.received();
+
+    alice.send(&ClientMessage::Rematch);
+    harness.step();
+    let asked = ServerMessage::RematchRequested { by: 1 };
+    assert_eq!(bob.received(), vec![asked.clone()]);
+    assert_eq!(alice.received(), vec![asked]);
+
+    bob.send(&ClientMessage::Rematch);
+    harness.step();
+    let received = alice.received();
+    assert_eq!(received[0], ServerMessage::RematchRequested { by: 2 });
+    assert!(matches!(
+        received[1],
+        ServerMessage::Snapshot { seq: 0, .. }
+    ));
+    assert!(matches!(
+        received.last(),
+        Some(ServerMessage::Event {
+            event: GameEvent::SetupBoard,
+            ..
+        })
+    ));
+    let room = harness.server.rooms().next().unwrap();
+    assert_eq!(room.game_state.stage, GameStage::PreGame);
+    assert_eq!(room.game_state.players.len(), 2);
+}
+
+#[test]
+fn commands_are_audited_with_their_own_events() {
+    let audit_dir = std::env::temp_dir().join(format!("audit-{}", std::process::id()));
+    let mut harness = Harness::with_settings(ServerSettings {
+        audit_dir: Some(audit_dir.clone()),
+        ..settings()
+    });
+    let (mut alice, mut bob) = two_players(&mut harness);
+    place_fleets(&mut harness, &mut alice, &mut bob);
+    sink_fleet(&mut harness, &mut alice, &mut bob);
+    bob.send(&ClientMessage::Rematch);
+    harness.step();
+
+    // The rematch restarts the room right before the placement is handled
+    alice.send(&ClientMessage::Rematch);
+    let placement = fleet(1).remove(0);
+    let id = alice.command(placement.clone());
+    harness.step();
+
+    let log = std::fs::read_dir(&audit_dir)
+        .unwrap()
+        .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect::<String>();
+    std::fs::remove_dir_all(&audit_dir).unwrap();
+    let records: Vec<serde_json::Value> = log
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let placed = records
+        .iter()
+        .find(|record| record["kind"] == "command" && record["client"] == 1 && record["id"] == id)
+        .unwrap();
+    assert_eq!(placed["accepted"], true);
+    let emitted: Vec<(u64, GameEvent)> = serde_json::from_value(placed["emitted"].clone()).unwrap();
+    assert_eq!(emitted, vec![(4, placement)]);
+    // The events of the rematch were logged on their own, before the command
+    let position = |record| records.iter().position(|other| other == record).unwrap();
+    let setup = records
+        .iter()
+        .rfind(|record| record["kind"] == "event" && record["event"] == "SetupBoard")
+        .unwrap();
+    assert!(position(setup) < position(placed));
+}
+
+#[test]
+fn correspondence_moves_expire_on_the_clock() {
+    let mut harness = Harness::new();
+    let move_deadline = settings().move_deadline;
+    let mut alice = harness.hello(1, "alice", Role::Correspondence, None);
+    let mut bob = harness.hello(2, "bob", Role::Correspondence, None);
+    alice.received();
+    bob.received();
+
+    // The game begins with the last ship placed, not when the deadline passes
+    place_fleets(&mut harness, &mut alice, &mut bob);
+    let room = harness.server.rooms().next().unwrap();
+    assert_eq!(room.game_state.stage, GameStage::InGame);
+    assert_eq!(room.game_state.cur_player, Some(1));
+
+    harness
+        .clock
+        .advance(move_deadline - Duration::from_secs(60));
+    harness.step();
+    assert!(alice.events().is_empty());
+
+    harness.clock.advance(Duration::from_secs(60));
+    harness.step();
+    assert_eq!(
+        bob.events(),
+        vec![GameEvent::EndGame {
+            reason: EndGameReason::TimedOut { player_id: 1 }
+        }]
+    );
+}
+
+#[test]
+fn correspondence_seats_are_kept_across_disconnects() {
+    let mut harness = Harness::new();
+    let mut alice = harness.hello(1, "alice", Role::Correspondence, None);
+    let mut bob = harness.hello(2, "bob", Role::Correspondence, None);
+    let alice_token = alice.issued_token();
+    bob.received();
+    place_fleets(&mut harness, &mut alice, &mut bob);
+
+    alice.transport.disconnect();
+    harness.step();
+    // Bob keeps waiting for alice's move instead of winning
+    assert!(bob.events().is_empty());
+
+    let mut alice = harness.hello(3, "alice", Role::Correspondence, Some(&alice_token));
+    assert_eq!(
+        alice.received()[0],
+        ServerMessage::Welcome {
+            player_id: 3,
+            room: 1,
+            role: Role::Correspondence
+        }
+    );
+    alice.send(&ClientMessage::ListGames);
+    harness.step();
+    match &alice.received()[..] {
+        [ServerMessage::OpenGames(games)] => {
+            assert_eq!(games.len(), 1);
+            assert_eq!(games[0].opponent.as_deref(), Some("bob"));
+            assert!(games[0].your_turn);
+        }
+        other => panic!("expected the open games, got {:?}", other),
+    }
+
+    alice.command(GameEvent::ShipMove {
+        player_id: 3,
+        at: CubeCoords::ZERO,
+    });
+    harness.step();
+    assert_eq!(
+        bob.events(),
+        vec![GameEvent::ShipMove {
+            player_id: 3,
+            at: CubeCoords::ZERO
+        }]
+    );
+}
+
+#[test]
+fn leaving_mid_game_ends_it() {
+    let mut harness = Harness::new();
+    let (mut alice, mut bob) = two_players(&mut harness);
+    place_fleets(&mut harness, &mut alice, &mut bob);
+
+    bob.transport.disconnect();
+    harness.step();
+
+    assert_eq!(
+        alice.events(),
+        vec![
+            GameEvent::PlayerDisconnected { player_id: 2 },
+            GameEvent::EndGame {
+                reason: EndGameReason::PlayerLeft { player_id: 2 }
+            },
+        ]
+    );
+    let room = harness.server.rooms().next().unwrap();
+    assert_eq!(room.game_state.stage, GameStage::Ended);
+    assert_eq!(harness.server.client_count(), 1);
+
+    // The room closes once the last player is gone too
+    alice.transport.disconnect();
+    harness.step();
+    assert_eq!(harness.server.rooms().count(), 0);
+}
+
+#[test]
+fn shutdown_notifies_and_turns_away_clients() {
+    let mut harness = Harness::new();
+    let mut alice = harness.join(1, "alice");
+    alice.received();
+
+    harness.server.begin_shutdown("maintenance");
+    harness.step();
+    let shutdown = ServerMessage::Shutdown {
+        reason: "maintenance".to_string(),
+    };
+    assert_eq!(alice.received(), vec![shutdown.clone()]);
+
+    // Latecomers get the notice right away and are let go
+    let mut bob = harness.connect(2, "bob");
+    harness.step();
+    assert_eq!(bob.received(), vec![shutdown]);
+    assert!(!bob.transport.is_connected());
+
+    harness.server.disconnect_all().unwrap();
+    assert!(!alice.transport.is_connected());
+}
+
+/// A game in progress where it is alice's turn, snapshotted once the server began to restart,
+/// along with the tokens of alice and bob.
+fn restarted_game() -> (ServerSnapshot, String, String) {
+    let mut harness = Harness::new();
+    let mut alice = harness.join(1, "alice");
+    let mut bob = harness.join(2, "bob");
+    let tokens = (alice.issued_token(), bob.issued_token());
+    place_fleets(&mut harness, &mut alice, &mut bob);
+
+    harness.server.begin_restart();
+    harness.step();
+    assert_eq!(alice.received(), vec![ServerMessage::Restarting]);
+    // Moves made now would be lost with the restart
+    let id = alice.command(GameEvent::ShipMove {
+        player_id: 1,
+        at: CubeCoords::ZERO,
+    });
+    harness.step();
+    assert_eq!(
+        alice.received(),
+        vec![ServerMessage::Rejected {
+            id,
+            reason: RejectReason::Restarting
+        }]
+    );
+
+    // Latecomers are told to come back later instead of that the server is gone
+    let mut carol = harness.connect(3, "carol");
+    harness.step();
+    assert_eq!(carol.received(), vec![ServerMessage::Restarting]);
+
+    let snapshot = harness.server.snapshot();
+    assert_eq!(snapshot.rooms.len(), 1);
+    (snapshot, tokens.0, tokens.1)
+}
+
+#[test]
+fn players_take_their_seats_back_after_a_restart() {
+    let (snapshot, alice_token, bob_token) = restarted_game();
+    let mut harness = Harness::restored(snapshot);
+
+    // A newcomer does not get into the restored game
+    let mut carol = harness.join(10, "carol");
+    assert!(carol.received().iter().any(|message| matches!(
+        message,
+        ServerMessage::Welcome { room, .. } if *room != 1
+    )));
+
+    let mut alice = harness.hello(11, "alice", Role::Player, Some(&alice_token));
+    let mut bob = harness.hello(12, "bob", Role::Player, Some(&bob_token));
+    let received = alice.received();
+    assert_eq!(
+        received[0],
+        ServerMessage::Welcome {
+            player_id: 11,
+            room: 1,
+            role: Role::Player
+        }
+    );
+    match received.last() {
+        Some(ServerMessage::Snapshot { state, .. }) => {
+            assert_eq!(state.stage, GameStage::InGame);
+            let mut players: Vec<_> = state.players.keys().copied().collect();
+            players.sort();
+            assert_eq!(players, vec![11, 12]);
+        }
+        other => panic!("expected a snapshot, got {:?}", other),
+    }
+    bob.received();
+
+    // The game goes on where it was left, under the new ids
+    alice.command(GameEvent::ShipMove {
+        player_id: 11,
+        at: CubeCoords::ZERO,
+    });
+    harness.step();
+    assert_eq!(
+        bob.events(),
+        vec![GameEvent::ShipMove {
+            player_id: 11,
+            at: CubeCoords::ZERO
+        }]
+    );
+}
+
+#[test]
+fn restored_games_are_aborted_when_players_stay_away() {
+    let (snapshot, alice_token, _) = restarted_game();
+    let mut harness = Harness::restored(snapshot);
+    let mut alice = harness.hello(11, "alice", Role::Player, Some(&alice_token));
+    alice.received();
+
+    harness.clock.advance(RECONNECT_GRACE);
+    harness.step();
+    assert_eq!(
+        alice.events(),
+        vec![
+            GameEvent::PlayerDisconnected { player_id: 2 },
+            GameEvent::EndGame {
+                reason: EndGameReason::Aborted {
+                    reason: "Your opponent did not come back after the server restarted"
+                        .to_string()
+                }
+            },
+        ]
+    );
+}
@@ -7,3 +7,7 @@ pub struct MainCamera;
 pub struct MouseWorldPos(pub Vec2);
 
 impl Resource for MouseWorldPos {}
+
+/// Set while a text field owns the keyboard, so typing does not move the camera.
+#[derive(Default, Resource)]
+pub struct KeyboardCaptured(pub bool);
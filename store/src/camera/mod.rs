@@ -3,7 +3,6 @@ mod systems;
 
 pub use bevy::prelude::*;
 pub use bevy::render::camera::ScalingMode;
-pub use components::*;
 pub use systems::*;
 
 pub struct CameraPlugin;
@@ -11,6 +10,7 @@ pub struct CameraPlugin;
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(MouseWorldPos::default())
+            .insert_resource(KeyboardCaptured::default())
             .add_startup_system(camera_setup)
             .add_system(camera_system)
             .add_system(mouse_to_world_pos);
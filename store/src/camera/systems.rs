@@ -8,36 +8,40 @@ pub use super::components::*;
 pub fn camera_system(
     time: Res<Time>,
     keyboard_input: Res<Input<KeyCode>>,
+    keyboard_captured: Res<KeyboardCaptured>,
     mut ms_wheel_rdr: EventReader<MouseWheel>,
     mut query: Query<&mut Transform, With<MainCamera>>,
 ) {
     let mut transf = query.single_mut();
     let mut direction = Vec3::ZERO;
     let scale: f32 = transf.scale.x;
-    if keyboard_input.pressed(KeyCode::A) {
-        direction -= Vec3::new(2.0, 0.0, 0.0);
-    }
+    // Typing into a text field should not move the camera
+    if !keyboard_captured.0 {
+        if keyboard_input.pressed(KeyCode::A) {
+            direction -= Vec3::new(2.0, 0.0, 0.0);
+        }
 
-    if keyboard_input.pressed(KeyCode::D) {
-        direction += Vec3::new(2.0, 0.0, 0.0);
-    }
+        if keyboard_input.pressed(KeyCode::D) {
+            direction += Vec3::new(2.0, 0.0, 0.0);
+        }
 
-    if keyboard_input.pressed(KeyCode::W) {
-        direction += Vec3::new(0.0, 2.0, 0.0);
-    }
+        if keyboard_input.pressed(KeyCode::W) {
+            direction += Vec3::new(0.0, 2.0, 0.0);
+        }
 
-    if keyboard_input.pressed(KeyCode::S) {
-        direction -= Vec3::new(0.0, 2.0, 0.0);
-    }
+        if keyboard_input.pressed(KeyCode::S) {
+            direction -= Vec3::new(0.0, 2.0, 0.0);
+        }
 
-    if keyboard_input.pressed(KeyCode::Z) {
-        let scale = scale + 0.5;
-        transf.scale = Vec3::splat(scale);
-    }
+        if keyboard_input.pressed(KeyCode::Z) {
+            let scale = scale + 0.5;
+            transf.scale = Vec3::splat(scale);
+        }
 
-    if keyboard_input.pressed(KeyCode::X) {
-        let scale = scale - 0.5;
-        transf.scale = Vec3::splat(scale);
+        if keyboard_input.pressed(KeyCode::X) {
+            let scale = scale - 0.5;
+            transf.scale = Vec3::splat(scale);
+        }
     }
     for e in ms_wheel_rdr.iter() {
         let scale = scale - e.y * 0.1;
@@ -67,7 +71,7 @@ fn cursor_to_world(
 ) -> Vec2 {
     let (transform, camera) = camera_query.single();
 
-    let screen_size = Vec2::new(window.width() as f32, window.height() as f32);
+    let screen_size = Vec2::new(window.width(), window.height());
     let camera_position = transform.compute_matrix();
     let projection_matrix = camera.projection_matrix();
 
@@ -1,29 +1,20 @@
 pub mod components;
 pub mod systems;
 
-use crate::{
-    map::components::world_pos_to_coordinates, GameEvent, GameStage, GameState, PlayerId, WhoAmI,
-};
+use crate::{run_if_identified, GameStage, GameState, PlayerId, Spectator, WhoAmI};
 use bevy::{
+    ecs::system::SystemParam,
     prelude::*,
     render::{mesh::Indices, render_resource::PrimitiveTopology},
 };
 // use serde::{Deserialize, Serialize};
 
 pub use components::*;
-use renet::RenetClient;
-
-use crate::map::{
-    components::{CubeCoords, Hexagon},
-    HEX_CONFIG_PADDING, HEX_CONFIG_SIZE,
+pub use rules::ships::{
+    build_coordinate_vector, get_max_grid_rotation, get_object_all_coords, line_coords, SHIPS,
 };
 
-pub const SHIPS: [GameObject; 4] = [
-    GameObject::Cruizer,
-    GameObject::Ship,
-    GameObject::Boat,
-    GameObject::Boat,
-];
+use crate::map::{components::Hexagon, HEX_CONFIG_PADDING, HEX_CONFIG_SIZE};
 
 #[derive(Resource)]
 pub struct Garage(pub Vec<GameObject>);
@@ -34,57 +25,71 @@ impl Plugin for GameObjectsPlugin {
         app.add_system(systems::object_mouse_rotate)
             .add_system(systems::object_mouse_follow)
             .add_system(systems::object_mouse_hover)
-            .add_system(systems::object_mouse_place_send)
-            .add_system(systems::object_mouse_place_consume)
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(run_if_identified)
+                    .with_system(systems::object_mouse_place_send)
+                    .with_system(systems::object_mouse_place_consume)
+                    .with_system(systems::rebuild_from_snapshot),
+            )
             .add_system_set(SystemSet::on_enter(GameStage::PreGame).with_system(populate_garage))
             .add_system_set(SystemSet::on_update(GameStage::PreGame).with_system(place_ships));
     }
 }
 
-fn populate_garage(mut commands: Commands) {
-    let mut garage = Vec::new();
-    for s in SHIPS {
-        garage.push(s);
-    }
-    commands.insert_resource(Garage(garage));
+fn populate_garage(mut commands: Commands, game_state: Res<GameState>, who_am_i: Res<WhoAmI>) {
+    commands.insert_resource(garage_for(&game_state, &who_am_i.0));
+}
+
+/// The ships `player_id` still has to place. Ships leave the garage from the back.
+pub fn garage_for(game_state: &GameState, player_id: &PlayerId) -> Garage {
+    let placed = game_state
+        .player_ships
+        .get(player_id)
+        .map_or(0, |ships| ships.len());
+    Garage(SHIPS[..SHIPS.len().saturating_sub(placed)].to_vec())
 }
 
 fn place_ships(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut spawner: ObjectSpawner,
     mut garage: ResMut<Garage>,
-    who_am_i: Res<WhoAmI>,
     query: Query<Entity, With<MouseFollow>>,
-    mut client: ResMut<RenetClient>,
+    spectator: Option<Res<Spectator>>,
 ) {
+    // Spectators have no fleet to place
+    if spectator.is_some() {
+        return;
+    }
     if query.is_empty() {
-        let obj_from_garage = garage.0.pop();
-        match obj_from_garage {
-            // if there are ships to place
-            Some(obj) => {
-                let ship = spawn_object(
-                    &mut commands,
-                    &mut meshes,
-                    &mut materials,
-                    &who_am_i.0,
-                    &obj,
-                    0,
-                    Transform::from_xyz(0.0, 0.0, 2.0),
-                    Color::BLUE,
-                );
-                commands.entity(ship).insert(MouseFollow);
-            }
+        // The server begins the game once both garages are empty
+        if let Some(obj) = garage.0.pop() {
+            let ship = spawn_object(
+                &mut spawner.commands,
+                &mut spawner.meshes,
+                &mut spawner.materials,
+                &obj,
+                0,
+                Transform::from_xyz(0.0, 0.0, 2.0),
+                Color::BLUE,
+            );
+            spawner.commands.entity(ship).insert(MouseFollow);
         }
     }
 }
 
+/// Everything needed to spawn the meshes of game objects.
+#[derive(SystemParam)]
+pub struct ObjectSpawner<'w, 's> {
+    pub commands: Commands<'w, 's>,
+    pub meshes: ResMut<'w, Assets<Mesh>>,
+    pub materials: ResMut<'w, Assets<StandardMaterial>>,
+}
+
 /// Spawns an object to world.
 pub fn spawn_object(
     commands: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
     materials: &mut ResMut<Assets<StandardMaterial>>,
-    player_id: &PlayerId,
     game_object: &GameObject,
     angular_rot: i32,
     transform: Transform,
@@ -178,96 +183,3 @@ pub fn to_mesh(game_object: &GameObject) -> Mesh {
     }
     mesh
 }
-
-pub fn get_max_grid_rotation(game_object: &GameObject) -> i32 {
-    match game_object {
-        GameObject::Boat => 6 * 1,
-        GameObject::Ship => 6 * 2,
-        GameObject::Cruizer => 6 * 3,
-    }
-}
-pub fn get_object_all_coords(
-    game_object: &GameObject,
-    rotation: i32,
-    at: &CubeCoords,
-) -> Vec<CubeCoords> {
-    match game_object {
-        GameObject::Boat => {
-            let end_cube = hex_end_rotate(rotation, 2);
-            line_coords(*at, end_cube + *at)
-        }
-        GameObject::Ship => {
-            let end_cube = hex_end_rotate(rotation, 3);
-            line_coords(*at, end_cube + *at)
-        }
-        GameObject::Cruizer => {
-            let end_cube = hex_end_rotate(rotation, 4);
-            line_coords(*at, end_cube + *at)
-        }
-    }
-}
-
-fn hex_end_rotate(rotation: i32, object_len: u32) -> CubeCoords {
-    let max_valid_rotations = 6 * (object_len - 1);
-    let mut u_rotation = rotation;
-    if u_rotation < 0 {
-        u_rotation = max_valid_rotations as i32 + u_rotation;
-    }
-    let coord_vector = build_coordinate_vector(object_len);
-    let q = coord_vector[u_rotation as usize];
-    let r =
-        coord_vector[((u_rotation as u32 + 2 * (object_len - 1)) % max_valid_rotations) as usize];
-    let s = -r - q;
-    CubeCoords { q, r, s }
-}
-
-pub fn build_coordinate_vector(object_len: u32) -> Vec<i32> {
-    let displacement = object_len - 1;
-    let vector_len = 6 * displacement;
-    let mut first_counter: i32 = 0;
-    let mut direction = 1;
-    let mut coord_pos = Vec::with_capacity(vector_len as usize);
-    while coord_pos.len() < vector_len as usize {
-        if first_counter.abs() < displacement as i32 {
-            coord_pos.push(first_counter);
-            first_counter += direction;
-        }
-        if first_counter.abs() >= displacement as i32 {
-            for _ in 0..object_len {
-                coord_pos.push((object_len - 1) as i32 * direction);
-            }
-            direction *= -1;
-            first_counter += direction;
-        }
-    }
-    coord_pos
-}
-
-pub fn line_coords(coord_origin: CubeCoords, coord_end: CubeCoords) -> Vec<CubeCoords> {
-    let hex_origin = Hexagon::new(HEX_CONFIG_SIZE, HEX_CONFIG_PADDING, Some(coord_origin), 0.0);
-    let hex_end = Hexagon::new(HEX_CONFIG_SIZE, HEX_CONFIG_PADDING, Some(coord_end), 0.0);
-    let distance = (coord_end - coord_origin).magnitude();
-
-    let points: Vec<Vec3> = (0..=distance)
-        .map(|i| {
-            lerp(
-                hex_origin.world_pos(),
-                hex_end.world_pos(),
-                i as f32 / (distance) as f32,
-            )
-        })
-        .collect();
-    points
-        .into_iter()
-        .map(|pos| {
-            world_pos_to_coordinates(
-                HEX_CONFIG_SIZE + HEX_CONFIG_PADDING,
-                Vec2::new(pos.x, pos.y),
-            )
-        })
-        .collect()
-}
-
-fn lerp(start: Vec3, end: Vec3, distance: f32) -> Vec3 {
-    start + (end - start) * distance
-}
@@ -1,19 +1,17 @@
-use itertools::Itertools;
-use renet::RenetClient;
 use std::f32::consts::PI;
 
 use bevy::{input::mouse::MouseWheel, prelude::*};
 
 use crate::{
     map::{
-        self,
-        components::{HexMapObjects, HexMapTiles, Hexagon, MouseCubePos},
+        components::{CubeCoords, HexMapObjects, HexMapTiles, Hexagon, HexagonMesh, MouseCubePos},
         HEX_CONFIG_PADDING, HEX_CONFIG_SIZE,
     },
-    GameEvent, WhoAmI,
+    transport::Transport,
+    GameEvent, GameState, PendingCommands, SnapshotLoaded, WhoAmI,
 };
 
-use super::{AngularRot, GameObject, GridMaxRotation, MouseFollow, ObjectHover};
+use super::{AngularRot, GameObject, GridMaxRotation, MouseFollow, ObjectHover, ObjectSpawner};
 
 pub fn object_mouse_follow(
     mut query: Query<&mut Transform, With<MouseFollow>>,
@@ -48,27 +46,23 @@ pub fn object_mouse_place_send(
     mut query: Query<(&GameObject, &AngularRot), With<MouseFollow>>,
     ms_input: Res<Input<MouseButton>>,
     ms_pos: Res<MouseCubePos>,
-    mut client: ResMut<RenetClient>,
+    mut transport: ResMut<Transport>,
+    mut pending: ResMut<PendingCommands>,
 ) {
     if ms_input.just_pressed(MouseButton::Left) {
+        // Wait for the server to answer the previous placement, a double click places only once
+        if pending.any(|event| matches!(event, GameEvent::ShipPlaced { .. })) {
+            return;
+        }
         if let Ok((game_object, rotation)) = query.get_single_mut() {
             let event = GameEvent::ShipPlaced {
-                player_id: client.client_id(),
+                player_id: transport.client_id(),
                 at: ms_pos.0,
                 rotation: rotation.0,
-                ship_type: game_object.clone(),
+                ship_type: *game_object,
             };
-            client.send_message(0, bincode::serialize(&event).unwrap());
-
-            // TODO! If garage == 0, attempt game start.
-            // BUG! BeginGame event must be fired only once.
-            // if there are none, attempt game start
-            None => {
-                let event = GameEvent::BeginGame {
-                    first_player: who_am_i.0,
-                };
-                client.send_message(0, bincode::serialize(&event).unwrap());
-            }
+            // The server begins the game once both fleets are in place
+            pending.send(&mut transport, event);
         }
     }
 }
@@ -78,56 +72,89 @@ pub fn object_mouse_place_consume(
     mut game_events: EventReader<GameEvent>,
     who_am_i: Res<WhoAmI>,
     // spawn object
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut spawner: ObjectSpawner,
     query: Query<Entity, With<MouseFollow>>,
     // update HexMap with occupied hexes
     mut hex_objects: ResMut<HexMapObjects>,
 ) {
     for ev in game_events.iter() {
         use GameEvent::*;
-        match ev {
-            ShipPlaced {
-                player_id,
-                ship_type,
-                at,
-                rotation,
-            } => {
-                // remove mouse_follow entity if player_id == ME
-                if player_id == &who_am_i.0 {
-                    for entity in &query {
-                        commands.entity(entity).despawn_recursive();
-                    }
+        if let ShipPlaced {
+            player_id,
+            ship_type,
+            at,
+            rotation,
+        } = ev
+        {
+            // remove mouse_follow entity if player_id == ME
+            if player_id == &who_am_i.0 {
+                for entity in &query {
+                    spawner.commands.entity(entity).despawn_recursive();
                 }
+            }
 
-                // place object
-                let hex = Hexagon::new(HEX_CONFIG_SIZE, HEX_CONFIG_PADDING, Some(*at), 2.0);
-                let hex_pos = hex.world_pos();
-                let mut transform = Transform::from_xyz(hex_pos.x, hex_pos.y, hex_pos.z);
-                let grid_max_rot = super::get_max_grid_rotation(ship_type);
-                transform.rotate_local_z(*rotation as f32 * PI * 2.0 / grid_max_rot as f32);
-                let entity = super::spawn_object(
-                    &mut commands,
-                    &mut meshes,
-                    &mut materials,
-                    player_id,
-                    ship_type,
-                    *rotation,
-                    transform,
-                    Color::ORANGE_RED,
-                );
+            spawn_placed_ship(&mut spawner, &mut hex_objects, ship_type, at, *rotation);
+        }
+    }
+}
 
-                // update hex_object dictionary
-                let all_coordinates = super::get_object_all_coords(ship_type, *rotation, at);
-                for coord in all_coordinates {
-                    let result = hex_objects.0.insert(coord, entity);
-                    if let Some(_) = result {
-                        error!("placed object on top of pre-existing one");
-                    }
-                }
-            }
-            _ => {}
+/// Throws away every spawned object and spawns the ships of the GameState again
+/// once the client received a snapshot from the server.
+pub fn rebuild_from_snapshot(
+    mut snapshots: EventReader<SnapshotLoaded>,
+    game_state: Res<GameState>,
+    who_am_i: Res<WhoAmI>,
+    mut spawner: ObjectSpawner,
+    query: Query<Entity, With<GameObject>>,
+    mut hex_objects: ResMut<HexMapObjects>,
+) {
+    if snapshots.iter().count() == 0 {
+        return;
+    }
+    for entity in &query {
+        spawner.commands.entity(entity).despawn_recursive();
+    }
+    hex_objects.0.clear();
+    for ships in game_state.player_ships.values() {
+        for (ship_type, at, rotation) in ships {
+            spawn_placed_ship(&mut spawner, &mut hex_objects, ship_type, at, *rotation);
+        }
+    }
+    spawner
+        .commands
+        .insert_resource(super::garage_for(&game_state, &who_am_i.0));
+}
+
+/// Spawns a ship placed on the board and marks the hexes it covers as occupied.
+fn spawn_placed_ship(
+    spawner: &mut ObjectSpawner,
+    hex_objects: &mut ResMut<HexMapObjects>,
+    ship_type: &GameObject,
+    at: &CubeCoords,
+    rotation: i32,
+) {
+    // place object
+    let hex = Hexagon::new(HEX_CONFIG_SIZE, HEX_CONFIG_PADDING, Some(*at), 2.0);
+    let hex_pos = hex.world_pos();
+    let mut transform = Transform::from_xyz(hex_pos.x, hex_pos.y, hex_pos.z);
+    let grid_max_rot = super::get_max_grid_rotation(ship_type);
+    transform.rotate_local_z(rotation as f32 * PI * 2.0 / grid_max_rot as f32);
+    let entity = super::spawn_object(
+        &mut spawner.commands,
+        &mut spawner.meshes,
+        &mut spawner.materials,
+        ship_type,
+        rotation,
+        transform,
+        Color::ORANGE_RED,
+    );
+
+    // update hex_object dictionary
+    let all_coordinates = super::get_object_all_coords(ship_type, rotation, at);
+    for coord in all_coordinates {
+        let result = hex_objects.0.insert(coord, entity);
+        if result.is_some() {
+            error!("placed object on top of pre-existing one");
         }
     }
 }
@@ -135,9 +162,7 @@ pub fn object_mouse_place_consume(
 pub fn object_mouse_hover(
     hex_board: Res<HexMapTiles>,
     hex_objects: Res<HexMapObjects>,
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut spawner: ObjectSpawner,
     ms_coord: Res<MouseCubePos>,
     object: Query<(&GameObject, &AngularRot, &Transform), With<MouseFollow>>,
     hex_query: Query<(Entity, &Transform), With<ObjectHover>>,
@@ -160,21 +185,19 @@ pub fn object_mouse_hover(
 
         // despawn hexes of prev. frame
         for (e, _) in &hex_query {
-            commands.entity(e).despawn_recursive();
+            spawner.commands.entity(e).despawn_recursive();
         }
         // respawn with curr position
         for coord in coords.iter() {
             // TODO: implement proper layering system;
-            let is_entity = match hex_board.0.get(&coord) {
-                Some(_) => true,
-                None => false,
-            };
+            let is_entity = hex_board.0.contains_key(coord);
             let hex = Hexagon::new(HEX_CONFIG_SIZE, HEX_CONFIG_PADDING, Some(*coord), 1.1);
             let hex_pos = hex.world_pos();
-            commands
+            spawner
+                .commands
                 .spawn(MaterialMeshBundle {
-                    mesh: meshes.add(hex.to_mesh()),
-                    material: materials.add(StandardMaterial {
+                    mesh: spawner.meshes.add(hex.to_mesh()),
+                    material: spawner.materials.add(StandardMaterial {
                         base_color: hex_color,
                         unlit: true,
                         alpha_mode: AlphaMode::Blend,
@@ -1,240 +1,65 @@
 pub mod camera;
 pub mod game_objects;
 pub mod map;
+pub mod transport;
 
+use bevy::ecs::schedule::ShouldRun;
 pub use bevy::prelude::*;
-use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-use game_objects::{GameObject, SHIPS};
-use map::components::CubeCoords;
-
-/// Struct for storing player related data.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct Player {
-    pub name: String,
-}
+use protocol::{ClientMessage, CommandId, GAME_CHANNEL};
+pub use rules::{
+    names, protocol, EndGameReason, GameEvent, GameStage, GameState, Player, PlayerId, Viewer,
+};
+use transport::Transport;
 
 #[derive(Resource)]
 pub struct WhoAmI(pub PlayerId);
 
-/// An event that progresses the GameState forward
-#[derive(Debug, Clone, Serialize, PartialEq, Deserialize)]
-pub enum GameEvent {
-    SetupBoard,
-    BeginGame {
-        first_player: PlayerId,
-    },
-    EndGame {
-        reason: EndGameReason,
-    },
-    PlayerJoined {
-        player_id: PlayerId,
-        player_details: Player,
-    },
-    PlayerDisconnected {
-        player_id: PlayerId,
-    },
-    // PlayerSelects {
-    //     player_id: PlayerId,
-    //     select_box: SelectQuad,
-    // },
-    ShipMove {
-        player_id: PlayerId,
-        at: CubeCoords,
-    },
-    ShipPlaced {
-        player_id: PlayerId,
-        ship_type: GameObject,
-        at: CubeCoords,
-        rotation: i32,
-    },
-}
-
-/// The different states a game can be in. (not to be confused with the entire "GameState")
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
-pub enum GameStage {
-    Lobby,
-    PreGame,
-    InGame,
-    Paused,
-    Ended,
-}
-
-/// This just makes it easier to dissern between a player id and any ol' u64
-type PlayerId = u64;
-
-/// A GameState object that is able to keep track of a game of TicTacTussle
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Resource)]
-pub struct GameState {
-    pub stage: GameStage,
-    pub players: HashMap<PlayerId, Player>,
-    pub player_ships: HashMap<PlayerId, Vec<(GameObject, CubeCoords, i32)>>,
-    pub history: Vec<GameEvent>,
-    pub cur_player: Option<PlayerId>,
-}
+/// Inserted on clients that watch a match instead of playing it.
+#[derive(Resource)]
+pub struct Spectator;
 
-impl Default for GameState {
-    fn default() -> Self {
-        Self {
-            stage: GameStage::Lobby,
-            players: HashMap::new(),
-            player_ships: HashMap::new(),
-            history: Vec::new(),
-            cur_player: None,
-        }
-    }
+/// Commands sent to the server that have not been acknowledged yet.
+#[derive(Debug, Default, Resource)]
+pub struct PendingCommands {
+    next_id: CommandId,
+    pending: HashMap<CommandId, GameEvent>,
 }
 
-impl GameState {
-    /// Determines whether an event is valid considering the current GameState
-    pub fn validade(&self, event: &GameEvent) -> bool {
-        use GameEvent::*;
-        match event {
-            BeginGame { first_player } => {
-                if None == self.players.get(first_player) {
-                    return false;
-                }
-                if self.players.len() != 2 {
-                    return false;
-                }
-                if self
-                    .player_ships
-                    .iter()
-                    .any(|(_, vec)| vec.len() < SHIPS.len())
-                {
-                    return false;
-                }
-            }
-            EndGame { reason } => match reason {
-                EndGameReason::PlayerWon { winner: _ } => {
-                    if self.stage != GameStage::InGame {
-                        return false;
-                    }
-                }
-                _ => {}
-            },
-            PlayerJoined {
-                player_id,
-                player_details: _,
-            } => {
-                if self.players.contains_key(player_id) {
-                    return false;
-                }
-            }
-            PlayerDisconnected { player_id } => {
-                if !self.players.contains_key(player_id) {
-                    return false;
-                }
-            }
-            ShipMove { player_id, at: _ } => return self.is_player_turn(player_id),
-            ShipPlaced { player_id, .. } => {
-                // check if game is in PreGame
-                if self.stage != GameStage::PreGame {
-                    return false;
-                }
-
-                // check if player is still allowed to place ships
-                match self.player_ships.get(player_id) {
-                    Some(garage) => {
-                        if garage.len() == SHIPS.len() {
-                            return false;
-                        }
-                        if garage.len() > SHIPS.len() {
-                            panic!("{:?} has placed more ships than allowed", player_id);
-                        }
-                    }
-                    None => {
-                        return false;
-                    }
-                }
-            }
-            SetupBoard => {
-                if self.stage != GameStage::Lobby {
-                    return false;
-                }
-                if self.players.len() != 2 {
-                    return false;
-                }
-            }
-        }
-        true
+impl PendingCommands {
+    /// Sends `event` to the server as a new command and remembers it until it is acknowledged.
+    pub fn send(&mut self, transport: &mut Transport, event: GameEvent) -> CommandId {
+        self.next_id += 1;
+        let message = ClientMessage::Command {
+            id: self.next_id,
+            event: event.clone(),
+        };
+        transport.send_message(GAME_CHANNEL, protocol::encode(&message));
+        self.pending.insert(self.next_id, event);
+        self.next_id
     }
 
-    pub fn consume(&mut self, valid_event: &GameEvent) {
-        use GameEvent::*;
-        match valid_event {
-            BeginGame { first_player } => {
-                let player = self
-                    .players
-                    .iter()
-                    .filter(|(p, _)| *p != first_player)
-                    .next()
-                    .unwrap();
-                self.cur_player = Some(*player.0);
-                trace!("First player: {:?}", *player.0);
-                self.stage = GameStage::InGame;
-            }
-            EndGame { reason: _ } => self.stage = GameStage::Ended,
-            PlayerDisconnected { player_id } => {
-                self.players.remove(player_id);
-            }
-            PlayerJoined {
-                player_id,
-                player_details,
-            } => {
-                self.players.insert(*player_id, player_details.clone());
-            }
-            ShipMove {
-                player_id: _,
-                at: _,
-            } => {
-                self.cur_player = self.next_player();
-            }
-            ShipPlaced {
-                player_id,
-                at,
-                rotation,
-                ship_type,
-            } => {
-                let ship_vec = self.player_ships.get_mut(&player_id).unwrap();
-                ship_vec.push((*ship_type, *at, *rotation));
-            }
-            SetupBoard => {
-                self.stage = GameStage::PreGame;
-                for p in &self.players {
-                    self.player_ships.insert(*p.0, Vec::new());
-                }
-            }
-        }
-
-        self.history.push(valid_event.clone());
+    /// Forgets an acknowledged command, returning its event.
+    pub fn ack(&mut self, id: CommandId) -> Option<GameEvent> {
+        self.pending.remove(&id)
     }
 
-    fn next_player(&self) -> Option<PlayerId> {
-        if let Some(player_moved) = self.cur_player {
-            for (key, _) in self.players.iter() {
-                if player_moved != *key {
-                    return Some(*key);
-                }
-            }
-        }
-        None
+    /// Whether a command matching `f` is still waiting on the server.
+    pub fn any(&self, f: impl Fn(&GameEvent) -> bool) -> bool {
+        self.pending.values().any(f)
     }
+}
 
-    fn is_player_turn(&self, player_id: &PlayerId) -> bool {
-        if let Some(p) = self.cur_player {
-            if *player_id == p {
-                return true;
-            }
-        }
-        false
+/// Run criteria for systems that need to know who the local player is.
+/// [`WhoAmI`] is only inserted once the client has connected to a server.
+pub fn run_if_identified(who_am_i: Option<Res<WhoAmI>>) -> ShouldRun {
+    match who_am_i {
+        Some(_) => ShouldRun::Yes,
+        None => ShouldRun::No,
     }
 }
 
-/// The various reasons why a game could end
-#[derive(Debug, Clone, Copy, Serialize, PartialEq, Deserialize)]
-pub enum EndGameReason {
-    PlayerLeft { player_id: PlayerId },
-    PlayerWon { winner: PlayerId },
-}
+/// Sent by the client whenever its GameState was replaced by a snapshot from the server,
+/// so systems can rebuild whatever they derived from the previous state.
+pub struct SnapshotLoaded;
@@ -1,118 +1,24 @@
-use std::ops::{Add, Sub};
-
 use bevy::{
     prelude::*,
     render::{mesh::Indices, render_resource::PrimitiveTopology},
     utils::HashMap,
 };
-use serde::{Deserialize, Serialize};
+pub use rules::hex::{world_pos_to_coordinates, CubeCoords, Hexagon};
 
 #[derive(Default, Resource)]
 pub struct MouseCubePos(pub CubeCoords);
 
-#[derive(Clone, Copy, Debug, Hash, Default, Serialize, Deserialize)]
-pub struct CubeCoords {
-    pub q: i32,
-    pub r: i32,
-    pub s: i32,
-}
-
-impl CubeCoords {
-    pub const ZERO: Self = Self { q: 0, r: 0, s: 0 };
-
-    pub fn distance(&self, other: &CubeCoords) -> u32 {
-        let dist = *other - *self;
-        (dist.q.abs() + dist.r.abs() + dist.s.abs()) as u32 / 2
-    }
-    pub fn magnitude(&self) -> u32 {
-        self.distance(&CubeCoords::ZERO)
-    }
-
-    pub fn rotate_left(&mut self) {
-        self.q = -self.s;
-        self.r = -self.q;
-        self.s = -self.r;
-    }
-
-    pub fn rotate_right(&mut self) {
-        self.q = -self.r;
-        self.r = -self.s;
-        self.s = -self.q;
-    }
-
-    pub fn scalar_mul(&mut self, scalar: i32) {
-        self.q *= scalar;
-        self.r *= scalar;
-        self.s *= scalar;
-    }
-}
-
-impl Eq for CubeCoords {}
-impl PartialEq for CubeCoords {
-    fn eq(&self, other: &Self) -> bool {
-        self.q == other.q && self.r == other.r && self.s == other.s
-    }
-}
-impl Add for CubeCoords {
-    type Output = Self;
-
-    fn add(self, rhs: Self) -> Self::Output {
-        Self {
-            q: self.q + rhs.q,
-            r: self.r + rhs.r,
-            s: self.s + rhs.s,
-        }
-    }
-}
-impl Sub for CubeCoords {
-    type Output = Self;
-
-    fn sub(self, rhs: Self) -> Self::Output {
-        Self {
-            q: self.q - rhs.q,
-            r: self.r - rhs.r,
-            s: self.s - rhs.s,
-        }
-    }
-}
-
 #[derive(Component, Copy, Clone)]
 pub struct Hex(pub HexStatus);
 
-#[derive(Clone, Debug)]
-pub struct Hexagon {
-    pub size: f32,
-    pub padding: f32,
-    pub height: f32,
-    pub width: f32,
-    pub coords: Option<CubeCoords>,
-    pub layer: f32,
+/// Bevy meshes for the hexes of [`rules::hex`].
+pub trait HexagonMesh {
+    fn to_mesh(&self) -> Mesh;
 }
 
-impl Hexagon {
-    /// Create a new Hexagon struct
-    pub fn new(size: f32, padding: f32, coords: Option<CubeCoords>, layer: f32) -> Self {
-        Hexagon {
-            size,
-            padding,
-            height: 3.0_f32.sqrt() * (size + padding),
-            width: 2.0 * (size + padding),
-            coords,
-            layer, // neighbors: None,
-        }
-    }
-
-    /// Return the Vec2 coordinate of point i in a Hexagon
-    fn hex_corner_pos(&self, i: usize) -> Vec2 {
-        let angle = 60.0_f32.to_radians() * i as f32;
-        return Vec2 {
-            x: self.size * angle.cos(),
-            y: self.size * angle.sin(),
-        };
-    }
-
+impl HexagonMesh for Hexagon {
     /// Generate a ['MaterialMeshBundle'] based on Hexagon coordinates and size.
-    pub fn to_mesh(&self) -> Mesh {
+    fn to_mesh(&self) -> Mesh {
         let mut vectors = Vec::with_capacity(8);
         vectors.push([0.0, 0.0, 0.0]);
         let mut indices = Vec::new();
@@ -134,24 +40,6 @@ impl Hexagon {
         mesh.set_indices(Some(Indices::U32(indices)));
         mesh
     }
-
-    pub fn world_pos(&self) -> Vec3 {
-        let coords = self
-            .coords
-            .as_ref()
-            .expect("Cannot return Vec3 for a hex without a coordinate");
-
-        // this is for axial coordinates
-        let y_offset = self.height * (coords.s as f32 + 0.5 * coords.q as f32);
-        let x_offset = 0.75 * self.width * coords.q as f32;
-
-        // this is for offset coordinates only
-        // let y_offset = (coordinates[0] % 2) as f32 * self.height * 0.5;
-        // let x_offset = 0.75 * self.width;
-
-        trace!("x: {:?}, y: {:?}", x_offset, y_offset);
-        Vec3::new(x_offset, y_offset, self.layer)
-    }
 }
 
 #[derive(Debug, Default, Resource)]
@@ -199,42 +87,6 @@ impl HexMap {
     // pub fn coordinate_from_pos(pos: Vec2) -> [u32; 3] {}
 }
 
-pub fn world_pos_to_coordinates(total_hex_size: f32, pos: Vec2) -> CubeCoords {
-    let basis_vec = Mat2::from_cols(
-        Vec2 {
-            x: 2.0 / 3.0,
-            y: -1.0 / 3.0,
-        },
-        Vec2 {
-            x: 0.,
-            y: 3_f32.sqrt() / 3.0,
-        },
-    );
-
-    let q_r = basis_vec * pos / total_hex_size;
-    cube_round(q_r.x, -q_r.x - q_r.y, q_r.y)
-}
-
-fn cube_round(q: f32, r: f32, s: f32) -> CubeCoords {
-    let mut qr = q.round();
-    let mut rr = r.round();
-    let mut sr = s.round();
-    let q_diff = (q - qr).abs();
-    let r_diff = (r - rr).abs();
-    let s_diff = (s - sr).abs();
-    if (q_diff > r_diff) & (q_diff > s_diff) {
-        qr = -rr - sr;
-    } else if r_diff > s_diff {
-        rr = -qr - sr
-    } else {
-        sr = -qr - rr
-    }
-    CubeCoords {
-        q: qr as i32,
-        r: rr as i32,
-        s: sr as i32,
-    }
-}
 // fn hexes_from_offset(offset_type: OffsetType, size: f32) -> Vec<Hexagon> {
 //     let mut hex = Hexagon::new(size);
 //     let mut hexes = Vec::new();
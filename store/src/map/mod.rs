@@ -5,27 +5,7 @@ use bevy::prelude::*;
 use components::*;
 use systems::*;
 
-pub const HEX_CONFIG_SIZE: f32 = 1.0;
-pub const HEX_CONFIG_PADDING: f32 = 0.1;
-pub const HEX_TOT_SIZE: f32 = HEX_CONFIG_SIZE + HEX_CONFIG_PADDING;
-
-const CUBE_NEIGHBORS: [CubeCoords; 6] = [
-    CubeCoords { q: 1, r: 0, s: -1 },
-    CubeCoords { q: 1, r: -1, s: 0 },
-    CubeCoords { q: 0, r: -1, s: 1 },
-    CubeCoords { q: -1, r: 0, s: 1 },
-    CubeCoords { q: -1, r: 1, s: 0 },
-    CubeCoords { q: 0, r: 1, s: -1 },
-];
-
-const CUBE_DIAGONALS: [CubeCoords; 6] = [
-    CubeCoords { q: 2, r: -1, s: -1 },
-    CubeCoords { q: 1, r: -2, s: 1 },
-    CubeCoords { q: -1, r: -1, s: 2 },
-    CubeCoords { q: -2, r: 1, s: 1 },
-    CubeCoords { q: -1, r: 2, s: -1 },
-    CubeCoords { q: 1, r: 1, s: -2 },
-];
+pub use rules::hex::{HEX_CONFIG_PADDING, HEX_CONFIG_SIZE, HEX_TOT_SIZE, MAP_RADIUS};
 
 pub struct HexPlugin;
 impl Plugin for HexPlugin {
@@ -33,7 +13,7 @@ impl Plugin for HexPlugin {
         app.insert_resource(HexMapTiles::default())
             .insert_resource(HexMapObjects::default())
             // TODO: CHECK IF HEXMAP RESOURCE IS ACTUALLY NECESSARY.
-            .insert_resource(HexMap::new_from_axial(8, 1.0, 0.1))
+            .insert_resource(HexMap::new_from_axial(MAP_RADIUS, 1.0, 0.1))
             .insert_resource(MouseCubePos::default())
             // TODO: MOUSE CUBE POS NEED TO BE UPDATED FIRST
             .add_system(world_pos_to_cube_coords)
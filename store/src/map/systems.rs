@@ -1,5 +1,5 @@
 use super::{
-    components::{world_pos_to_coordinates, CubeCoords},
+    components::{world_pos_to_coordinates, CubeCoords, HexagonMesh},
     Hex, HexHover, HexMapTiles, HexStatus, Hexagon, MouseCubePos, HEX_CONFIG_PADDING,
     HEX_CONFIG_SIZE, HEX_TOT_SIZE,
 };
@@ -21,10 +21,7 @@ pub fn update_hover_hex(
     hex_board: Res<HexMapTiles>,
     mut query: Query<(&mut Transform, &mut Visibility), With<HexHover>>,
 ) {
-    let is_entity = match hex_board.0.get(&ms_coord.0) {
-        Some(_) => true,
-        None => false,
-    };
+    let is_entity = hex_board.0.contains_key(&ms_coord.0);
     let hex = Hexagon::new(HEX_CONFIG_SIZE, HEX_CONFIG_PADDING, Some(ms_coord.0), 1.0);
     let hex_pos = hex.world_pos();
 
@@ -113,12 +110,10 @@ pub fn hex_draw_line(
             .into_iter()
             .map(|coords| hex_board.0.get(&coords))
             .collect();
-        for opt_entity in entities {
-            if let Some(entity) = opt_entity {
-                if let Ok((mut hex, handle)) = query.get_mut(*entity) {
-                    update_hex_status(&mut hex);
-                    hex_to_color(&hex, handle, &mut materials);
-                }
+        for entity in entities.into_iter().flatten() {
+            if let Ok((mut hex, handle)) = query.get_mut(*entity) {
+                update_hex_status(&mut hex);
+                hex_to_color(&hex, handle, &mut materials);
             }
         }
     }
@@ -137,7 +132,7 @@ fn hex_to_color(
     handle: &Handle<StandardMaterial>,
     materials: &mut ResMut<Assets<StandardMaterial>>,
 ) {
-    let mut material = materials.get_mut(&handle).unwrap();
+    let material = materials.get_mut(handle).unwrap();
     match hex.0 {
         HexStatus::Cold => {
             material.base_color = Color::rgb(0.67, 0.67, 0.67);
@@ -153,7 +148,3 @@ fn hex_to_color(
         }
     }
 }
-
-fn lerp(start: Vec3, end: Vec3, distance: f32) -> Vec3 {
-    start + (end - start) * distance
-}
@@ -0,0 +1,80 @@
+//! Bevy side of the client's connection to the server. The transports themselves live in
+//! [`rules::transport`] and are re-exported here.
+
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+use std::ops::{Deref, DerefMut};
+
+pub use rules::transport::*;
+
+/// The client's connection to the server. Only inserted once the client is connecting.
+#[derive(Resource)]
+pub struct Transport(Box<dyn ClientTransport>);
+
+impl Transport {
+    pub fn new(transport: impl ClientTransport + 'static) -> Self {
+        Self(Box::new(transport))
+    }
+}
+
+impl Deref for Transport {
+    type Target = dyn ClientTransport;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl DerefMut for Transport {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0.as_mut()
+    }
+}
+
+/// Run criteria for systems that talk to the server.
+pub fn run_if_connected(transport: Option<Res<Transport>>) -> ShouldRun {
+    match transport {
+        Some(transport) if transport.is_connected() => ShouldRun::Yes,
+        _ => ShouldRun::No,
+    }
+}
+
+/// Run criteria for systems that read what the server sent. Unlike [`run_if_connected`] they
+/// keep running once the connection closed, so the messages that came before that are not lost.
+pub fn run_if_transport(transport: Option<Res<Transport>>) -> ShouldRun {
+    match transport {
+        Some(_) => ShouldRun::Yes,
+        None => ShouldRun::No,
+    }
+}
+
+/// Drives the [`Transport`] resource: takes in messages before the frame and sends
+/// whatever the systems queued after it. Failures are sent as [`TransportError`] events.
+pub struct TransportPlugin;
+impl Plugin for TransportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<TransportError>()
+            .add_system_to_stage(CoreStage::PreUpdate, update_transport)
+            .add_system_to_stage(CoreStage::Last, send_packets);
+    }
+}
+
+fn update_transport(
+    time: Res<Time>,
+    transport: Option<ResMut<Transport>>,
+    mut errors: EventWriter<TransportError>,
+) {
+    if let Some(mut transport) = transport {
+        if let Err(err) = transport.update(time.delta()) {
+            errors.send(err);
+        }
+    }
+}
+
+fn send_packets(transport: Option<ResMut<Transport>>, mut errors: EventWriter<TransportError>) {
+    if let Some(mut transport) = transport {
+        if let Err(err) = transport.send_packets() {
+            errors.send(err);
+        }
+    }
+}
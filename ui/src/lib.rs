@@ -36,18 +36,6 @@ fn startup(
     commands.insert_resource(widget_context);
 }
 
-fn render_ui(windows: Res<Windows>) {
-    // global variables
-    let heigth = windows.primary().height();
-    let width = windows.primary().width();
-
-    // UI hierarchy
-    let main_ui = Widget::default();
-    main_ui.call(heigth, width);
-
-    // traverse objects
-}
-
 #[derive(Default)]
 pub struct Widget {
     pub parent: Option<f32>,
@@ -55,14 +43,14 @@ pub struct Widget {
     pub draw: Option<Draw>,
 }
 impl Widget {
-    pub fn call(&self, heigth: f32, width: f32) {
+    pub fn call(&self) {
         // draw object
         if let Some(d) = &self.draw {
             d.to_mesh();
         }
         // do the same for children objects
         for child in self.children.iter() {
-            child.call(heigth, width);
+            child.call();
         }
     }
 }